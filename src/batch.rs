@@ -0,0 +1,756 @@
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, rc::Rc};
+
+use futures_signals::{signal::{Mutable, SignalExt}, signal_vec::MutableVec};
+use gtk::{gio, glib::{self, clone}, prelude::*, Button, Label, ScrolledWindow};
+use reqwest::{header::{HeaderValue, CONTENT_TYPE}, multipart};
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    inspector::LogEntry,
+    message::{Message, Role},
+    pricing::{format_cost, model_pricing, Usage},
+    settings::{APIKey, Provider, Settings},
+    submit::{
+        anthropic_headers, api_url, build_request_body, openai_headers, read_anthropic_usage,
+        read_openai_usage, read_responses_usage, send_request, uses_responses_api
+    }
+};
+
+// both providers bill batched requests at half the regular price
+const BATCH_DISCOUNT: f64 = 0.5;
+const POLL_INTERVAL_SECONDS: u32 = 15;
+
+#[derive(Debug, Clone)]
+pub struct BatchPrompt {
+    pub custom_id: String,
+    pub prompt: String
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchStatus {
+    pub id: String,
+    pub status: String,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub total: u64,
+    pub done: bool,
+    // JSONL files holding the results once the batch is done
+    pub result_urls: Vec<String>
+}
+
+impl BatchStatus {
+    fn describe(&self) -> String {
+        return format!(
+            "Batch {}: {} · {} succeeded · {} failed · {} total",
+            self.id, self.status, self.succeeded, self.failed, self.total
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    pub custom_id: String,
+    pub prompt: String,
+    pub response: String,
+    pub error: Option<String>,
+    pub usage: Option<Usage>
+}
+
+fn csv_prompts(path: &Path) -> Result<Vec<(Option<String>, String)>, String> {
+    let mut reader = csv::Reader::from_path(path).map_err(|err| err.to_string())?;
+    let headers = reader.headers().map_err(|err| err.to_string())?.clone();
+    let prompt_column = headers
+        .iter()
+        .position(|header| header == "prompt")
+        .ok_or("The CSV needs a \"prompt\" column")?;
+    let id_column = headers.iter().position(|header| header == "custom_id" || header == "id");
+
+    let mut prompts = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|err| err.to_string())?;
+        let custom_id = id_column.and_then(|column| record.get(column)).map(|id| id.to_string());
+        prompts.push((custom_id, record.get(prompt_column).unwrap_or_default().to_string()));
+    }
+
+    return Ok(prompts);
+}
+
+// each line is either a bare JSON string or an object with a prompt and an optional custom_id
+fn jsonl_prompts(path: &Path) -> Result<Vec<(Option<String>, String)>, String> {
+    let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+
+    let mut prompts = vec![];
+    for (line_number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let data: serde_json::Value = serde_json::from_str(line)
+            .map_err(|err| format!("Line {}: {}", line_number + 1, err))?;
+        match &data {
+            serde_json::Value::String(prompt) => prompts.push((None, prompt.clone())),
+            _ => {
+                let prompt = data["prompt"]
+                    .as_str()
+                    .ok_or(format!("Line {}: missing \"prompt\"", line_number + 1))?;
+                let custom_id = data["custom_id"].as_str().or(data["id"].as_str()).map(|id| id.to_string());
+                prompts.push((custom_id, prompt.to_string()));
+            }
+        }
+    }
+
+    return Ok(prompts);
+}
+
+pub fn load_prompts(path: &Path) -> Result<Vec<BatchPrompt>, String> {
+    let prompts = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => csv_prompts(path)?,
+        _ => jsonl_prompts(path)?
+    };
+    if prompts.is_empty() {
+        return Err("The file has no prompts".to_string());
+    }
+
+    // both APIs match results back to requests by custom_id, so ids have to be unique
+    let mut seen = HashSet::new();
+    let mut batch_prompts = vec![];
+    for (index, (custom_id, prompt)) in prompts.into_iter().enumerate() {
+        let custom_id = custom_id.unwrap_or_else(|| format!("request-{}", index + 1));
+        if !seen.insert(custom_id.clone()) {
+            return Err(format!("Duplicate custom_id \"{}\"", custom_id));
+        }
+        batch_prompts.push(BatchPrompt { custom_id, prompt });
+    }
+
+    return Ok(batch_prompts);
+}
+
+// the same body a chat request would send, minus streaming
+fn batch_body(settings: &Settings, prompt: &str) -> serde_json::Value {
+    let mut body = build_request_body(settings, &[Message::text(Role::User, prompt)], None);
+    let fields = body.as_object_mut().unwrap();
+    fields.remove("stream");
+    fields.remove("stream_options");
+
+    return body;
+}
+
+fn parse_json(text: &str) -> Result<serde_json::Value, String> {
+    return serde_json::from_str(text).map_err(|err| err.to_string());
+}
+
+async fn submit_anthropic_batch(
+    api_key: &APIKey,
+    settings: &Settings,
+    prompts: &[BatchPrompt],
+    log: &impl Fn(LogEntry)
+) -> Result<String, String> {
+    let requests: Vec<serde_json::Value> = prompts
+        .iter()
+        .map(|prompt| json!({
+            "custom_id": prompt.custom_id,
+            "params": batch_body(settings, &prompt.prompt)
+        }))
+        .collect();
+    let body = json!({ "requests": requests }).to_string();
+
+    let mut headers = anthropic_headers(&api_key.key);
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let request_builder = reqwest::Client::new()
+        .post(api_url(api_key, "/messages/batches"))
+        .headers(headers)
+        .body(body.clone());
+
+    let data = parse_json(&send_request(request_builder, &body, log).await?)?;
+    return data["id"].as_str().map(|id| id.to_string()).ok_or("The response has no batch id".to_string());
+}
+
+// OpenAI batches are a two-step affair: upload the requests as a file, then start a batch over it
+async fn submit_openai_batch(
+    api_key: &APIKey,
+    settings: &Settings,
+    prompts: &[BatchPrompt],
+    log: &impl Fn(LogEntry)
+) -> Result<String, String> {
+    let endpoint = if uses_responses_api(settings) { "/v1/responses" } else { "/v1/chat/completions" };
+    let lines: Vec<String> = prompts
+        .iter()
+        .map(|prompt| json!({
+            "custom_id": prompt.custom_id,
+            "method": "POST",
+            "url": endpoint,
+            "body": batch_body(settings, &prompt.prompt)
+        }).to_string())
+        .collect();
+    let jsonl = lines.join("\n");
+
+    let part = multipart::Part::text(jsonl.clone())
+        .file_name("batch.jsonl")
+        .mime_str("application/jsonl")
+        .map_err(|err| err.to_string())?;
+    let form = multipart::Form::new().text("purpose", "batch").part("file", part);
+    let request_builder = reqwest::Client::new()
+        .post(api_url(api_key, "/files"))
+        .headers(openai_headers(&api_key.key))
+        .multipart(form);
+    let file = parse_json(&send_request(request_builder, &jsonl, log).await?)?;
+    let file_id = file["id"].as_str().ok_or("The upload response has no file id")?;
+
+    let body = json!({
+        "input_file_id": file_id,
+        "endpoint": endpoint,
+        "completion_window": "24h"
+    }).to_string();
+    let mut headers = openai_headers(&api_key.key);
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    let request_builder = reqwest::Client::new()
+        .post(api_url(api_key, "/batches"))
+        .headers(headers)
+        .body(body.clone());
+
+    let data = parse_json(&send_request(request_builder, &body, log).await?)?;
+    return data["id"].as_str().map(|id| id.to_string()).ok_or("The response has no batch id".to_string());
+}
+
+pub async fn submit_batch(
+    api_key: &APIKey,
+    settings: &Settings,
+    prompts: &[BatchPrompt],
+    log: impl Fn(LogEntry)
+) -> Result<String, String> {
+    match api_key.provider {
+        Provider::Anthropic => submit_anthropic_batch(api_key, settings, prompts, &log).await,
+        Provider::OpenAI => submit_openai_batch(api_key, settings, prompts, &log).await,
+        provider => Err(format!("Batches aren't supported for {} keys", provider.to_string()))
+    }
+}
+
+pub async fn poll_batch(api_key: &APIKey, id: &str, log: impl Fn(LogEntry)) -> Result<BatchStatus, String> {
+    match api_key.provider {
+        Provider::Anthropic => {
+            let request_builder = reqwest::Client::new()
+                .get(api_url(api_key, &format!("/messages/batches/{}", id)))
+                .headers(anthropic_headers(&api_key.key));
+            let data = parse_json(&send_request(request_builder, "", &log).await?)?;
+
+            let counts = &data["request_counts"];
+            let count = |name: &str| counts[name].as_u64().unwrap_or(0);
+            let failed = count("errored") + count("canceled") + count("expired");
+            let status = data["processing_status"].as_str().unwrap_or("unknown").to_string();
+            return Ok(BatchStatus {
+                id: id.to_string(),
+                done: status == "ended",
+                status,
+                succeeded: count("succeeded"),
+                failed,
+                total: count("processing") + count("succeeded") + failed,
+                result_urls: data["results_url"].as_str().map(|url| url.to_string()).into_iter().collect()
+            });
+        },
+        Provider::OpenAI => {
+            let request_builder = reqwest::Client::new()
+                .get(api_url(api_key, &format!("/batches/{}", id)))
+                .headers(openai_headers(&api_key.key));
+            let data = parse_json(&send_request(request_builder, "", &log).await?)?;
+
+            let counts = &data["request_counts"];
+            let status = data["status"].as_str().unwrap_or("unknown").to_string();
+            // failed requests are written to a separate error file
+            let result_urls = [&data["output_file_id"], &data["error_file_id"]]
+                .iter()
+                .filter_map(|file_id| file_id.as_str())
+                .map(|file_id| api_url(api_key, &format!("/files/{}/content", file_id)))
+                .collect();
+            return Ok(BatchStatus {
+                id: id.to_string(),
+                done: matches!(status.as_str(), "completed" | "failed" | "expired" | "cancelled"),
+                status,
+                succeeded: counts["completed"].as_u64().unwrap_or(0),
+                failed: counts["failed"].as_u64().unwrap_or(0),
+                total: counts["total"].as_u64().unwrap_or(0),
+                result_urls
+            });
+        },
+        provider => Err(format!("Batches aren't supported for {} keys", provider.to_string()))
+    }
+}
+
+fn anthropic_result(line: &serde_json::Value) -> (String, Option<String>, Option<Usage>) {
+    let result = &line["result"];
+    match result["type"].as_str() {
+        Some("succeeded") => {
+            let message = &result["message"];
+            let response = message["content"]
+                .as_array()
+                .map(|blocks| blocks.iter().filter_map(|block| block["text"].as_str()).collect::<String>())
+                .unwrap_or_default();
+            let mut usage = Usage::default();
+            read_anthropic_usage(&message["usage"], &mut usage);
+            (response, None, Some(usage))
+        },
+        Some("errored") => {
+            let message = result["error"]["error"]["message"].as_str().unwrap_or("Request errored");
+            (String::new(), Some(message.to_string()), None)
+        },
+        other => (String::new(), Some(other.unwrap_or("unknown").to_string()), None)
+    }
+}
+
+fn openai_result(line: &serde_json::Value) -> (String, Option<String>, Option<Usage>) {
+    if let Some(message) = line["error"]["message"].as_str() {
+        return (String::new(), Some(message.to_string()), None);
+    }
+
+    let body = &line["response"]["body"];
+    if let Some(message) = body["error"]["message"].as_str() {
+        return (String::new(), Some(message.to_string()), None);
+    }
+
+    let mut usage = Usage::default();
+    // chat completions put the text in choices, the Responses API in output message items
+    if let Some(content) = body["choices"][0]["message"]["content"].as_str() {
+        read_openai_usage(&body["usage"], &mut usage);
+        return (content.to_string(), None, Some(usage));
+    }
+
+    let response = body["output"]
+        .as_array()
+        .map(|items| items
+            .iter()
+            .filter_map(|item| item["content"].as_array())
+            .flatten()
+            .filter(|content| content["type"] == "output_text")
+            .filter_map(|content| content["text"].as_str())
+            .collect::<String>())
+        .unwrap_or_default();
+    read_responses_usage(&body["usage"], &mut usage);
+    return (response, None, Some(usage));
+}
+
+pub async fn fetch_results(
+    api_key: &APIKey,
+    settings: &Settings,
+    status: &BatchStatus,
+    prompts: &[BatchPrompt],
+    log: impl Fn(LogEntry)
+) -> Result<Vec<BatchResult>, String> {
+    let mut results: HashMap<String, BatchResult> = HashMap::new();
+    let pricing = model_pricing(&settings.pricing, &settings.model);
+
+    for url in &status.result_urls {
+        let headers = match api_key.provider {
+            Provider::Anthropic => anthropic_headers(&api_key.key),
+            _ => openai_headers(&api_key.key)
+        };
+        let request_builder = reqwest::Client::new().get(url).headers(headers);
+        let content = send_request(request_builder, "", &log).await?;
+
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let line = parse_json(line)?;
+            let custom_id = line["custom_id"].as_str().unwrap_or_default().to_string();
+            let (response, error, mut usage) = match api_key.provider {
+                Provider::Anthropic => anthropic_result(&line),
+                _ => openai_result(&line)
+            };
+            if let Some(usage) = usage.as_mut() {
+                usage.cost = pricing.map(|pricing| pricing.cost(usage) * BATCH_DISCOUNT);
+            }
+            let prompt = prompts
+                .iter()
+                .find(|prompt| prompt.custom_id == custom_id)
+                .map(|prompt| prompt.prompt.clone())
+                .unwrap_or_default();
+            results.insert(custom_id.clone(), BatchResult { custom_id, prompt, response, error, usage });
+        }
+    }
+
+    // results come back in completion order, so restore the order of the input file
+    let mut ordered = vec![];
+    for prompt in prompts {
+        match results.remove(&prompt.custom_id) {
+            Some(result) => ordered.push(result),
+            None => ordered.push(BatchResult {
+                custom_id: prompt.custom_id.clone(),
+                prompt: prompt.prompt.clone(),
+                response: String::new(),
+                error: Some("No result".to_string()),
+                usage: None
+            })
+        }
+    }
+
+    return Ok(ordered);
+}
+
+pub fn export_results(path: &Path, results: &[BatchResult]) -> Result<(), String> {
+    if path.extension().and_then(|extension| extension.to_str()) == Some("csv") {
+        let mut writer = csv::Writer::from_path(path).map_err(|err| err.to_string())?;
+        writer
+            .write_record(["custom_id", "prompt", "response", "error", "input_tokens", "output_tokens", "cost"])
+            .map_err(|err| err.to_string())?;
+        for result in results {
+            let usage = result.usage.unwrap_or_default();
+            writer.write_record([
+                result.custom_id.clone(),
+                result.prompt.clone(),
+                result.response.clone(),
+                result.error.clone().unwrap_or_default(),
+                usage.input_tokens.to_string(),
+                usage.output_tokens.to_string(),
+                usage.cost.map(|cost| cost.to_string()).unwrap_or_default()
+            ]).map_err(|err| err.to_string())?;
+        }
+        return writer.flush().map_err(|err| err.to_string());
+    }
+
+    let lines: Vec<String> = results
+        .iter()
+        .map(|result| json!({
+            "custom_id": result.custom_id,
+            "prompt": result.prompt,
+            "response": result.response,
+            "error": result.error,
+            "usage": result.usage
+        }).to_string())
+        .collect();
+    return std::fs::write(path, lines.join("\n") + "\n").map_err(|err| err.to_string());
+}
+
+fn ResultsTable(results: &[BatchResult]) -> gtk::Grid {
+    let grid = gtk::Grid::new();
+    grid.set_css_classes(&["batch-table"]);
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(12);
+
+    let cell = |text: &str, width: i32| {
+        let label = Label::new(Some(text));
+        label.set_xalign(0.0);
+        label.set_yalign(0.0);
+        label.set_wrap(true);
+        label.set_wrap_mode(gtk::pango::WrapMode::WordChar);
+        label.set_max_width_chars(width);
+        label.set_lines(4);
+        label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+        label.set_selectable(true);
+        label
+    };
+
+    for (column, title) in ["ID", "Prompt", "Response", "Usage"].iter().enumerate() {
+        let label = Label::new(Some(title));
+        label.set_xalign(0.0);
+        label.set_css_classes(&["batch-table-header"]);
+        grid.attach(&label, column as i32, 0, 1, 1);
+    }
+
+    for (row, result) in results.iter().enumerate() {
+        let row = row as i32 + 1;
+        grid.attach(&cell(&result.custom_id, 16), 0, row, 1, 1);
+        grid.attach(&cell(&result.prompt, 30), 1, row, 1, 1);
+        match &result.error {
+            Some(error) => {
+                let label = cell(error, 40);
+                label.add_css_class("error-label");
+                grid.attach(&label, 2, row, 1, 1);
+            },
+            None => grid.attach(&cell(&result.response, 40), 2, row, 1, 1)
+        }
+        let usage = match result.usage {
+            Some(usage) => {
+                let mut text = format!("{} in · {} out", usage.input_tokens, usage.output_tokens);
+                match usage.cost {
+                    Some(cost) => text += &format!(" · {}", format_cost(cost)),
+                    None => text += " · cost unknown"
+                }
+                text
+            },
+            None => String::new()
+        };
+        let label = cell(&usage, 16);
+        label.add_css_class("usage-label");
+        grid.attach(&label, 3, row, 1, 1);
+    }
+
+    return grid;
+}
+
+fn file_dialog(title: &str) -> gtk::FileDialog {
+    let filter = gtk::FileFilter::new();
+    filter.set_name(Some("JSONL or CSV"));
+    filter.add_suffix("jsonl");
+    filter.add_suffix("csv");
+    let filters = gio::ListStore::new::<gtk::FileFilter>();
+    filters.append(&filter);
+
+    let dialog = gtk::FileDialog::new();
+    dialog.set_title(title);
+    dialog.set_filters(Some(&filters));
+    dialog.set_default_filter(Some(&filter));
+
+    return dialog;
+}
+
+fn window_of(widget: &impl IsA<gtk::Widget>) -> Option<gtk::Window> {
+    return widget.root().and_downcast::<gtk::Window>();
+}
+
+// a submitted batch and what fetching its results takes, kept so that stopped polling can resume
+#[derive(Clone)]
+struct PendingBatch {
+    id: String,
+    api_key: APIKey,
+    settings: Settings,
+    prompts: Vec<BatchPrompt>
+}
+
+pub fn BatchPage(stack: gtk::Stack, settings: Mutable<Settings>, request_log: MutableVec<LogEntry>) -> gtk::Box {
+    let prompts: Mutable<Vec<BatchPrompt>> = Mutable::new(vec![]);
+    let results: Mutable<Vec<BatchResult>> = Mutable::new(vec![]);
+    let status = Mutable::new(String::new());
+    let error = Mutable::new(String::new());
+    let running = Mutable::new(false);
+    let cancel = Mutable::new(CancellationToken::new());
+    let stopped: Mutable<Option<PendingBatch>> = Mutable::new(None);
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    vbox.set_css_classes(&["top-level-box"]);
+
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let back_button = Button::with_label("Back");
+    back_button.connect_clicked(move |_| stack.set_visible_child_name("chat"));
+    hbox.append(&back_button);
+
+    let title = Label::new(Some("Batch"));
+    title.set_css_classes(&["title"]);
+    title.set_hexpand(true);
+    hbox.append(&title);
+    vbox.append(&hbox);
+
+    let error_label = Label::new(None);
+    error_label.set_css_classes(&["error-label"]);
+    error_label.set_wrap(true);
+    vbox.append(&error_label);
+
+    let file_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    let open_button = Button::with_label("Open…");
+    file_box.append(&open_button);
+    let file_label = Label::new(Some("Load a JSONL or CSV file of prompts"));
+    file_label.set_hexpand(true);
+    file_label.set_xalign(0.0);
+    file_label.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
+    file_box.append(&file_label);
+    vbox.append(&file_box);
+
+    let status_label = Label::new(None);
+    status_label.set_xalign(0.0);
+    status_label.set_wrap(true);
+    status_label.set_selectable(true);
+    vbox.append(&status_label);
+
+    let scrolled_window = ScrolledWindow::new();
+    scrolled_window.set_vexpand(true);
+    vbox.append(&scrolled_window);
+
+    let action_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let total_label = Label::new(None);
+    total_label.set_css_classes(&["usage-label"]);
+    total_label.set_hexpand(true);
+    total_label.set_xalign(0.0);
+    action_box.append(&total_label);
+    let cancel_button = Button::with_label("Stop polling");
+    action_box.append(&cancel_button);
+    let resume_button = Button::with_label("Resume polling");
+    action_box.append(&resume_button);
+    let export_button = Button::with_label("Export…");
+    action_box.append(&export_button);
+    let submit_button = Button::with_label("Submit batch");
+    action_box.append(&submit_button);
+    vbox.append(&action_box);
+
+    open_button.connect_clicked(clone!(@strong prompts, @strong error, @strong results => move |button| {
+        let dialog = file_dialog("Open Prompts");
+        let window = window_of(button);
+        glib::spawn_future_local(clone!(@strong prompts, @strong error, @strong results, @strong file_label => async move {
+            let path: PathBuf = match dialog.open_future(window.as_ref()).await.ok().and_then(|file| file.path()) {
+                Some(path) => path,
+                None => return
+            };
+            match load_prompts(&path) {
+                Ok(loaded) => {
+                    file_label.set_text(&format!("{} · {} prompts", path.display(), loaded.len()));
+                    prompts.set(loaded);
+                    results.set(vec![]);
+                    error.set(String::new());
+                },
+                Err(err) => error.set(format!("Couldn't load {}: {}", path.display(), err))
+            }
+        }));
+    }));
+
+    // polls until the batch is done and fetches its results, or until stopped or failing,
+    // either of which leaves it to be resumed
+    let poll = Rc::new(clone!(
+        @strong results,
+        @strong status,
+        @strong error,
+        @strong running,
+        @strong cancel,
+        @strong stopped,
+        @strong request_log => move |batch: PendingBatch| {
+            let token = CancellationToken::new();
+            cancel.set(token.clone());
+            stopped.set(None);
+            error.set(String::new());
+            running.set(true);
+
+            glib::spawn_future_local(clone!(
+                @strong results,
+                @strong status,
+                @strong error,
+                @strong running,
+                @strong stopped,
+                @strong request_log => async move {
+                    let log = |entry| request_log.lock_mut().push_cloned(entry);
+                    loop {
+                        let batch_status = match poll_batch(&batch.api_key, &batch.id, log).await {
+                            Ok(batch_status) => batch_status,
+                            Err(err) => {
+                                error.set(format!("Polling batch {} failed: {}", batch.id, err));
+                                stopped.set(Some(batch));
+                                break;
+                            }
+                        };
+                        status.set(batch_status.describe());
+
+                        if batch_status.done {
+                            match fetch_results(&batch.api_key, &batch.settings, &batch_status, &batch.prompts, log).await {
+                                Ok(fetched) => results.set(fetched),
+                                Err(err) => error.set(format!("Downloading results failed: {}", err))
+                            }
+                            break;
+                        }
+
+                        // the wait ends early on Stop so that the button takes effect right away
+                        tokio::select! {
+                            _ = glib::timeout_future_seconds(POLL_INTERVAL_SECONDS) => (),
+                            _ = token.cancelled() => ()
+                        }
+                        // the batch keeps running on the provider's side; only our polling stops
+                        if token.is_cancelled() {
+                            status.set(format!("Stopped polling batch {}", batch.id));
+                            stopped.set(Some(batch));
+                            break;
+                        }
+                    }
+                    running.set(false);
+                }
+            ));
+        }
+    ));
+
+    submit_button.connect_clicked(clone!(
+        @strong settings,
+        @strong prompts,
+        @strong results,
+        @strong status,
+        @strong error,
+        @strong running,
+        @strong poll,
+        @strong request_log => move |_| {
+            let settings = settings.lock_ref().clone();
+            let api_key = match settings.api_key {
+                Some(index) => settings.api_keys[index].clone(),
+                None => {
+                    error.set("No API key selected.".to_string());
+                    return;
+                }
+            };
+            let prompts = prompts.get_cloned();
+            if prompts.is_empty() {
+                error.set("Load a file of prompts first.".to_string());
+                return;
+            }
+
+            error.set(String::new());
+            results.set(vec![]);
+            running.set(true);
+
+            glib::spawn_future_local(clone!(
+                @strong status,
+                @strong error,
+                @strong running,
+                @strong poll,
+                @strong request_log => async move {
+                    let log = |entry| request_log.lock_mut().push_cloned(entry);
+                    status.set(format!("Submitting {} requests…", prompts.len()));
+                    match submit_batch(&api_key, &settings, &prompts, log).await {
+                        Ok(id) => poll(PendingBatch { id, api_key, settings, prompts }),
+                        Err(err) => {
+                            error.set(format!("Submitting the batch failed: {}", err));
+                            status.set(String::new());
+                            running.set(false);
+                        }
+                    }
+                }
+            ));
+        }
+    ));
+
+    cancel_button.connect_clicked(clone!(@strong cancel => move |_| cancel.lock_ref().cancel()));
+
+    resume_button.connect_clicked(clone!(@strong stopped => move |_| {
+        if let Some(batch) = stopped.get_cloned() {
+            poll(batch);
+        }
+    }));
+
+    export_button.connect_clicked(clone!(@strong results, @strong error => move |button| {
+        let dialog = file_dialog("Export Results");
+        dialog.set_initial_name(Some("results.jsonl"));
+        let window = window_of(button);
+        glib::spawn_future_local(clone!(@strong results, @strong error => async move {
+            if let Some(path) = dialog.save_future(window.as_ref()).await.ok().and_then(|file| file.path()) {
+                if let Err(err) = export_results(&path, &results.lock_ref()) {
+                    error.set(format!("Couldn't export to {}: {}", path.display(), err));
+                }
+            }
+        }));
+    }));
+
+    glib::spawn_future_local(error.signal_cloned().for_each(move |error| {
+        error_label.set_text(&error);
+        error_label.set_visible(!error.is_empty());
+        async {}
+    }));
+
+    glib::spawn_future_local(status.signal_cloned().for_each(move |status| {
+        status_label.set_text(&status);
+        status_label.set_visible(!status.is_empty());
+        async {}
+    }));
+
+    glib::spawn_future_local(running.signal().for_each(move |running| {
+        submit_button.set_visible(!running);
+        open_button.set_sensitive(!running);
+        cancel_button.set_visible(running);
+        async {}
+    }));
+
+    glib::spawn_future_local(stopped.signal_ref(|stopped| stopped.is_some()).for_each(move |stopped| {
+        resume_button.set_visible(stopped);
+        async {}
+    }));
+
+    glib::spawn_future_local(results.signal_cloned().for_each(move |results| {
+        scrolled_window.set_child(Some(&ResultsTable(&results)));
+        export_button.set_sensitive(!results.is_empty());
+        let cost: f64 = results.iter().filter_map(|result| result.usage.and_then(|usage| usage.cost)).sum();
+        total_label.set_text(&format!("Batch cost {}", format_cost(cost)));
+        total_label.set_visible(!results.is_empty());
+        async {}
+    }));
+
+    return vbox;
+}
+
+#[cfg(test)]
+mod tests;
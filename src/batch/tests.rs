@@ -0,0 +1,310 @@
+// Runs batches against a local server standing in for the provider, and loads
+// prompt files from a scratch directory.
+
+use std::path::PathBuf;
+
+use serde_json::json;
+
+use super::{fetch_results, load_prompts, poll_batch, submit_batch, BatchPrompt, BatchStatus};
+use crate::{
+    settings::{APIKey, Provider, Settings},
+    test_server::{request_body, serve, serve_all, test_settings, Reply}
+};
+
+const ANTHROPIC_MODEL: &str = "claude-3-5-sonnet-20241022";
+const OPENAI_MODEL: &str = "gpt-4o";
+
+fn write_prompts(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("llm-playground-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    return path;
+}
+
+fn settings(provider: Provider, model: &str, base_url: String) -> (APIKey, Settings) {
+    let settings = test_settings(provider, model, base_url).get_cloned();
+    return (settings.api_keys[0].clone(), settings);
+}
+
+fn prompts(ids: &[&str]) -> Vec<BatchPrompt> {
+    return ids
+        .iter()
+        .map(|id| BatchPrompt { custom_id: id.to_string(), prompt: format!("Prompt {}", id) })
+        .collect();
+}
+
+fn finished(result_urls: Vec<String>) -> BatchStatus {
+    return BatchStatus {
+        id: "batch".to_string(),
+        status: "ended".to_string(),
+        succeeded: 0,
+        failed: 0,
+        total: 0,
+        done: true,
+        result_urls
+    };
+}
+
+fn jsonl(lines: &[serde_json::Value]) -> String {
+    return lines.iter().map(|line| line.to_string() + "\n").collect();
+}
+
+fn assert_cost(cost: Option<f64>, expected: f64) {
+    assert!((cost.unwrap() - expected).abs() < 1e-12, "{:?} != {}", cost, expected);
+}
+
+#[test]
+fn csv_prompts() {
+    let path = write_prompts("prompts.csv", "id,prompt\na,\"Hello, world\"\nb,Second\n");
+    let prompts = load_prompts(&path).unwrap();
+
+    assert_eq!(prompts.len(), 2);
+    assert_eq!((prompts[0].custom_id.as_str(), prompts[0].prompt.as_str()), ("a", "Hello, world"));
+    assert_eq!((prompts[1].custom_id.as_str(), prompts[1].prompt.as_str()), ("b", "Second"));
+}
+
+#[test]
+fn csv_without_ids() {
+    let path = write_prompts("no-ids.csv", "prompt\nFirst\nSecond\n");
+    let ids: Vec<String> = load_prompts(&path).unwrap().into_iter().map(|prompt| prompt.custom_id).collect();
+
+    assert_eq!(ids, vec!["request-1", "request-2"]);
+}
+
+#[test]
+fn csv_without_prompt_column() {
+    let path = write_prompts("no-prompt.csv", "id,text\na,Hello\n");
+
+    assert_eq!(load_prompts(&path).unwrap_err(), "The CSV needs a \"prompt\" column");
+}
+
+#[test]
+fn jsonl_prompts() {
+    let path = write_prompts("prompts.jsonl", "\"Bare\"\n\n{\"custom_id\": \"x\", \"prompt\": \"Object\"}\n{\"id\": \"y\", \"prompt\": \"Id\"}\n");
+    let prompts: Vec<(String, String)> = load_prompts(&path)
+        .unwrap()
+        .into_iter()
+        .map(|prompt| (prompt.custom_id, prompt.prompt))
+        .collect();
+
+    assert_eq!(prompts, vec![
+        ("request-1".to_string(), "Bare".to_string()),
+        ("x".to_string(), "Object".to_string()),
+        ("y".to_string(), "Id".to_string())
+    ]);
+}
+
+#[test]
+fn jsonl_without_prompt() {
+    let path = write_prompts("no-prompt.jsonl", "\"Fine\"\n{\"custom_id\": \"x\"}\n");
+
+    assert_eq!(load_prompts(&path).unwrap_err(), "Line 2: missing \"prompt\"");
+}
+
+#[test]
+fn duplicate_ids() {
+    let path = write_prompts("duplicates.jsonl", "{\"custom_id\": \"a\", \"prompt\": \"One\"}\n{\"custom_id\": \"a\", \"prompt\": \"Two\"}\n");
+
+    assert_eq!(load_prompts(&path).unwrap_err(), "Duplicate custom_id \"a\"");
+}
+
+#[test]
+fn empty_file() {
+    let path = write_prompts("empty.jsonl", "\n");
+
+    assert_eq!(load_prompts(&path).unwrap_err(), "The file has no prompts");
+}
+
+#[tokio::test]
+async fn submit_anthropic() {
+    let (base_url, server) = serve(Reply::json(r#"{"id": "msgbatch_1"}"#)).await;
+    let (api_key, settings) = settings(Provider::Anthropic, ANTHROPIC_MODEL, base_url);
+
+    let id = submit_batch(&api_key, &settings, &prompts(&["a", "b"]), |_| ()).await;
+    assert_eq!(id, Ok("msgbatch_1".to_string()));
+
+    let request = server.await.unwrap();
+    assert!(request.starts_with("POST /v1/messages/batches "));
+    let body: serde_json::Value = serde_json::from_str(request_body(&request)).unwrap();
+    let requests = body["requests"].as_array().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0]["custom_id"], "a");
+    assert_eq!(requests[0]["params"]["model"], ANTHROPIC_MODEL);
+    assert!(requests[0]["params"]["stream"].is_null());
+    assert!(requests[1]["params"]["messages"].to_string().contains("Prompt b"));
+}
+
+#[tokio::test]
+async fn submit_openai() {
+    let (base_url, server) = serve_all(vec![
+        Reply::json(r#"{"id": "file-1"}"#),
+        Reply::json(r#"{"id": "batch_1"}"#)
+    ]).await;
+    let (api_key, settings) = settings(Provider::OpenAI, OPENAI_MODEL, base_url);
+
+    let id = submit_batch(&api_key, &settings, &prompts(&["a", "b"]), |_| ()).await;
+    assert_eq!(id, Ok("batch_1".to_string()));
+
+    let requests = server.await.unwrap();
+    let upload = &requests[0];
+    assert!(upload.starts_with("POST /v1/files "));
+    assert!(upload.contains("name=\"purpose\"\r\n\r\nbatch"));
+    assert!(upload.contains("filename=\"batch.jsonl\""));
+    let lines: Vec<serde_json::Value> = request_body(upload)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|line| line.is_object())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["custom_id"], "b");
+    assert_eq!(lines[1]["method"], "POST");
+    assert_eq!(lines[1]["url"], "/v1/chat/completions");
+    assert_eq!(lines[1]["body"]["model"], OPENAI_MODEL);
+    assert!(lines[1]["body"]["stream"].is_null());
+    assert!(lines[1]["body"]["stream_options"].is_null());
+
+    let create = &requests[1];
+    assert!(create.starts_with("POST /v1/batches "));
+    let body: serde_json::Value = serde_json::from_str(request_body(create)).unwrap();
+    assert_eq!(body, json!({
+        "input_file_id": "file-1",
+        "endpoint": "/v1/chat/completions",
+        "completion_window": "24h"
+    }));
+}
+
+#[tokio::test]
+async fn poll_anthropic() {
+    let reply = json!({
+        "id": "msgbatch_1",
+        "processing_status": "ended",
+        "request_counts": { "processing": 0, "succeeded": 2, "errored": 1, "canceled": 0, "expired": 1 },
+        "results_url": "https://example.com/results"
+    });
+    let (base_url, server) = serve(Reply::json(&reply.to_string())).await;
+    let (api_key, _) = settings(Provider::Anthropic, ANTHROPIC_MODEL, base_url);
+
+    let status = poll_batch(&api_key, "msgbatch_1", |_| ()).await.unwrap();
+    assert!(server.await.unwrap().starts_with("GET /v1/messages/batches/msgbatch_1 "));
+    assert!(status.done);
+    assert_eq!((status.succeeded, status.failed, status.total), (2, 2, 4));
+    assert_eq!(status.result_urls, vec!["https://example.com/results"]);
+}
+
+#[tokio::test]
+async fn poll_openai() {
+    let running = json!({
+        "id": "batch_1",
+        "status": "in_progress",
+        "request_counts": { "total": 3, "completed": 1, "failed": 0 },
+        "output_file_id": null,
+        "error_file_id": null
+    });
+    let completed = json!({
+        "id": "batch_1",
+        "status": "completed",
+        "request_counts": { "total": 3, "completed": 2, "failed": 1 },
+        "output_file_id": "file-out",
+        "error_file_id": "file-err"
+    });
+    let (base_url, server) = serve_all(vec![
+        Reply::json(&running.to_string()),
+        Reply::json(&completed.to_string())
+    ]).await;
+    let (api_key, _) = settings(Provider::OpenAI, OPENAI_MODEL, base_url.clone());
+
+    let status = poll_batch(&api_key, "batch_1", |_| ()).await.unwrap();
+    assert!(!status.done);
+    assert_eq!(status.status, "in_progress");
+    assert!(status.result_urls.is_empty());
+
+    let status = poll_batch(&api_key, "batch_1", |_| ()).await.unwrap();
+    assert!(status.done);
+    assert_eq!((status.succeeded, status.failed, status.total), (2, 1, 3));
+    assert_eq!(status.result_urls, vec![
+        format!("{}/files/file-out/content", base_url),
+        format!("{}/files/file-err/content", base_url)
+    ]);
+
+    let requests = server.await.unwrap();
+    assert!(requests.iter().all(|request| request.starts_with("GET /v1/batches/batch_1 ")));
+}
+
+// results arrive out of order, one errored and one never came back
+#[tokio::test]
+async fn anthropic_results() {
+    let results = jsonl(&[
+        json!({
+            "custom_id": "c",
+            "result": {
+                "type": "succeeded",
+                "message": {
+                    "content": [{ "type": "text", "text": "Answer " }, { "type": "text", "text": "c" }],
+                    "usage": { "input_tokens": 1000, "output_tokens": 100 }
+                }
+            }
+        }),
+        json!({
+            "custom_id": "a",
+            "result": { "type": "errored", "error": { "type": "error", "error": { "type": "invalid_request_error", "message": "Too long" } } }
+        })
+    ]);
+    let (base_url, server) = serve(Reply::json(&results)).await;
+    let (api_key, settings) = settings(Provider::Anthropic, ANTHROPIC_MODEL, base_url.clone());
+    let status = finished(vec![format!("{}/messages/batches/msgbatch_1/results", base_url)]);
+
+    let results = fetch_results(&api_key, &settings, &status, &prompts(&["a", "b", "c"]), |_| ()).await.unwrap();
+    assert!(server.await.unwrap().starts_with("GET /v1/messages/batches/msgbatch_1/results "));
+
+    let ids: Vec<&str> = results.iter().map(|result| result.custom_id.as_str()).collect();
+    assert_eq!(ids, vec!["a", "b", "c"]);
+    assert_eq!(results[0].error.as_deref(), Some("Too long"));
+    assert_eq!(results[0].prompt, "Prompt a");
+    assert_eq!(results[1].error.as_deref(), Some("No result"));
+    assert!(results[2].error.is_none());
+    assert_eq!(results[2].response, "Answer c");
+    // $3 in and $15 out per million tokens, halved
+    assert_cost(results[2].usage.unwrap().cost, (1000.0 * 3.0 + 100.0 * 15.0) / 1_000_000.0 * 0.5);
+}
+
+// the output and error files are fetched in turn and merged
+#[tokio::test]
+async fn openai_results() {
+    let output = jsonl(&[
+        json!({
+            "custom_id": "c",
+            "response": {
+                "status_code": 200,
+                "body": {
+                    "choices": [{ "message": { "role": "assistant", "content": "Answer c" } }],
+                    "usage": { "prompt_tokens": 1000, "completion_tokens": 100 }
+                }
+            },
+            "error": null
+        }),
+        json!({
+            "custom_id": "a",
+            "response": { "status_code": 400, "body": { "error": { "message": "Bad request" } } },
+            "error": null
+        })
+    ]);
+    let errors = jsonl(&[json!({ "custom_id": "b", "response": null, "error": { "message": "Expired" } })]);
+    let (base_url, server) = serve_all(vec![Reply::json(&output), Reply::json(&errors)]).await;
+    let (api_key, settings) = settings(Provider::OpenAI, OPENAI_MODEL, base_url.clone());
+    let status = finished(vec![
+        format!("{}/files/file-out/content", base_url),
+        format!("{}/files/file-err/content", base_url)
+    ]);
+
+    let results = fetch_results(&api_key, &settings, &status, &prompts(&["a", "b", "c"]), |_| ()).await.unwrap();
+    let requests = server.await.unwrap();
+    assert!(requests[0].starts_with("GET /v1/files/file-out/content "));
+    assert!(requests[1].starts_with("GET /v1/files/file-err/content "));
+
+    let ids: Vec<&str> = results.iter().map(|result| result.custom_id.as_str()).collect();
+    assert_eq!(ids, vec!["a", "b", "c"]);
+    assert_eq!(results[0].error.as_deref(), Some("Bad request"));
+    assert_eq!(results[1].error.as_deref(), Some("Expired"));
+    assert_eq!(results[2].response, "Answer c");
+    // $2.50 in and $10 out per million tokens, halved
+    assert_cost(results[2].usage.unwrap().cost, (1000.0 * 2.5 + 100.0 * 10.0) / 1_000_000.0 * 0.5);
+}
@@ -0,0 +1,411 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+
+use crate::{inspector::LogEntry, settings::APIKey};
+
+const SERVICE: &str = "bedrock";
+const ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+
+pub struct Credentials<'a> {
+    pub access_key: &'a str,
+    pub secret_key: &'a str,
+    pub session_token: Option<&'a str>
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    return hex::encode(Sha256::digest(data));
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    return mac.finalize().into_bytes().to_vec();
+}
+
+// percent-encodes everything except the unreserved characters, as SigV4 requires
+pub fn uri_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded += &format!("%{:02X}", byte)
+        }
+    }
+
+    return encoded;
+}
+
+// days since the epoch to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = (if days >= 0 { days } else { days - 146_096 }) / 146_097;
+    let day_of_era = (days - era * 146_097) as u64;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = (if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 }) as u32;
+    let year = year_of_era as i64 + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return (year, month, day);
+}
+
+// formats a timestamp as the ISO 8601 basic format SigV4 uses, e.g. 20150830T123600Z
+pub fn amz_date(timestamp: SystemTime) -> String {
+    let seconds = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let seconds_of_day = seconds.rem_euclid(86_400);
+
+    return format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year, month, day,
+        seconds_of_day / 3600, (seconds_of_day / 60) % 60, seconds_of_day % 60
+    );
+}
+
+// returns the headers to add to a request so AWS accepts it, following
+// https://docs.aws.amazon.com/IAM/latest/UserGuide/create-signed-request.html
+#[allow(clippy::too_many_arguments)]
+pub fn sign(
+    method: &str,
+    host: &str,
+    path: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    payload: &[u8],
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    timestamp: SystemTime
+) -> Vec<(String, String)> {
+    let amz_date = amz_date(timestamp);
+    let date = &amz_date[..8];
+
+    let mut signed: Vec<(String, String)> = headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+        .collect();
+    signed.push(("host".to_string(), host.to_string()));
+    signed.push(("x-amz-date".to_string(), amz_date.clone()));
+    if let Some(session_token) = credentials.session_token {
+        signed.push(("x-amz-security-token".to_string(), session_token.to_string()));
+    }
+    signed.sort();
+
+    let canonical_headers: String = signed
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = signed
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<&str>>()
+        .join(";");
+
+    // every service but S3 encodes the already encoded path a second time
+    let canonical_uri = path
+        .split('/')
+        .map(uri_encode)
+        .collect::<Vec<String>>()
+        .join("/");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, canonical_uri, query, canonical_headers, signed_headers, sha256_hex(payload)
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date, scope, sha256_hex(canonical_request.as_bytes())
+    );
+
+    let date_key = hmac_sha256(format!("AWS4{}", credentials.secret_key).as_bytes(), date.as_bytes());
+    let region_key = hmac_sha256(&date_key, region.as_bytes());
+    let service_key = hmac_sha256(&region_key, service.as_bytes());
+    let signing_key = hmac_sha256(&service_key, b"aws4_request");
+    let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    let mut added = vec![
+        ("x-amz-date".to_string(), amz_date.clone()),
+        ("authorization".to_string(), format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key, scope, signed_headers, signature
+        ))
+    ];
+    if let Some(session_token) = credentials.session_token {
+        added.push(("x-amz-security-token".to_string(), session_token.to_string()));
+    }
+
+    return added;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventStreamMessage {
+    // only string-valued headers are kept, which covers :message-type, :event-type and friends
+    pub headers: Vec<(String, String)>,
+    pub payload: Vec<u8>
+}
+
+impl EventStreamMessage {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        return self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str());
+    }
+}
+
+fn read_u16(bytes: &[u8]) -> usize {
+    return u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+}
+
+fn read_u32(bytes: &[u8]) -> usize {
+    return u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+}
+
+fn parse_headers(mut bytes: &[u8]) -> Result<Vec<(String, String)>, String> {
+    let malformed = || "Malformed event stream header".to_string();
+    let mut headers = vec![];
+    while !bytes.is_empty() {
+        let name_len = bytes[0] as usize;
+        let name = bytes.get(1..1 + name_len).ok_or_else(malformed)?;
+        let name = String::from_utf8_lossy(name).to_string();
+        bytes = &bytes[1 + name_len..];
+
+        let value_type = *bytes.first().ok_or_else(malformed)?;
+        bytes = &bytes[1..];
+        let value_len = match value_type {
+            0 | 1 => 0,                 // booleans are encoded in the type itself
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 | 8 => 8,                 // long and timestamp
+            9 => 16,                    // uuid
+            6 | 7 => {                  // byte array and string carry a length prefix
+                let len = read_u16(bytes.get(..2).ok_or_else(malformed)?);
+                bytes = &bytes[2..];
+                len
+            },
+            _ => return Err(malformed())
+        };
+        let value = bytes.get(..value_len).ok_or_else(malformed)?;
+        if value_type == 7 {
+            headers.push((name, String::from_utf8_lossy(value).to_string()));
+        }
+        bytes = &bytes[value_len..];
+    }
+
+    return Ok(headers);
+}
+
+// decodes the binary application/vnd.amazon.eventstream framing:
+// total length, headers length and a prelude CRC, then headers, payload and a message CRC
+#[derive(Default)]
+pub struct EventStreamDecoder {
+    buffer: Vec<u8>
+}
+
+impl EventStreamDecoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn next_message(&mut self) -> Result<Option<EventStreamMessage>, String> {
+        if self.buffer.len() < 12 {
+            return Ok(None);
+        }
+
+        let total_len = read_u32(&self.buffer[0..4]);
+        let headers_len = read_u32(&self.buffer[4..8]);
+        if crc32fast::hash(&self.buffer[0..8]) as usize != read_u32(&self.buffer[8..12]) {
+            return Err("Event stream prelude checksum mismatch".to_string());
+        }
+        if total_len < 16 + headers_len {
+            return Err("Malformed event stream prelude".to_string());
+        }
+        if self.buffer.len() < total_len {
+            return Ok(None);
+        }
+
+        let message: Vec<u8> = self.buffer.drain(..total_len).collect();
+        if crc32fast::hash(&message[..total_len - 4]) as usize != read_u32(&message[total_len - 4..]) {
+            return Err("Event stream message checksum mismatch".to_string());
+        }
+
+        let headers = parse_headers(&message[12..12 + headers_len])?;
+        let payload = message[12 + headers_len..total_len - 4].to_vec();
+
+        return Ok(Some(EventStreamMessage { headers, payload }));
+    }
+}
+
+// Bedrock takes the model in the URL and the API version in the body
+pub fn bedrock_body(body: &serde_json::Value) -> serde_json::Value {
+    let mut body = body.clone();
+    let object = body.as_object_mut().unwrap();
+    object.remove("model");
+    object.remove("stream");
+    object.insert("anthropic_version".to_string(), json!(ANTHROPIC_VERSION));
+
+    return body;
+}
+
+pub fn bedrock_host(api_key: &APIKey) -> String {
+    return format!("bedrock-runtime.{}.amazonaws.com", api_key.region.as_deref().unwrap_or("us-east-1"));
+}
+
+pub fn bedrock_path(model: &str) -> String {
+    return format!("/model/{}/invoke-with-response-stream", uri_encode(model));
+}
+
+// streams InvokeModelWithResponseStream, passing each decoded Anthropic event to on_event
+pub async fn stream_response(
+    api_key: &APIKey,
+    model: &str,
+    body: &serde_json::Value,
+    cancel: CancellationToken,
+    mut on_event: impl FnMut(&serde_json::Value),
+    err: impl Fn(String),
+    log: impl Fn(LogEntry)
+) {
+    let body = bedrock_body(body).to_string();
+    let host = bedrock_host(api_key);
+    let path = bedrock_path(model);
+    let region = api_key.region.as_deref().unwrap_or("us-east-1");
+    let credentials = Credentials {
+        access_key: &api_key.key,
+        secret_key: api_key.secret_key.as_deref().unwrap_or_default(),
+        session_token: api_key.session_token.as_deref()
+    };
+
+    let base_headers = [
+        ("content-type", "application/json"),
+        ("accept", "application/vnd.amazon.eventstream")
+    ];
+    let signature = sign(
+        "POST", &host, &path, "", &base_headers, body.as_bytes(),
+        &credentials, region, SERVICE, SystemTime::now()
+    );
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in base_headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).chain(signature) {
+        // a key or token pasted with a line break or other control character can't go in a header
+        match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&value)) {
+            (Ok(name), Ok(value)) => { headers.insert(name, value); },
+            _ => {
+                let err_msg = format!("Invalid characters in the {} header; check the AWS credentials.", name);
+                log(LogEntry::status(&err_msg));
+                err(err_msg);
+                return;
+            }
+        }
+    }
+
+    let request_builder = reqwest::Client::new()
+        .post(format!("https://{}{}", host, path))
+        .headers(headers)
+        .body(body.clone());
+
+    if let Some(Ok(request)) = request_builder.try_clone().map(|builder| builder.build()) {
+        log(LogEntry::request(&request, &body));
+    }
+
+    let response = tokio::select! {
+        response = request_builder.send() => response,
+        _ = cancel.cancelled() => {
+            log(LogEntry::status("Cancelled"));
+            return;
+        }
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(err_msg) => {
+            log(LogEntry::status(&err_msg));
+            err(err_msg.to_string());
+            return;
+        }
+    };
+
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        log(LogEntry::status(format!("{}: {}", status, text)));
+        err(format!("Invalid status code: {}: {}", status, text));
+        return;
+    }
+    log(LogEntry::status("Connection opened"));
+
+    let mut decoder = EventStreamDecoder::default();
+    let mut stream = response.bytes_stream();
+    loop {
+        let chunk = tokio::select! {
+            chunk = stream.next() => chunk,
+            _ = cancel.cancelled() => {
+                log(LogEntry::status("Cancelled"));
+                return;
+            }
+        };
+        let chunk = match chunk {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(err_msg)) => {
+                log(LogEntry::status(&err_msg));
+                err(err_msg.to_string());
+                return;
+            },
+            None => {
+                log(LogEntry::status("Stream ended"));
+                return;
+            }
+        };
+
+        decoder.push(&chunk);
+        loop {
+            let message = match decoder.next_message() {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(err_msg) => {
+                    log(LogEntry::status(&err_msg));
+                    err(err_msg);
+                    return;
+                }
+            };
+
+            let payload = String::from_utf8_lossy(&message.payload).to_string();
+            match message.header(":message-type") {
+                Some("event") => {
+                    // chunk events wrap the Anthropic event JSON in base64
+                    let event = serde_json::from_str::<serde_json::Value>(&payload).ok()
+                        .and_then(|payload| payload["bytes"].as_str().map(|bytes| bytes.to_string()))
+                        .and_then(|bytes| BASE64.decode(bytes).ok())
+                        .map(|bytes| String::from_utf8_lossy(&bytes).to_string());
+                    if let Some(event) = event {
+                        log(LogEntry::event(message.header(":event-type").unwrap_or_default(), &event));
+                        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&event) {
+                            on_event(&data);
+                        }
+                    }
+                },
+                _ => {
+                    let exception = message.header(":exception-type")
+                        .or(message.header(":error-code"))
+                        .unwrap_or("Error")
+                        .to_string();
+                    log(LogEntry::event(&exception, &payload));
+                    err(format!("{}: {}", exception, payload));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
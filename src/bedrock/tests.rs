@@ -0,0 +1,173 @@
+// Checks the request signing against cases from AWS's SigV4 test suite and the
+// event stream decoder against fixed frames, all without touching the network.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{amz_date, parse_headers, sign, uri_encode, Credentials, EventStreamDecoder};
+
+// the suite's credentials, scope and clock
+const CREDENTIALS: Credentials = Credentials {
+    access_key: "AKIDEXAMPLE",
+    secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+    session_token: None
+};
+const HOST: &str = "example.amazonaws.com";
+const REGION: &str = "us-east-1";
+const SERVICE: &str = "service";
+
+// a Bedrock chunk event wrapping {"type":"message_stop"}, with string headers only
+const CHUNK_FRAME: &str = concat!(
+    "000000870000004b87ea2bf30b3a6576656e742d747970650700056368756e6b",
+    "0d3a636f6e74656e742d747970650700106170706c69636174696f6e2f6a736f",
+    "6e0d3a6d6573736167652d747970650700056576656e747b226279746573223a",
+    "2265794a306558426c496a6f696257567a6332466e5a56397a64473977496e30",
+    "3d227dba48ab22"
+);
+
+// the smallest valid frame: no headers, no payload
+const EMPTY_FRAME: &str = "000000100000000005c248eb7d98c8ff";
+
+// an int32 header named "rows" ahead of :message-type
+const INT_HEADER_FRAME: &str = concat!(
+    "0000003000000020ff6d472704726f777304000000070d3a6d6573736167652d",
+    "747970650700056576656e744042f2e1"
+);
+
+fn suite_time() -> SystemTime {
+    return UNIX_EPOCH + Duration::from_secs(1_440_938_160);
+}
+
+fn authorization(method: &str, query: &str) -> String {
+    let added = sign(method, HOST, "/", query, &[], b"", &CREDENTIALS, REGION, SERVICE, suite_time());
+    return added
+        .into_iter()
+        .find(|(name, _)| name == "authorization")
+        .map(|(_, value)| value)
+        .unwrap();
+}
+
+fn frame(hex: &str) -> Vec<u8> {
+    return hex::decode(hex).unwrap();
+}
+
+#[test]
+fn get_vanilla() {
+    assert_eq!(
+        authorization("GET", ""),
+        "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+         SignedHeaders=host;x-amz-date, \
+         Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+    );
+}
+
+#[test]
+fn post_vanilla() {
+    assert!(authorization("POST", "").ends_with("Signature=5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b"));
+}
+
+// the caller passes the canonical query, sorted by key with uppercase first
+#[test]
+fn get_vanilla_query_order_key_case() {
+    assert!(authorization("GET", "Param1=value1&Param2=value2")
+        .ends_with("Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"));
+}
+
+#[test]
+fn session_token_is_signed() {
+    let credentials = Credentials { session_token: Some("token"), ..CREDENTIALS };
+    let added = sign("GET", HOST, "/", "", &[], b"", &credentials, REGION, SERVICE, suite_time());
+
+    assert!(added.contains(&("x-amz-security-token".to_string(), "token".to_string())));
+    let (_, authorization) = added.iter().find(|(name, _)| name == "authorization").unwrap();
+    assert!(authorization.contains("SignedHeaders=host;x-amz-date;x-amz-security-token,"));
+}
+
+#[test]
+fn dates() {
+    assert_eq!(amz_date(suite_time()), "20150830T123600Z");
+    assert_eq!(amz_date(UNIX_EPOCH), "19700101T000000Z");
+    assert_eq!(amz_date(UNIX_EPOCH + Duration::from_secs(951_825_599)), "20000229T115959Z");
+    assert_eq!(amz_date(UNIX_EPOCH + Duration::from_secs(4_107_542_400)), "21000301T000000Z");
+}
+
+#[test]
+fn encodes_all_but_unreserved() {
+    assert_eq!(uri_encode("anthropic.claude-3-5-sonnet-20241022-v2:0"), "anthropic.claude-3-5-sonnet-20241022-v2%3A0");
+    assert_eq!(uri_encode("a b/~é"), "a%20b%2F~%C3%A9");
+}
+
+#[test]
+fn decodes_known_frame() {
+    let mut decoder = EventStreamDecoder::default();
+    decoder.push(&frame(CHUNK_FRAME));
+    let message = decoder.next_message().unwrap().unwrap();
+
+    assert_eq!(message.header(":event-type"), Some("chunk"));
+    assert_eq!(message.header(":content-type"), Some("application/json"));
+    assert_eq!(message.header(":message-type"), Some("event"));
+    assert_eq!(message.payload, br#"{"bytes":"eyJ0eXBlIjoibWVzc2FnZV9zdG9wIn0="}"#);
+    assert_eq!(decoder.next_message(), Ok(None));
+}
+
+#[test]
+fn decodes_empty_frame() {
+    let mut decoder = EventStreamDecoder::default();
+    decoder.push(&frame(EMPTY_FRAME));
+    let message = decoder.next_message().unwrap().unwrap();
+
+    assert!(message.headers.is_empty());
+    assert!(message.payload.is_empty());
+}
+
+#[test]
+fn skips_non_string_headers() {
+    let bytes = frame(INT_HEADER_FRAME);
+    assert_eq!(parse_headers(&bytes[12..44]), Ok(vec![(":message-type".to_string(), "event".to_string())]));
+    assert!(parse_headers(&bytes[12..20]).is_err());
+}
+
+#[test]
+fn rejects_bad_message_crc() {
+    let mut bytes = frame(CHUNK_FRAME);
+    bytes[100] ^= 1;
+    let mut decoder = EventStreamDecoder::default();
+    decoder.push(&bytes);
+
+    assert_eq!(decoder.next_message(), Err("Event stream message checksum mismatch".to_string()));
+}
+
+#[test]
+fn rejects_bad_prelude_crc() {
+    let mut bytes = frame(CHUNK_FRAME);
+    bytes[3] ^= 1;
+    let mut decoder = EventStreamDecoder::default();
+    decoder.push(&bytes);
+
+    assert_eq!(decoder.next_message(), Err("Event stream prelude checksum mismatch".to_string()));
+}
+
+#[test]
+fn frame_split_across_chunks() {
+    let bytes = frame(CHUNK_FRAME);
+    let mut decoder = EventStreamDecoder::default();
+    for (index, byte) in bytes.iter().enumerate() {
+        decoder.push(&[*byte]);
+        let message = decoder.next_message().unwrap();
+        assert_eq!(message.is_some(), index == bytes.len() - 1);
+    }
+}
+
+#[test]
+fn frames_sharing_a_chunk() {
+    let mut bytes = frame(EMPTY_FRAME);
+    bytes.extend(frame(CHUNK_FRAME));
+    let (first, second) = bytes.split_at(40);
+    let mut decoder = EventStreamDecoder::default();
+
+    decoder.push(first);
+    assert!(decoder.next_message().unwrap().unwrap().headers.is_empty());
+    assert_eq!(decoder.next_message(), Ok(None));
+    decoder.push(second);
+    assert_eq!(decoder.next_message().unwrap().unwrap().header(":event-type"), Some("chunk"));
+    assert_eq!(decoder.next_message(), Ok(None));
+}
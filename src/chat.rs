@@ -1,11 +1,28 @@
 #![allow(non_snake_case)]
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::{Cell, RefCell}, rc::Rc, time::Duration};
 
 use gtk::{glib::{self, clone}, prelude::*, Label};
-use futures_signals::{signal::{Mutable, SignalExt}, signal_vec::{MutableVec, SignalVecExt, VecDiff}};
+use futures_signals::{map_ref, signal::{Mutable, SignalExt}, signal_vec::{MutableVec, SignalVecExt, VecDiff}};
 use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
 
-use crate::{settings::Settings, submit::SubmitButton, util::{get_buffer_content, DummyLabel}};
+use crate::{
+    context::{plan_context, OverflowStrategy},
+    conversation::{Conversation, ConversationTree},
+    export::ExportButton,
+    find::{FindBar, FindShortcut},
+    history::{default_title, History, HistorySidebar, SettingsSnapshot},
+    import::ImportButton,
+    inspector::LogEntry,
+    markdown::{append_markdown, stable_prefix_len, Markdown},
+    message::{Message, Metadata, Rating, Role, ROLES},
+    pricing::format_cost,
+    settings::Settings,
+    snippets::CodeWindow,
+    submit::{build_request_body, preview_context, regenerate_message, SubmitButton},
+    tokens::{count_message_tokens, count_prompt_tokens, ContextFit},
+    util::{format_timestamp, get_buffer_content, DummyLabel}
+};
 
 
 fn MessageTextBox(message: &str) -> gtk::Label {
@@ -49,7 +66,13 @@ fn PromptTextBox(clear_prompt: Rc<Notify>) -> gtk::TextView {
     return text_view;
 }
 
+// finished blocks are rendered once and left alone, while the block still being
+// written stays plain text, so the reply doesn't reflow on every token
 fn ResponseTextBox(response_tokens: &MutableVec<String>, streaming: Mutable<bool>) -> impl IsA<gtk::Widget> {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    let rendered = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    vbox.append(&rendered);
+
     let text_view = gtk::TextView::new();
     text_view.set_editable(false);
     text_view.set_cursor_visible(false);
@@ -59,13 +82,36 @@ fn ResponseTextBox(response_tokens: &MutableVec<String>, streaming: Mutable<bool
     text_view.set_valign(gtk::Align::Start);
     text_view.set_left_margin(0);
     text_view.set_right_margin(0);
+    vbox.append(&text_view);
+
+    // the reply so far and how much of it has been rendered
+    let streamed = Rc::new(RefCell::new((String::new(), 0usize)));
 
     glib::spawn_future_local(response_tokens.signal_vec_cloned().for_each({
         let text_view = text_view.clone();
         move |vd| {
             match vd {
-                VecDiff::Push { value: token } => text_view.buffer().insert(&mut text_view.buffer().end_iter(), &token),
-                VecDiff::Clear {} => text_view.buffer().set_text(""),
+                VecDiff::Push { value: token } => {
+                    let mut streamed = streamed.borrow_mut();
+                    let (text, rendered_len) = &mut *streamed;
+                    text.push_str(&token);
+                    // a block boundary is never inside a code fence, so scanning can start at the last one
+                    let stable_len = *rendered_len + stable_prefix_len(&text[*rendered_len..]);
+                    if stable_len > *rendered_len {
+                        append_markdown(&rendered, &text[*rendered_len..stable_len]);
+                        *rendered_len = stable_len;
+                        text_view.buffer().set_text(&text[stable_len..]);
+                    } else {
+                        text_view.buffer().insert(&mut text_view.buffer().end_iter(), &token);
+                    }
+                },
+                VecDiff::Clear {} => {
+                    *streamed.borrow_mut() = (String::new(), 0);
+                    text_view.buffer().set_text("");
+                    while let Some(child) = rendered.first_child() {
+                        rendered.remove(&child);
+                    }
+                },
                 _ => panic!("Not supported.")
             }
             async {}
@@ -73,20 +119,22 @@ fn ResponseTextBox(response_tokens: &MutableVec<String>, streaming: Mutable<bool
     }));
 
     glib::spawn_future_local(streaming.signal().for_each({
-        let text_view = text_view.clone();
+        let vbox = vbox.clone();
         move |streaming| {
-            text_view.set_visible(streaming);
+            vbox.set_visible(streaming);
             async {}
         }
     }));
 
-    return text_view;
+    return vbox;
 }
 
 fn NewButton(
-    exchanges: MutableVec<(String, String)>,
+    conversation: Conversation,
     streaming: Mutable<bool>,
-    clear_prompt: Rc<Notify>
+    clear_prompt: Rc<Notify>,
+    request_log: MutableVec<LogEntry>,
+    toast: Mutable<Option<String>>
 ) -> impl IsA<gtk::Widget> {
     let button = gtk::Button::builder()
         .label("New")
@@ -101,10 +149,16 @@ fn NewButton(
     }));    
 
     button.connect_clicked(move |_| {
-        let exchanges = exchanges.clone();
+        let conversation = conversation.clone();
         let clear_prompt = clear_prompt.clone();
+        let request_log = request_log.clone();
+        let toast = toast.clone();
         glib::spawn_future_local(async move {
-            exchanges.lock_mut().clear();
+            if !conversation.messages.lock_ref().is_empty() {
+                toast.set(Some("Started a new chat".to_string()));
+            }
+            conversation.clear();
+            request_log.lock_mut().clear();
             clear_prompt.notify_one();
         });
     });
@@ -112,17 +166,13 @@ fn NewButton(
     return button;
 }
 
-fn CancelButton(streaming: Mutable<bool>) -> impl IsA<gtk::Widget> {
+fn CancelButton(streaming: Mutable<bool>, cancel: Mutable<CancellationToken>) -> impl IsA<gtk::Widget> {
     let button = gtk::Button::builder()
         .label("Cancel")
         .build();
     
-    button.connect_clicked(clone!(
-        @strong streaming
-        => move |_| {
-            *streaming.lock_mut() = false;
-        }
-    ));
+    // the submit task drops the connection and returns to idle once the token fires
+    button.connect_clicked(move |_| cancel.lock_ref().cancel());
 
     glib::spawn_future_local(streaming.signal().for_each({
         let button = button.clone();
@@ -144,36 +194,233 @@ fn ExchangeHeaderOption(label: &str) -> gtk::Button {
     return button;
 }
 
+// replies that end for any other reason were cut short or stopped to call a tool
+const NORMAL_STOP_REASONS: [&str; 4] = ["stop", "end_turn", "stop_sequence", "completed"];
+
+fn MetadataLabel(metadata: &Metadata) -> Label {
+    let mut parts = vec![];
+    if let Some(model) = &metadata.model {
+        parts.push(model.clone());
+    }
+    if let Some(usage) = metadata.usage {
+        parts.push(format!("{} in · {} out", usage.input_tokens, usage.output_tokens));
+        if usage.cache_read_tokens > 0 || usage.cache_write_tokens > 0 {
+            parts.push(format!("{} cached · {} cache write", usage.cache_read_tokens, usage.cache_write_tokens));
+        }
+        match usage.cost {
+            Some(cost) => parts.push(format_cost(cost)),
+            None => parts.push("cost unknown".to_string())
+        }
+    }
+    if let Some(stop_reason) = &metadata.stop_reason {
+        if !NORMAL_STOP_REASONS.contains(&stop_reason.as_str()) {
+            parts.push(format!("stopped: {}", stop_reason));
+        }
+    }
+
+    let label = Label::new(Some(&parts.join(" · ")));
+    label.set_css_classes(&["usage-label"]);
+    label.set_visible(!parts.is_empty());
+    return label;
+}
+
+fn RoleLabel(message: &Message) -> Label {
+    let label = Label::new(Some(&message.role.to_string()));
+    label.set_css_classes(&["role-label"]);
+    if let Some(timestamp) = message.metadata.timestamp {
+        label.set_tooltip_text(Some(&format!("{} UTC", format_timestamp(timestamp))));
+    }
+
+    return label;
+}
+
+// "< 2/3 >" arrows that flip between the alternatives of a message
+fn BranchSwitcher((position, count): (usize, usize), switch_branch: impl Fn(isize) + 'static) -> gtk::Box {
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    hbox.set_visible(count > 1);
+    let switch_branch = Rc::new(switch_branch);
+
+    let previous_button = ExchangeHeaderOption("<");
+    previous_button.set_sensitive(position > 0);
+    previous_button.connect_clicked(clone!(@strong switch_branch => move |_| switch_branch(-1)));
+    hbox.append(&previous_button);
+
+    let label = Label::new(Some(&format!("{}/{}", position + 1, count)));
+    label.set_css_classes(&["branch-label"]);
+    hbox.append(&label);
+
+    let next_button = ExchangeHeaderOption(">");
+    next_button.set_sensitive(position + 1 < count);
+    next_button.connect_clicked(move |_| switch_branch(1));
+    hbox.append(&next_button);
+
+    return hbox;
+}
+
+// the model and temperature start out as the current settings each time the popover opens
+fn RegenerateOption(settings: Mutable<Settings>, regenerate: impl Fn(Settings) + 'static) -> gtk::MenuButton {
+    let menu_button = gtk::MenuButton::new();
+    menu_button.set_label("Regenerate");
+    menu_button.set_has_frame(false);
+    menu_button.set_css_classes(&["exchange-header-button"]);
+
+    let grid = gtk::Grid::new();
+    grid.set_css_classes(&["settings-box"]);
+    grid.set_row_spacing(10);
+    grid.set_column_spacing(10);
+    let label = Label::new(Some("Model:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 0, 1, 1);
+    let model_entry = gtk::Entry::new();
+    grid.attach(&model_entry, 1, 0, 1, 1);
+    let label = Label::new(Some("Temperature:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 1, 1, 1);
+    let temperature_entry = gtk::SpinButton::with_range(0.0, 1.0, 0.1);
+    temperature_entry.set_digits(1);
+    grid.attach(&temperature_entry, 1, 1, 1, 1);
+    let regenerate_button = gtk::Button::with_label("Regenerate");
+    grid.attach(&regenerate_button, 0, 2, 2, 1);
+
+    let popover = gtk::Popover::new();
+    popover.set_child(Some(&grid));
+    menu_button.set_popover(Some(&popover));
+
+    popover.connect_show(clone!(
+        @strong settings,
+        @strong model_entry,
+        @strong temperature_entry => move |_| {
+            let settings = settings.lock_ref();
+            model_entry.set_text(&settings.model);
+            temperature_entry.set_value(settings.temperature);
+        }
+    ));
+
+    regenerate_button.connect_clicked(clone!(@weak popover => move |_| {
+        let mut settings = settings.get_cloned();
+        let model = model_entry.text().trim().to_string();
+        if !model.is_empty() {
+            settings.model = model;
+        }
+        settings.temperature = temperature_entry.value();
+        popover.popdown();
+        regenerate(settings);
+    }));
+
+    return menu_button;
+}
+
+// Good and Bad toggles; clicking the active one clears the rating
+fn RatingOption(rating: Option<Rating>, rate: impl Fn(Option<Rating>) + 'static) -> gtk::Box {
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    let good_button = gtk::ToggleButton::with_label("Good");
+    good_button.set_css_classes(&["flat", "exchange-header-button"]);
+    good_button.set_active(rating == Some(Rating::Good));
+    hbox.append(&good_button);
+    let bad_button = gtk::ToggleButton::with_label("Bad");
+    bad_button.set_css_classes(&["flat", "exchange-header-button"]);
+    bad_button.set_active(rating == Some(Rating::Bad));
+    hbox.append(&bad_button);
+
+    let rate = Rc::new(rate);
+    good_button.connect_clicked(clone!(@weak bad_button, @strong rate => move |button| {
+        if button.is_active() {
+            bad_button.set_active(false);
+            rate(Some(Rating::Good));
+        } else {
+            rate(None);
+        }
+    }));
+    bad_button.connect_clicked(clone!(@weak good_button => move |button| {
+        if button.is_active() {
+            good_button.set_active(false);
+            rate(Some(Rating::Bad));
+        } else {
+            rate(None);
+        }
+    }));
+
+    return hbox;
+}
+
+// a single message of the conversation
 type ExchangeWidget = gtk::Box;
+#[allow(clippy::too_many_arguments)]
 fn Exchange(
-    user_message: String,
-    assistant_message: String,
-    edit_exchange: impl Fn((String, String)) + 'static,
-    delete_exchange: impl Fn() + 'static
+    message: Message,
+    branches: (usize, usize),
+    switch_branch: impl Fn(isize) + 'static,
+    edit_message: impl Fn(String) + 'static,
+    delete_message: impl Fn() + 'static,
+    show_code: impl Fn() + 'static,
+    settings: Mutable<Settings>,
+    regenerate: impl Fn(Settings) + 'static,
+    rate_message: impl Fn(Option<Rating>) + 'static,
+    streaming: Mutable<bool>
 ) -> ExchangeWidget {
     let exchange = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    let is_assistant = message.role == Role::Assistant;
 
     let overlay = gtk::Overlay::new();
 
-    let user_text_box = MessageTextBox(&user_message);
-    user_text_box.set_valign(gtk::Align::Start);
-    user_text_box.set_hexpand(true);
-    let editable_user_text_box = EditableMessageTextBox(&user_message);
-    editable_user_text_box.set_hexpand(true);
-    overlay.set_child(Some(&user_text_box));
+    let text_box = MessageTextBox(&message.display_text());
+    text_box.set_valign(gtk::Align::Start);
+    text_box.set_hexpand(true);
+    let rendered_text_box = Markdown(&message.display_text());
+    rendered_text_box.set_valign(gtk::Align::Start);
+    rendered_text_box.set_hexpand(true);
+    let editable_text_box = EditableMessageTextBox(&message.text_content());
+    editable_text_box.set_hexpand(true);
+
+    // replies are rendered by default; prompts are shown as typed, since they're rarely meant as Markdown
+    let raw_button = gtk::ToggleButton::with_label("Raw");
+    raw_button.set_css_classes(&["flat", "exchange-header-button"]);
+    raw_button.set_active(!is_assistant);
+    let show_text = clone!(
+        @weak overlay,
+        @weak raw_button,
+        @weak text_box,
+        @weak rendered_text_box => move || {
+            if raw_button.is_active() {
+                overlay.set_child(Some(&text_box));
+            } else {
+                overlay.set_child(Some(&rendered_text_box));
+            }
+        }
+    );
+    show_text();
+    raw_button.connect_toggled(clone!(@strong show_text => move |_| show_text()));
 
     let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 0);
     hbox.set_css_classes(&["button-box"]);
     hbox.set_halign(gtk::Align::End);
     hbox.set_valign(gtk::Align::Start);
+    hbox.append(&RoleLabel(&message));
+    let branch_switcher = BranchSwitcher(branches, switch_branch);
+    hbox.append(&branch_switcher);
+    hbox.append(&MetadataLabel(&message.metadata));
+    hbox.append(&raw_button);
+
     let edit_button = ExchangeHeaderOption("Edit");
     hbox.append(&edit_button);
 
     let delete_button = ExchangeHeaderOption("Delete");
     delete_button.set_valign(gtk::Align::Start);
-    delete_button.connect_clicked(move |_| delete_exchange());
+    delete_button.connect_clicked(move |_| delete_message());
     hbox.append(&delete_button);
 
+    let code_button = ExchangeHeaderOption("Code");
+    code_button.connect_clicked(move |_| show_code());
+    hbox.append(&code_button);
+
+    let regenerate_button = RegenerateOption(settings, regenerate);
+    regenerate_button.set_visible(is_assistant);
+    hbox.append(&regenerate_button);
+
+    let rating_option = RatingOption(message.metadata.rating, rate_message);
+    rating_option.set_visible(is_assistant);
+    hbox.append(&rating_option);
+
     let done_button = ExchangeHeaderOption("Done");
     done_button.set_visible(false);
     hbox.append(&done_button);
@@ -181,83 +428,99 @@ fn Exchange(
     overlay.add_overlay(&hbox);
 
     exchange.append(&overlay);
-
-    let assistant_text_box = MessageTextBox(&assistant_message);
-    let editable_assistant_text_box = EditableMessageTextBox(&assistant_message);
-    exchange.append(&assistant_text_box);
     exchange.set_hexpand(true);
 
+    // the controls that change the path, taken before the handlers below move them
+    let controls: Vec<glib::WeakRef<gtk::Widget>> = [
+        branch_switcher.upcast_ref::<gtk::Widget>(),
+        edit_button.upcast_ref(),
+        delete_button.upcast_ref(),
+        done_button.upcast_ref()
+    ].iter().map(|control| control.downgrade()).collect();
+    let exchange_ref = exchange.downgrade();
+
     edit_button.connect_clicked(clone!(
         @weak edit_button,
         @weak delete_button,
+        @weak code_button,
+        @weak regenerate_button,
+        @weak rating_option,
+        @weak raw_button,
         @weak done_button,
+        @weak branch_switcher,
         @weak overlay,
-        @weak exchange,
-        @strong user_text_box,
-        @strong assistant_text_box,
-        @strong editable_user_text_box,
-        @strong editable_assistant_text_box
+        @strong editable_text_box
         => move |_| {
+            raw_button.set_visible(false);
             edit_button.set_visible(false);
             delete_button.set_visible(false);
-            exchange.remove(&assistant_text_box);
-            editable_user_text_box.buffer().set_text(&user_text_box.label().to_string());
-            editable_assistant_text_box.buffer().set_text(&assistant_text_box.label().to_string());
-            overlay.set_child(Some(&editable_user_text_box));
-            exchange.append(&editable_assistant_text_box);
+            code_button.set_visible(false);
+            regenerate_button.set_visible(false);
+            rating_option.set_visible(false);
+            branch_switcher.set_visible(false);
+            // attachments and tool blocks aren't editable and are kept as they are
+            editable_text_box.buffer().set_text(&message.text_content());
+            overlay.set_child(Some(&editable_text_box));
             done_button.set_visible(true);
         }
     ));
 
     done_button.connect_clicked(clone!(
-        @weak done_button,
-        @weak exchange
+        @weak done_button
         => move |_| {
             done_button.set_visible(false);
-            exchange.remove(&editable_assistant_text_box);
-            user_text_box.set_label(&get_buffer_content(&editable_user_text_box.buffer()));
-            assistant_text_box.set_label(&get_buffer_content(&editable_assistant_text_box.buffer()));
-            edit_exchange((user_text_box.label().to_string(), assistant_text_box.label().to_string()));
-            overlay.set_child(Some(&user_text_box));
-            exchange.append(&assistant_text_box);
+            edit_message(get_buffer_content(&editable_text_box.buffer()));
+            show_text();
+            raw_button.set_visible(true);
             edit_button.set_visible(true);
             delete_button.set_visible(true);
+            code_button.set_visible(true);
+            regenerate_button.set_visible(is_assistant);
+            rating_option.set_visible(is_assistant);
+            branch_switcher.set_visible(branches.1 > 1);
         }
     ));
 
-    return exchange;
-}
-
-fn edit_exchange(exchanges: &MutableVec<(String, String)>, new_exchange: (String, String), deletions: &Rc<RefCell<Vec<usize>>>, id: usize) {
-    let mut index = id;
-    for deletion in (*(*deletions)).borrow().iter() {
-        if index >= *deletion {
-            index -= 1;
+    // a streamed reply is pushed onto whatever path is current when it ends, so the path
+    // stays put until then; the signal ends with the exchange
+    glib::spawn_future_local(streaming.signal().stop_if(move |_| exchange_ref.upgrade().is_none()).for_each(move |streaming| {
+        for control in controls.iter().filter_map(|control| control.upgrade()) {
+            control.set_sensitive(!streaming);
         }
-    }
+        async {}
+    }));
 
-    exchanges.lock_mut().set_cloned(index, new_exchange);
+    return exchange;
 }
 
-fn delete_exchange(exchanges: &MutableVec<(String, String)>, deletions: &Rc<RefCell<Vec<usize>>>, id: usize) {
-    let mut index = id;
-    for deletion in (*(*deletions)).borrow().iter() {
-        if index >= *deletion {
-            index -= 1;
-        }
+// the request for `messages`, less what the chat's overflow strategy would leave out
+fn show_code(conversation: &Conversation, settings: &Mutable<Settings>, messages: &[Message], error: &Mutable<String>) {
+    let settings = settings.lock_ref().clone();
+    match settings.api_key {
+        Some(api_key) => {
+            let context = preview_context(conversation, &settings, conversation.strategy.get(), messages);
+            let body = build_request_body(&settings, &context, None);
+            CodeWindow(settings.api_keys[api_key].clone(), settings.openai_api, body).present();
+        },
+        None => *error.lock_mut() = "No API key selected.".to_string()
     }
-
-    exchanges.lock_mut().remove(index);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn Exchanges(
-    exchanges: MutableVec<(String, String)>,
+    conversation: Conversation,
     response_tokens: MutableVec<String>,
     streaming: Mutable<bool>,
-    clear_prompt: Rc<Notify>
+    clear_prompt: Rc<Notify>,
+    settings: Mutable<Settings>,
+    error: Mutable<String>,
+    included: Mutable<Vec<bool>>,
+    cancel: Mutable<CancellationToken>,
+    request_log: MutableVec<LogEntry>,
+    session_cost: Mutable<f64>,
+    strategy: Mutable<OverflowStrategy>,
+    toast: Mutable<Option<String>>
 ) -> (gtk::TextBuffer, gtk::Box) {
-    let id_counter = Rc::new(RefCell::new(0usize));
-    let deletions: Rc<RefCell<Vec<usize>>> = Rc::new(RefCell::new(vec![]));
     let exchanges_memo: Rc<RefCell<Vec<ExchangeWidget>>> = Rc::new(RefCell::new(vec![]));
 
     let vbox_exchanges = gtk::Box::new(gtk::Orientation::Vertical, 10);
@@ -267,44 +530,107 @@ fn Exchanges(
     vbox_exchanges.append(&prompt_text_box);
     vbox_exchanges.append(&response_text_box);
 
-    glib::spawn_future_local(exchanges.signal_vec_cloned().for_each({
+    // dim the messages the overflow strategy leaves out of the next request
+    glib::spawn_future_local(included.signal_cloned().for_each({
+        let exchanges_memo = exchanges_memo.clone();
+        move |included| {
+            for (exchange, included) in exchanges_memo.borrow().iter().zip(included) {
+                if included {
+                    exchange.remove_css_class("excluded");
+                } else {
+                    exchange.add_css_class("excluded");
+                }
+            }
+            async {}
+        }
+    }));
+
+    glib::spawn_future_local(conversation.entries.signal_vec_cloned().for_each({
         let vbox_exchanges = vbox_exchanges.clone();
         let prompt_text_box = prompt_text_box.clone();
-        let exchanges = exchanges.clone();
         move |vd| {
             match vd {
                 VecDiff::UpdateAt { index: _, value: _ } => {},
-                VecDiff::Push { value: (user_message, assistant_message) } => {
-                    let id = *(*id_counter).borrow();
-                    *id_counter.borrow_mut() += 1;
-                    let exchange = Exchange(user_message, assistant_message, {
-                        let exchanges = exchanges.clone();
-                        let deletions = deletions.clone();
-                        move |new_exchange| edit_exchange(&exchanges, new_exchange, &deletions, id)
+                // the tree node behind the message, so callbacks survive changes before it
+                VecDiff::Push { value: (id, message) } => {
+                    // any other change would be the one the toast's Undo takes back, so the toast goes
+                    let exchange = Exchange(message, conversation.siblings(id), {
+                        let conversation = conversation.clone();
+                        let toast = toast.clone();
+                        move |offset| {
+                            conversation.switch(id, offset);
+                            toast.set(None);
+                        }
+                    }, {
+                        let conversation = conversation.clone();
+                        let toast = toast.clone();
+                        move |text| {
+                            if conversation.edit(id, &text) {
+                                toast.set(Some("Message edited".to_string()));
+                            }
+                        }
+                    }, {
+                        let conversation = conversation.clone();
+                        let toast = toast.clone();
+                        move || {
+                            conversation.delete(id);
+                            toast.set(Some("Message deleted".to_string()));
+                        }
                     }, {
-                        let exchanges = exchanges.clone();
-                        let deletions = deletions.clone();
-                        move || delete_exchange(&exchanges, &deletions, id)
-                    });
+                        let conversation = conversation.clone();
+                        let settings = settings.clone();
+                        let error = error.clone();
+                        move || {
+                            if let Some(index) = conversation.path().iter().position(|node| *node == id) {
+                                let messages = conversation.messages.lock_ref();
+                                // a reply shows the request that produced it, any other message the request it ends
+                                let end = if messages[index].role == Role::Assistant { index } else { index + 1 };
+                                show_code(&conversation, &settings, &messages[..end], &error);
+                            }
+                        }
+                    }, settings.clone(), clone!(
+                        @strong conversation,
+                        @strong response_tokens,
+                        @strong error,
+                        @strong streaming,
+                        @strong cancel,
+                        @strong request_log,
+                        @strong session_cost,
+                        @strong strategy => move |settings| regenerate_message(
+                            conversation.clone(),
+                            id,
+                            settings,
+                            response_tokens.clone(),
+                            error.clone(),
+                            streaming.clone(),
+                            cancel.clone(),
+                            request_log.clone(),
+                            session_cost.clone(),
+                            strategy.get()
+                        )
+                    ), {
+                        let conversation = conversation.clone();
+                        let toast = toast.clone();
+                        move |rating| {
+                            conversation.rate(id, rating);
+                            toast.set(None);
+                        }
+                    }, streaming.clone());
                     exchange.insert_before(&vbox_exchanges, Some(&prompt_text_box));
                     exchanges_memo.borrow_mut().push(exchange);
                 },
                 VecDiff::RemoveAt { index } => {
                     let child = exchanges_memo.borrow_mut().remove(index);
-                    deletions.borrow_mut().push(index);
                     vbox_exchanges.remove(&child);
                 },
                 VecDiff::Pop {} => {
                     let child = exchanges_memo.borrow_mut().pop().unwrap();
-                    deletions.borrow_mut().push(exchanges_memo.borrow().len());
                     vbox_exchanges.remove(&child);
                 },
                 VecDiff::Clear {} => {
                     for exchange in exchanges_memo.borrow().iter() {
                         vbox_exchanges.remove(exchange);
                     }
-                    *id_counter.borrow_mut() = 0;
-                    deletions.borrow_mut().clear();
                     exchanges_memo.borrow_mut().clear();
                 },
                 _ => panic!("Not supported: {:?}", vd)
@@ -324,6 +650,381 @@ fn SettingsButton(stack: gtk::Stack) -> gtk::Button {
     return button;
 }
 
+fn CodeButton(
+    settings: Mutable<Settings>,
+    conversation: Conversation,
+    prompt: impl Fn() -> String + 'static,
+    error: Mutable<String>
+) -> gtk::Button {
+    let button = gtk::Button::new();
+    button.set_label("Code");
+
+    button.connect_clicked(move |_| {
+        let mut messages = conversation.messages.lock_ref().to_vec();
+        let prompt = prompt();
+        if !prompt.trim().is_empty() {
+            messages.push(Message::text(Role::User, &prompt));
+        }
+        show_code(&conversation, &settings, &messages, &error);
+    });
+    return button;
+}
+
+// adds the prompt to the conversation without sending it, as any role,
+// for turns that weren't generated here
+fn AddMessageButton(
+    conversation: Conversation,
+    prompt_buffer: gtk::TextBuffer,
+    clear_prompt: Rc<Notify>,
+    streaming: Mutable<bool>,
+    toast: Mutable<Option<String>>
+) -> gtk::Box {
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    hbox.add_css_class("linked");
+
+    let role_names: Vec<String> = ROLES.iter().map(|role| role.to_string()).collect();
+    let role_names: Vec<&str> = role_names.iter().map(|name| name.as_str()).collect();
+    let store = gtk::StringList::new(&role_names);
+    let dropdown = gtk::DropDown::new(Some(store), None::<&gtk::Expression>);
+    hbox.append(&dropdown);
+
+    let button = gtk::Button::new();
+    button.set_label("Add");
+    button.connect_clicked(move |_| {
+        let text = get_buffer_content(&prompt_buffer);
+        if text.trim().is_empty() {
+            return;
+        }
+        conversation.push(Message::text(ROLES[dropdown.selected() as usize], &text));
+        clear_prompt.notify_one();
+        toast.set(None);
+    });
+    hbox.append(&button);
+
+    glib::spawn_future_local(streaming.signal().for_each({
+        let hbox = hbox.clone();
+        move |streaming| {
+            hbox.set_visible(!streaming);
+            async {}
+        }
+    }));
+
+    return hbox;
+}
+
+fn InspectorButton(stack: gtk::Stack) -> gtk::Button {
+    let button = gtk::Button::new();
+    button.set_label("Inspector");
+
+    button.connect_clicked(move |_| stack.set_visible_child_name("inspector"));
+    return button;
+}
+
+fn BatchButton(stack: gtk::Stack) -> gtk::Button {
+    let button = gtk::Button::new();
+    button.set_label("Batch");
+
+    button.connect_clicked(move |_| stack.set_visible_child_name("batch"));
+    return button;
+}
+
+fn EmbeddingsButton(stack: gtk::Stack) -> gtk::Button {
+    let button = gtk::Button::new();
+    button.set_label("Embeddings");
+
+    button.connect_clicked(move |_| stack.set_visible_child_name("embeddings"));
+    return button;
+}
+
+fn CostLabel(messages: MutableVec<Message>, session_cost: Mutable<f64>) -> Label {
+    let label = Label::new(None);
+    label.set_css_classes(&["usage-label"]);
+
+    let conversation_cost = messages.signal_vec_cloned()
+        .map(|message| message.metadata.usage.and_then(|usage| usage.cost).unwrap_or(0.0))
+        .to_signal_map(|costs| costs.iter().sum::<f64>());
+
+    glib::spawn_future_local(map_ref! {
+        let conversation_cost = conversation_cost,
+        let session_cost = session_cost.signal() =>
+        (*conversation_cost, *session_cost)
+    }.for_each({
+        let label = label.clone();
+        move |(conversation_cost, session_cost)| {
+            label.set_text(&format!("{} · session {}", format_cost(conversation_cost), format_cost(session_cost)));
+            label.set_visible(session_cost > 0.0);
+            async {}
+        }
+    }));
+
+    return label;
+}
+
+fn ContextMeter(
+    messages: MutableVec<Message>,
+    settings: Mutable<Settings>,
+    strategy: Mutable<OverflowStrategy>,
+    prompt_buffer: gtk::TextBuffer,
+    included: Mutable<Vec<bool>>
+) -> gtk::Box {
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    hbox.set_css_classes(&["context-meter"]);
+
+    let level_bar = gtk::LevelBar::for_interval(0.0, 1.0);
+    level_bar.set_hexpand(true);
+    level_bar.set_valign(gtk::Align::Center);
+    hbox.append(&level_bar);
+
+    let label = Label::new(None);
+    hbox.append(&label);
+
+    let prompt = Mutable::new(get_buffer_content(&prompt_buffer));
+    prompt_buffer.connect_changed(clone!(@strong prompt => move |buffer| {
+        prompt.set(get_buffer_content(buffer));
+    }));
+
+    // the history is only re-tokenized when it or the model changes, not on every keystroke
+    let message_tokens = map_ref! {
+        let model = settings.signal_ref(|settings| settings.model.clone()).dedupe_cloned(),
+        let messages = messages.signal_vec_cloned().to_signal_cloned() =>
+        count_message_tokens(model, messages)
+    };
+
+    let prompt_tokens = map_ref! {
+        let model = settings.signal_ref(|settings| settings.model.clone()).dedupe_cloned(),
+        let prompt = prompt.signal_cloned() =>
+        count_prompt_tokens(model, prompt)
+    };
+
+    glib::spawn_future_local(map_ref! {
+        let settings = settings.signal_cloned(),
+        let strategy = strategy.signal(),
+        let message_tokens = message_tokens,
+        let prompt_tokens = prompt_tokens =>
+        plan_context(settings, strategy, message_tokens, *prompt_tokens)
+    }.for_each(move |(estimate, selection)| {
+        level_bar.set_value(estimate.fraction().min(1.0));
+        level_bar.set_visible(estimate.context_window.is_some());
+        label.set_text(&estimate.describe());
+        hbox.remove_css_class("tight");
+        hbox.remove_css_class("overflow");
+        match estimate.fit() {
+            ContextFit::Fits => (),
+            ContextFit::Tight => hbox.add_css_class("tight"),
+            ContextFit::Overflow => hbox.add_css_class("overflow")
+        }
+        included.set_neq(selection);
+        async {}
+    }));
+
+    return hbox;
+}
+
+const STRATEGIES: [OverflowStrategy; 4] = [
+    OverflowStrategy::Error,
+    OverflowStrategy::DropOldest,
+    OverflowStrategy::KeepFirstLast { first: 1, last: 4 },
+    OverflowStrategy::Summarize
+];
+
+fn OverflowMenu(strategy: Mutable<OverflowStrategy>) -> gtk::MenuButton {
+    let menu_button = gtk::MenuButton::new();
+    menu_button.set_tooltip_text(Some("What to do when the conversation overflows the context window"));
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    vbox.set_css_classes(&["settings-box"]);
+
+    let strategy_names: Vec<String> = STRATEGIES.iter().map(|strategy| strategy.to_string()).collect();
+    let strategy_names: Vec<&str> = strategy_names.iter().map(|name| name.as_str()).collect();
+    let store = gtk::StringList::new(&strategy_names);
+    let dropdown = gtk::DropDown::new(Some(store), None::<&gtk::Expression>);
+    vbox.append(&dropdown);
+
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(10);
+    grid.set_column_spacing(10);
+    let label = Label::new(Some("Keep first:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 0, 1, 1);
+    let first_entry = gtk::SpinButton::with_range(0.0, 100.0, 1.0);
+    grid.attach(&first_entry, 1, 0, 1, 1);
+    let label = Label::new(Some("Keep last:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 1, 1, 1);
+    let last_entry = gtk::SpinButton::with_range(0.0, 100.0, 1.0);
+    grid.attach(&last_entry, 1, 1, 1, 1);
+    vbox.append(&grid);
+
+    let popover = gtk::Popover::new();
+    popover.set_child(Some(&vbox));
+    menu_button.set_popover(Some(&popover));
+
+    // set while the widgets are brought in line with a strategy loaded with a chat
+    let syncing = Rc::new(Cell::new(false));
+    let update_strategy = clone!(
+        @strong strategy,
+        @strong syncing,
+        @strong dropdown,
+        @strong first_entry,
+        @strong last_entry => move || {
+            if syncing.get() {
+                return;
+            }
+            let new_strategy = match STRATEGIES[dropdown.selected() as usize] {
+                OverflowStrategy::KeepFirstLast { .. } => OverflowStrategy::KeepFirstLast {
+                    first: first_entry.value() as usize,
+                    last: last_entry.value() as usize
+                },
+                new_strategy => new_strategy
+            };
+            strategy.set_neq(new_strategy);
+        }
+    );
+    let update_strategy = Rc::new(update_strategy);
+
+    if let OverflowStrategy::KeepFirstLast { first, last } = STRATEGIES[2] {
+        first_entry.set_value(first as f64);
+        last_entry.set_value(last as f64);
+    }
+    dropdown.connect_notify(Some("selected"), clone!(@strong update_strategy => move |_, _| update_strategy()));
+    first_entry.connect_value_changed(clone!(@strong update_strategy => move |_| update_strategy()));
+    last_entry.connect_value_changed(clone!(@strong update_strategy => move |_| update_strategy()));
+
+    // each chat has its own strategy, so the widgets follow it as chats are opened
+    glib::spawn_future_local(strategy.signal().for_each({
+        let menu_button = menu_button.clone();
+        move |strategy| {
+            syncing.set(true);
+            if let OverflowStrategy::KeepFirstLast { first, last } = strategy {
+                first_entry.set_value(first as f64);
+                last_entry.set_value(last as f64);
+            }
+            let position = STRATEGIES
+                .iter()
+                .position(|option| option.to_string() == strategy.to_string())
+                .unwrap();
+            dropdown.set_selected(position as u32);
+            syncing.set(false);
+
+            menu_button.set_label(&format!("Overflow: {}", strategy.to_string()));
+            grid.set_visible(matches!(strategy, OverflowStrategy::KeepFirstLast { .. }));
+            async {}
+        }
+    }));
+
+    return menu_button;
+}
+
+// waits for the exchanges to be built and laid out, then brings the one at `index` to the top
+fn scroll_to_exchange(scrolled_window: &gtk::ScrolledWindow, vbox_exchanges: &gtk::Box, index: usize) {
+    let scrolled_window = scrolled_window.clone();
+    let vbox_exchanges = vbox_exchanges.clone();
+    glib::spawn_future_local(async move {
+        glib::timeout_future(Duration::from_millis(100)).await;
+        let mut exchange = vbox_exchanges.first_child();
+        for _ in 0..index {
+            exchange = exchange.and_then(|exchange| exchange.next_sibling());
+        }
+        let Some(exchange) = exchange else {
+            return;
+        };
+        if let Some(point) = exchange.compute_point(&vbox_exchanges, &gtk::graphene::Point::new(0.0, 0.0)) {
+            scrolled_window.vadjustment().set_value(point.y() as f64);
+        }
+        exchange.add_css_class("search-match");
+        glib::timeout_future_seconds(2).await;
+        exchange.remove_css_class("search-match");
+    });
+}
+
+fn HistoryButton(sidebar: gtk::Box) -> gtk::ToggleButton {
+    let button = gtk::ToggleButton::with_label("Chats");
+    button.set_active(sidebar.is_visible());
+    button.connect_toggled(move |button| sidebar.set_visible(button.is_active()));
+
+    return button;
+}
+
+// how long the undo toast stays up
+const TOAST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// says what just changed and offers to put it back
+fn UndoToast(toast: Mutable<Option<String>>, conversation: Conversation, streaming: Mutable<bool>) -> gtk::Revealer {
+    let revealer = gtk::Revealer::new();
+    revealer.set_transition_type(gtk::RevealerTransitionType::SlideUp);
+    revealer.set_halign(gtk::Align::Center);
+    revealer.set_valign(gtk::Align::End);
+
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    hbox.set_css_classes(&["toast"]);
+    let label = Label::new(None);
+    hbox.append(&label);
+    let button = gtk::Button::with_label("Undo");
+    button.set_css_classes(&["flat"]);
+    hbox.append(&button);
+    revealer.set_child(Some(&hbox));
+
+    button.connect_clicked(clone!(@strong toast => move |_| {
+        conversation.undo();
+        toast.set(None);
+    }));
+
+    // the reply being streamed belongs to the current tree
+    glib::spawn_future_local(streaming.signal().for_each(clone!(@strong toast => move |streaming| {
+        if streaming {
+            toast.set(None);
+        }
+        async {}
+    })));
+
+    // a newer toast restarts the timer, so only the latest one may hide it
+    let generation = Rc::new(Cell::new(0u64));
+    glib::spawn_future_local(toast.signal_cloned().for_each({
+        let revealer = revealer.clone();
+        move |text| {
+            generation.set(generation.get() + 1);
+            revealer.set_reveal_child(text.is_some());
+            if let Some(text) = text {
+                label.set_text(&text);
+                let current = generation.get();
+                let generation = generation.clone();
+                let toast = toast.clone();
+                glib::spawn_future_local(async move {
+                    glib::timeout_future(TOAST_TIMEOUT).await;
+                    if generation.get() == current {
+                        toast.set(None);
+                    }
+                });
+            }
+            async {}
+        }
+    }));
+
+    return revealer;
+}
+
+// Ctrl+Z and Ctrl+Shift+Z for the conversation; they bubble so text being typed keeps its own undo
+fn UndoShortcuts(conversation: Conversation, streaming: Mutable<bool>, toast: Mutable<Option<String>>) -> gtk::ShortcutController {
+    let controller = gtk::ShortcutController::new();
+    controller.set_propagation_phase(gtk::PropagationPhase::Bubble);
+    let shortcuts: [(&str, fn(&Conversation) -> bool); 2] = [
+        ("<Control>z", Conversation::undo),
+        ("<Control><Shift>z", Conversation::redo)
+    ];
+    for (trigger, step) in shortcuts {
+        let action = gtk::CallbackAction::new(clone!(@strong conversation, @strong streaming, @strong toast => move |_, _| {
+            if streaming.get() || !step(&conversation) {
+                return glib::Propagation::Proceed;
+            }
+            toast.set(None);
+            return glib::Propagation::Stop;
+        }));
+        controller.add_shortcut(gtk::Shortcut::new(gtk::ShortcutTrigger::parse_string(trigger), Some(action)));
+    }
+
+    return controller;
+}
+
 fn ErrorLabel(error: Mutable<String>) -> Label {
     let label = Label::new(Some(""));
     label.set_css_classes(&["error-label"]);
@@ -340,18 +1041,104 @@ fn ErrorLabel(error: Mutable<String>) -> Label {
     return label;
 }
 
-pub fn Chat(stack: gtk::Stack, settings: Mutable<Settings>) -> impl IsA<gtk::Widget> {
-    let exchanges: MutableVec<(String, String)> = MutableVec::new();
+pub fn Chat(
+    stack: gtk::Stack,
+    settings: Mutable<Settings>,
+    request_log: MutableVec<LogEntry>
+) -> impl IsA<gtk::Widget> {
+    let conversation = Conversation::default();
+    let messages = conversation.messages.clone();
     let response_tokens = MutableVec::new();
     let streaming = Mutable::new(false);
+    let cancel = Mutable::new(CancellationToken::new());
     let error = Mutable::new(String::new());
     let clear_prompt = Rc::new(Notify::new());
+    let session_cost = Mutable::new(0.0);
+    let strategy = conversation.strategy.clone();
+    let included = Mutable::new(vec![]);
+    // the last response id and how many messages it covers
+    let previous_response: Mutable<Option<(String, usize)>> = Mutable::new(None);
+    let history = Rc::new(History::open().expect("Could not open the chat history."));
+    let chat_id = conversation.saved_id.clone();
+    // what the last undoable change did, shown with an Undo button
+    let toast: Mutable<Option<String>> = Mutable::new(None);
+    let chats = Mutable::new(history.list().unwrap_or_default());
+
+    // a response id stands for the conversation as the server saw it, so anything but
+    // appending a message breaks the chain; in-place updates only touch ratings
+    glib::spawn_future_local(messages.signal_vec_cloned().for_each(clone!(@strong previous_response => move |vd| {
+        if !matches!(vd, VecDiff::Push { .. } | VecDiff::UpdateAt { .. }) {
+            previous_response.set(None);
+        }
+        async {}
+    })));
+
+    // every change is written through, so closing the window loses nothing
+    let save_chat = Rc::new(clone!(
+        @strong conversation,
+        @strong history,
+        @strong chat_id,
+        @strong chats,
+        @strong settings,
+        @strong error => move || {
+            let messages = conversation.messages.lock_ref().to_vec();
+            if !messages.is_empty() {
+                let tree = conversation.tree();
+                let snapshot = SettingsSnapshot { overflow_strategy: conversation.strategy.get(), ..SettingsSnapshot::of(&settings.lock_ref()) };
+                // a chat deleted from the sidebar and brought back with undo is saved anew
+                let saved = match chat_id.get() {
+                    Some(id) => history.save(id, &tree, &snapshot),
+                    None => Ok(None)
+                };
+                let changed = saved.and_then(|changed| match changed {
+                    Some(changed) => Ok(changed),
+                    None => history.create(&default_title(&messages), &tree, &snapshot).map(|id| {
+                        chat_id.set(Some(id));
+                        true
+                    })
+                });
+                // the sidebar is only rebuilt when a row actually changed
+                match changed.and_then(|changed| changed.then(|| history.list()).transpose()) {
+                    Ok(Some(list)) => chats.set(list),
+                    Ok(None) => (),
+                    Err(err) => error.set(format!("Couldn't save the chat: {}", err))
+                }
+            }
+        }
+    ));
+    // loading or undoing sends a diff per message, all handled in one go, so the save
+    // is queued behind them and runs once; at the same priority, so nothing gets in between
+    let save_pending = Rc::new(Cell::new(false));
+    let schedule_save = Rc::new(clone!(@strong save_chat, @strong save_pending => move || {
+        if !save_pending.replace(true) {
+            glib::spawn_future_local(clone!(@strong save_chat, @strong save_pending => async move {
+                save_pending.set(false);
+                save_chat();
+            }));
+        }
+    }));
+    glib::spawn_future_local(messages.signal_vec_cloned().for_each(clone!(@strong schedule_save => move |_| {
+        schedule_save();
+        async {}
+    })));
+    glib::spawn_future_local(strategy.signal().for_each(clone!(@strong schedule_save => move |_| {
+        schedule_save();
+        async {}
+    })));
 
     let (prompt_buffer, vbox_exchanges) = Exchanges(
-        exchanges.clone(),
+        conversation.clone(),
         response_tokens.clone(),
         streaming.clone(),
-        clear_prompt.clone()
+        clear_prompt.clone(),
+        settings.clone(),
+        error.clone(),
+        included.clone(),
+        cancel.clone(),
+        request_log.clone(),
+        session_cost.clone(),
+        strategy.clone(),
+        toast.clone()
     );
 
     let scrolled_window = gtk::ScrolledWindow::new();
@@ -359,32 +1146,123 @@ pub fn Chat(stack: gtk::Stack, settings: Mutable<Settings>) -> impl IsA<gtk::Wid
     scrolled_window.set_child(Some(&vbox_exchanges));
     scrolled_window.set_vexpand(true);
 
+    let overlay = gtk::Overlay::new();
+    overlay.set_child(Some(&scrolled_window));
+    overlay.add_overlay(&UndoToast(toast.clone(), conversation.clone(), streaming.clone()));
+
     let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
 
-    hbox.append(&NewButton(exchanges.clone(), streaming.clone(), clear_prompt.clone()));
+    // None starts a new chat; a node brings that message into view
+    let open_chat: Rc<dyn Fn(Option<i64>, Option<usize>)> = Rc::new(clone!(
+        @strong conversation,
+        @strong history,
+        @strong settings,
+        @strong request_log,
+        @strong clear_prompt,
+        @strong error,
+        @strong toast,
+        @weak scrolled_window,
+        @weak vbox_exchanges => move |id, node| {
+            let chat = match id.map(|id| history.load(id)).transpose() {
+                Ok(chat) => chat,
+                Err(err) => {
+                    error.set(format!("Couldn't open the chat: {}", err));
+                    return;
+                }
+            };
+            match chat {
+                Some((mut tree, snapshot)) => {
+                    snapshot.restore(&mut settings.lock_mut());
+                    // picked before loading so the chat opens on that branch in a single change
+                    if let Some(node) = node {
+                        tree.select(node);
+                    }
+                    conversation.load(tree, id, snapshot.overflow_strategy);
+                    if let Some(node) = node {
+                        if let Some(index) = conversation.path().iter().position(|id| *id == node) {
+                            scroll_to_exchange(&scrolled_window, &vbox_exchanges, index);
+                        }
+                    }
+                }
+                None => conversation.load(ConversationTree::default(), None, OverflowStrategy::default())
+            }
+            toast.set(None);
+            request_log.lock_mut().clear();
+            clear_prompt.notify_one();
+        }
+    ));
+    let sidebar = HistorySidebar(history.clone(), chats.clone(), conversation.clone(), streaming.clone(), error.clone(), open_chat.clone());
+
+    hbox.append(&HistoryButton(sidebar.clone()));
+
+    hbox.append(&NewButton(conversation.clone(), streaming.clone(), clear_prompt.clone(), request_log.clone(), toast.clone()));
+
+    hbox.append(&AddMessageButton(conversation.clone(), prompt_buffer.clone(), clear_prompt.clone(), streaming.clone(), toast.clone()));
 
     hbox.append(&SubmitButton(
-        exchanges,
-        move || get_buffer_content(&prompt_buffer),
-        settings,
+        conversation.clone(),
+        {
+            let prompt_buffer = prompt_buffer.clone();
+            move || get_buffer_content(&prompt_buffer)
+        },
+        settings.clone(),
         clear_prompt,
         response_tokens,
         error.clone(),
-        streaming.clone()
+        streaming.clone(),
+        cancel.clone(),
+        request_log,
+        session_cost.clone(),
+        strategy.clone(),
+        previous_response
     ));
 
     hbox.append(&DummyLabel(gtk::Orientation::Horizontal));
 
-    let cancel_button = CancelButton(streaming.clone());
+    hbox.append(&CostLabel(messages.clone(), session_cost));
+
+    let cancel_button = CancelButton(streaming.clone(), cancel);
     hbox.append(&cancel_button);
 
+    hbox.append(&OverflowMenu(strategy.clone()));
+
+    hbox.append(&CodeButton(
+        settings.clone(),
+        conversation.clone(),
+        {
+            let prompt_buffer = prompt_buffer.clone();
+            move || get_buffer_content(&prompt_buffer)
+        },
+        error.clone()
+    ));
+
+    hbox.append(&ImportButton(history.clone(), settings.clone(), chats.clone(), streaming.clone(), open_chat));
+
+    hbox.append(&ExportButton(messages.clone(), settings.clone(), history, chats, chat_id, error.clone()));
+
+    hbox.append(&InspectorButton(stack.clone()));
+
+    hbox.append(&BatchButton(stack.clone()));
+
+    hbox.append(&EmbeddingsButton(stack.clone()));
+
     hbox.append(&SettingsButton(stack));
 
     let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
-    vbox.set_css_classes(&["top-level-box"]);
+    vbox.set_hexpand(true);
+    let find_bar = FindBar(scrolled_window.clone(), vbox_exchanges, messages.clone());
     vbox.append(&ErrorLabel(error));
-    vbox.append(&scrolled_window);
+    vbox.append(&find_bar);
+    vbox.append(&overlay);
+    vbox.append(&ContextMeter(messages, settings, strategy, prompt_buffer, included));
     vbox.append(&hbox);
-    
-    return vbox;
+
+    let top_level_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    top_level_box.set_css_classes(&["top-level-box"]);
+    top_level_box.append(&sidebar);
+    top_level_box.append(&vbox);
+    top_level_box.add_controller(FindShortcut(&find_bar));
+    top_level_box.add_controller(UndoShortcuts(conversation, streaming, toast));
+
+    return top_level_box;
 }
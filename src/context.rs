@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{settings::Settings, tokens::{context_window, estimate_tokens, TokenEstimate}};
+
+// room set aside for the summary that replaces dropped messages
+pub const SUMMARY_TOKENS: u32 = 512;
+
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub enum OverflowStrategy {
+    #[default]
+    Error,
+    DropOldest,
+    KeepFirstLast { first: usize, last: usize },
+    Summarize
+}
+
+impl ToString for OverflowStrategy {
+    fn to_string(&self) -> String {
+        match *self {
+            OverflowStrategy::Error => "Error".to_string(),
+            OverflowStrategy::DropOldest => "Drop oldest".to_string(),
+            OverflowStrategy::KeepFirstLast { .. } => "Keep first and last".to_string(),
+            OverflowStrategy::Summarize => "Summarize".to_string()
+        }
+    }
+}
+
+// returns which messages are sent; all of them unless the request overflows the budget
+pub fn select_context(
+    strategy: &OverflowStrategy,
+    message_tokens: &[usize],
+    prompt_tokens: usize,
+    budget: Option<usize>
+) -> Vec<bool> {
+    let mut included = vec![true; message_tokens.len()];
+    let budget = match budget {
+        Some(budget) => budget,
+        None => return included
+    };
+
+    let total = |included: &[bool]| -> usize {
+        let history_tokens: usize = message_tokens
+            .iter()
+            .zip(included)
+            .filter(|(_, included)| **included)
+            .map(|(tokens, _)| tokens)
+            .sum();
+        history_tokens + prompt_tokens
+    };
+
+    if total(&included) <= budget {
+        return included;
+    }
+
+    match *strategy {
+        OverflowStrategy::Error => (),
+        OverflowStrategy::DropOldest | OverflowStrategy::Summarize => {
+            let budget = match strategy {
+                OverflowStrategy::Summarize => budget.saturating_sub(SUMMARY_TOKENS as usize),
+                _ => budget
+            };
+            for index in 0..included.len() {
+                if total(&included) <= budget {
+                    break;
+                }
+                included[index] = false;
+            }
+        },
+        OverflowStrategy::KeepFirstLast { first, last } => {
+            let len = included.len();
+            for (index, included) in included.iter_mut().enumerate() {
+                *included = index < first || index + last >= len;
+            }
+        }
+    }
+
+    return included;
+}
+
+pub fn plan_context(
+    settings: &Settings,
+    strategy: &OverflowStrategy,
+    message_tokens: &[usize],
+    prompt_tokens: usize
+) -> (TokenEstimate, Vec<bool>) {
+    let budget = context_window(&settings.model)
+        .map(|context_window| (context_window as usize).saturating_sub(settings.max_tokens as usize));
+    let included = select_context(strategy, message_tokens, prompt_tokens, budget);
+
+    let mut history_tokens: usize = message_tokens
+        .iter()
+        .zip(&included)
+        .filter(|(_, included)| **included)
+        .map(|(tokens, _)| tokens)
+        .sum();
+    if *strategy == OverflowStrategy::Summarize && included.contains(&false) {
+        history_tokens += SUMMARY_TOKENS as usize;
+    }
+
+    let estimate = estimate_tokens(&settings.model, settings.max_tokens, history_tokens, prompt_tokens);
+    return (estimate, included);
+}
@@ -0,0 +1,345 @@
+use std::{cell::RefCell, rc::Rc};
+
+use futures_signals::{signal::Mutable, signal_vec::MutableVec};
+use serde::{Deserialize, Serialize};
+
+use crate::{context::OverflowStrategy, message::{Message, Rating, Role}};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Node {
+    // None for the root
+    message: Option<Message>,
+    parent: usize,
+    children: Vec<usize>,
+    // the child shown when walking the active path
+    selected: usize
+}
+
+// editing or regenerating a message adds a sibling instead of overwriting it,
+// so every earlier attempt stays one arrow click away
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConversationTree {
+    // nodes[0] is an empty root whose children are the alternative first messages
+    nodes: Vec<Node>
+}
+
+impl Default for ConversationTree {
+    fn default() -> ConversationTree {
+        let root = Node { message: None, parent: 0, children: vec![], selected: 0 };
+        return ConversationTree { nodes: vec![root] };
+    }
+}
+
+impl ConversationTree {
+    pub fn message(&self, id: usize) -> &Message {
+        return self.nodes[id].message.as_ref().expect("The root has no message.");
+    }
+
+    // follows the selected child of each node below `id`
+    fn selected_descendants(&self, id: usize) -> Vec<usize> {
+        let mut descendants = vec![];
+        let mut node = &self.nodes[id];
+        while let Some(&child) = node.children.get(node.selected) {
+            descendants.push(child);
+            node = &self.nodes[child];
+        }
+
+        return descendants;
+    }
+
+    // node ids of the messages on the active path, oldest first
+    pub fn path(&self) -> Vec<usize> {
+        return self.selected_descendants(0);
+    }
+
+    pub fn active_messages(&self) -> Vec<Message> {
+        return self.path().iter().map(|id| self.message(*id).clone()).collect();
+    }
+
+    // every message still reachable from the root, on any branch
+    pub fn messages(&self) -> Vec<(usize, &Message)> {
+        let mut messages = vec![];
+        let mut stack = self.nodes[0].children.clone();
+        while let Some(id) = stack.pop() {
+            messages.push((id, self.message(id)));
+            stack.extend(self.nodes[id].children.iter().rev());
+        }
+
+        return messages;
+    }
+
+    // selects the branches leading to `id` so that it's on the active path
+    pub fn select(&mut self, id: usize) {
+        let mut child = id;
+        while child != 0 {
+            let (position, _) = self.siblings(child);
+            let parent = self.nodes[child].parent;
+            self.nodes[parent].selected = position;
+            child = parent;
+        }
+    }
+
+    fn add_node(&mut self, parent: usize, message: Message) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(Node { message: Some(message), parent, children: vec![], selected: 0 });
+        let parent = &mut self.nodes[parent];
+        parent.children.push(id);
+        parent.selected = parent.children.len() - 1;
+
+        return id;
+    }
+
+    pub fn push(&mut self, message: Message) {
+        let parent = self.path().last().copied().unwrap_or(0);
+        self.add_node(parent, message);
+    }
+
+    // adds an alternative to `id` and selects it; keep_continuation copies the messages
+    // that followed `id` onto the new branch, otherwise the branch ends there
+    pub fn branch(&mut self, id: usize, message: Message, keep_continuation: bool) {
+        let continuation = if keep_continuation { self.selected_descendants(id) } else { vec![] };
+        let mut parent = self.add_node(self.nodes[id].parent, message);
+        for descendant in continuation {
+            let message = self.message(descendant).clone();
+            parent = self.add_node(parent, message);
+        }
+    }
+
+    // zero-based position of `id` among its siblings, and the number of siblings
+    pub fn siblings(&self, id: usize) -> (usize, usize) {
+        let siblings = &self.nodes[self.nodes[id].parent].children;
+        let position = siblings.iter().position(|sibling| *sibling == id).unwrap_or(0);
+        return (position, siblings.len());
+    }
+
+    pub fn switch(&mut self, id: usize, offset: isize) {
+        let (position, count) = self.siblings(id);
+        let position = position as isize + offset;
+        if position >= 0 && (position as usize) < count {
+            let parent = self.nodes[id].parent;
+            self.nodes[parent].selected = position as usize;
+        }
+    }
+
+    pub fn rate(&mut self, id: usize, rating: Option<Rating>) -> &Message {
+        let message = self.nodes[id].message.as_mut().expect("The root has no message.");
+        message.metadata.rating = rating;
+        return message;
+    }
+
+    // removes a single message; its children take its place so later messages are kept
+    pub fn delete(&mut self, id: usize) {
+        let parent = self.nodes[id].parent;
+        let (position, _) = self.siblings(id);
+        let children = std::mem::take(&mut self.nodes[id].children);
+        let selected = self.nodes[id].selected;
+        for child in &children {
+            self.nodes[*child].parent = parent;
+        }
+
+        let has_children = !children.is_empty();
+        let parent = &mut self.nodes[parent];
+        parent.children.splice(position..=position, children);
+        parent.selected = if has_children {
+            position + selected
+        } else {
+            position.min(parent.children.len().saturating_sub(1))
+        };
+    }
+}
+
+// older snapshots are dropped past this many, since each holds a whole tree
+const UNDO_LIMIT: usize = 100;
+
+// what an undo puts back; the saved chat and its strategy go with the tree so that undoing New reopens it
+struct Snapshot {
+    tree: ConversationTree,
+    saved_id: Option<i64>,
+    strategy: OverflowStrategy
+}
+
+// the tree plus a flat copy of its active path, which the rest of the chat reads and renders
+#[derive(Clone, Default)]
+pub struct Conversation {
+    tree: Rc<RefCell<ConversationTree>>,
+    // node ids of the entries in `messages`
+    path: Rc<RefCell<Vec<usize>>>,
+    pub messages: MutableVec<Message>,
+    // `messages` with each one's node id, changed in the same step; views that act on the node
+    // take the id from the diff, since by the time they run the path may have moved on
+    pub entries: MutableVec<(usize, Message)>,
+    // the chat in the history database, None until a new chat's first message is saved
+    pub saved_id: Mutable<Option<i64>>,
+    // what to do when the chat outgrows the context window; saved with the chat
+    pub strategy: Mutable<OverflowStrategy>,
+    // the summary sent in place of the oldest messages once they stop fitting, with the messages it covers
+    pub summary: Rc<RefCell<Option<(Vec<Message>, String)>>>,
+    // taken before every change to the tree, so undoing one never throws away a later one
+    undo_stack: Rc<RefCell<Vec<Snapshot>>>,
+    redo_stack: Rc<RefCell<Vec<Snapshot>>>
+}
+
+impl Conversation {
+    pub fn path(&self) -> Vec<usize> {
+        return self.path.borrow().clone();
+    }
+
+    pub fn siblings(&self, id: usize) -> (usize, usize) {
+        return self.tree.borrow().siblings(id);
+    }
+
+    // the messages before `id` on the active path
+    pub fn history(&self, id: usize) -> Vec<Message> {
+        let tree = self.tree.borrow();
+        let path = self.path.borrow();
+        let index = path.iter().position(|node| *node == id).unwrap_or(path.len());
+
+        return path[..index].iter().map(|node| tree.message(*node).clone()).collect();
+    }
+
+    // applies a change to the tree, then pops and pushes `messages` from where the paths diverge
+    fn update(&self, change: impl FnOnce(&mut ConversationTree)) {
+        let mut tree = self.tree.borrow_mut();
+        change(&mut tree);
+
+        let new_path = tree.path();
+        let mut path = self.path.borrow_mut();
+        let common = path.iter().zip(&new_path).take_while(|(old, new)| old == new).count();
+        let mut messages = self.messages.lock_mut();
+        let mut entries = self.entries.lock_mut();
+        while messages.len() > common {
+            messages.pop();
+            entries.pop();
+        }
+        for id in &new_path[common..] {
+            messages.push_cloned(tree.message(*id).clone());
+            entries.push_cloned((*id, tree.message(*id).clone()));
+        }
+        *path = new_path;
+    }
+
+    pub fn push(&self, message: Message) {
+        self.checkpoint();
+        self.update(|tree| tree.push(message));
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        return Snapshot { tree: self.tree(), saved_id: self.saved_id.get(), strategy: self.strategy.get() };
+    }
+
+    fn checkpoint(&self) {
+        let mut undo_stack = self.undo_stack.borrow_mut();
+        undo_stack.push(self.snapshot());
+        if undo_stack.len() > UNDO_LIMIT {
+            undo_stack.remove(0);
+        }
+        self.redo_stack.borrow_mut().clear();
+    }
+
+    // node ids of different trees can't be compared, so the path is rebuilt from empty
+    fn restore(&self, snapshot: Snapshot) {
+        self.saved_id.set(snapshot.saved_id);
+        self.strategy.set_neq(snapshot.strategy);
+        *self.tree.borrow_mut() = ConversationTree::default();
+        self.path.borrow_mut().clear();
+        self.messages.lock_mut().clear();
+        self.entries.lock_mut().clear();
+        self.update(|tree| *tree = snapshot.tree);
+    }
+
+    // false if there was nothing to undo
+    pub fn undo(&self) -> bool {
+        let Some(snapshot) = self.undo_stack.borrow_mut().pop() else {
+            return false;
+        };
+        self.redo_stack.borrow_mut().push(self.snapshot());
+        self.restore(snapshot);
+        return true;
+    }
+
+    pub fn redo(&self) -> bool {
+        let Some(snapshot) = self.redo_stack.borrow_mut().pop() else {
+            return false;
+        };
+        self.undo_stack.borrow_mut().push(self.snapshot());
+        self.restore(snapshot);
+        return true;
+    }
+
+    // a changed prompt makes the replies after it moot, while a fixed-up reply keeps them;
+    // false if the text was left as it was
+    pub fn edit(&self, id: usize, text: &str) -> bool {
+        let message = self.tree.borrow().message(id).clone();
+        if message.text_content() == text {
+            return false;
+        }
+        self.checkpoint();
+        let keep_continuation = message.role == Role::Assistant;
+        self.update(|tree| tree.branch(id, message.with_text(text), keep_continuation));
+        return true;
+    }
+
+    // the old reply stays behind as a sibling; the messages after it answered the old reply, so they stay with it
+    pub fn regenerate(&self, id: usize, message: Message) {
+        self.checkpoint();
+        self.update(|tree| tree.branch(id, message, false));
+    }
+
+    // a rating leaves the text alone, so it changes the message in place instead of branching
+    pub fn rate(&self, id: usize, rating: Option<Rating>) {
+        self.checkpoint();
+        let message = self.tree.borrow_mut().rate(id, rating).clone();
+        if let Some(index) = self.path.borrow().iter().position(|node| *node == id) {
+            self.messages.lock_mut().set_cloned(index, message.clone());
+            self.entries.lock_mut().set_cloned(index, (id, message));
+        }
+    }
+
+    pub fn switch(&self, id: usize, offset: isize) {
+        let (position, count) = self.siblings(id);
+        let position = position as isize + offset;
+        if position < 0 || position as usize >= count {
+            return;
+        }
+        self.checkpoint();
+        self.update(|tree| tree.switch(id, offset));
+    }
+
+    pub fn delete(&self, id: usize) {
+        self.checkpoint();
+        self.update(|tree| tree.delete(id));
+    }
+
+    pub fn tree(&self) -> ConversationTree {
+        return self.tree.borrow().clone();
+    }
+
+    // swaps in another chat; undoing past that point would mix the two, so the stacks start over
+    pub fn load(&self, tree: ConversationTree, saved_id: Option<i64>, strategy: OverflowStrategy) {
+        self.undo_stack.borrow_mut().clear();
+        self.redo_stack.borrow_mut().clear();
+        self.restore(Snapshot { tree, saved_id, strategy });
+    }
+
+    // a chat deleted from the history can't be saved to again, so undoing back to it saves a new one
+    pub fn forget_saved(&self, id: i64) {
+        let mut undo_stack = self.undo_stack.borrow_mut();
+        let mut redo_stack = self.redo_stack.borrow_mut();
+        for snapshot in undo_stack.iter_mut().chain(redo_stack.iter_mut()) {
+            if snapshot.saved_id == Some(id) {
+                snapshot.saved_id = None;
+            }
+        }
+    }
+
+    // starts a new chat; the old one is already saved and can be brought back with undo
+    pub fn clear(&self) {
+        if !self.path.borrow().is_empty() {
+            self.checkpoint();
+        }
+        self.restore(Snapshot { tree: ConversationTree::default(), saved_id: None, strategy: OverflowStrategy::default() });
+    }
+}
+
+#[cfg(test)]
+mod tests;
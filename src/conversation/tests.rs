@@ -0,0 +1,208 @@
+// Drives Conversation the way the chat does and replays the diffs it sends, which is
+// all a view sees; several changes often land before the view gets to run.
+
+use std::pin::Pin;
+
+use futures::{FutureExt, Stream, StreamExt};
+use futures_signals::signal_vec::{SignalVecExt, VecDiff};
+
+use super::{Conversation, ConversationTree};
+use crate::{context::OverflowStrategy, message::{Message, Rating, Role}};
+
+fn user(text: &str) -> Message {
+    return Message::text(Role::User, text);
+}
+
+fn assistant(text: &str) -> Message {
+    return Message::text(Role::Assistant, text);
+}
+
+fn texts(conversation: &Conversation) -> Vec<String> {
+    return conversation.messages.lock_ref().iter().map(|message| message.text_content()).collect();
+}
+
+// a four message chat whose first prompt was edited into a branch of its own;
+// the long branch is active, and the node starting the short one is returned with it
+fn long_and_short() -> (ConversationTree, usize) {
+    let mut tree = ConversationTree::default();
+    tree.push(user("Question"));
+    tree.push(assistant("Answer"));
+    tree.push(user("Follow-up"));
+    tree.push(assistant("More"));
+    let first = tree.path()[0];
+    tree.branch(first, user("Other question"), false);
+    let short = tree.path()[0];
+    tree.switch(short, -1);
+
+    return (tree, short);
+}
+
+// what a view holds after applying every diff sent so far
+struct Replay {
+    diffs: Pin<Box<dyn Stream<Item = VecDiff<(usize, Message)>>>>,
+    entries: Vec<(usize, Message)>
+}
+
+impl Replay {
+    fn catch_up(&mut self) -> Vec<usize> {
+        while let Some(Some(diff)) = self.diffs.next().now_or_never() {
+            diff.apply_to_vec(&mut self.entries);
+        }
+        return self.entries.iter().map(|(id, _)| *id).collect();
+    }
+}
+
+fn replay(conversation: &Conversation) -> Replay {
+    let mut replay = Replay { diffs: Box::pin(conversation.entries.signal_vec_cloned().to_stream()), entries: vec![] };
+    replay.catch_up();
+    return replay;
+}
+
+#[test]
+fn select_before_load() {
+    let (mut tree, short) = long_and_short();
+    tree.select(short);
+    let conversation = Conversation::default();
+    let mut view = replay(&conversation);
+
+    conversation.load(tree, Some(1), OverflowStrategy::default());
+    assert_eq!(view.catch_up(), vec![short]);
+    assert_eq!(texts(&conversation), vec!["Other question"]);
+    assert_eq!(conversation.saved_id.get(), Some(1));
+}
+
+// the view runs once both changes are in, when the path is shorter than the pushes it reads
+#[test]
+fn load_then_move_to_shorter_branch() {
+    let (tree, short) = long_and_short();
+    let conversation = Conversation::default();
+    let mut view = replay(&conversation);
+
+    conversation.load(tree, None, OverflowStrategy::default());
+    conversation.switch(conversation.path()[0], 1);
+
+    assert_eq!(view.catch_up(), conversation.path());
+    assert_eq!(conversation.path(), vec![short]);
+    let shown: Vec<String> = view.entries.iter().map(|(_, message)| message.text_content()).collect();
+    assert_eq!(shown, vec!["Other question"]);
+}
+
+#[test]
+fn entries_follow_messages() {
+    let (tree, _) = long_and_short();
+    let conversation = Conversation::default();
+    let mut view = replay(&conversation);
+
+    conversation.load(tree, None, OverflowStrategy::default());
+    let path = conversation.path();
+    conversation.delete(path[1]);
+    conversation.rate(path[3], Some(Rating::Good));
+
+    assert_eq!(view.catch_up(), conversation.path());
+    let messages = conversation.messages.lock_ref().to_vec();
+    let entries: Vec<Message> = view.entries.iter().map(|(_, message)| message.clone()).collect();
+    assert_eq!(entries, messages);
+}
+
+#[test]
+fn undo_and_redo_every_change() {
+    let conversation = Conversation::default();
+    let mut view = replay(&conversation);
+    // the messages after each change, which undo and redo should step back and forth through
+    let mut steps = vec![texts(&conversation)];
+
+    conversation.push(user("Question"));
+    steps.push(texts(&conversation));
+    conversation.push(assistant("Answer"));
+    steps.push(texts(&conversation));
+    let path = conversation.path();
+    conversation.regenerate(path[1], assistant("Better answer"));
+    steps.push(texts(&conversation));
+    conversation.switch(conversation.path()[1], -1);
+    steps.push(texts(&conversation));
+    assert!(conversation.edit(path[0], "Edited question"));
+    steps.push(texts(&conversation));
+    conversation.delete(conversation.path()[0]);
+    steps.push(texts(&conversation));
+
+    for expected in steps.iter().rev().skip(1) {
+        assert!(conversation.undo());
+        assert_eq!(&texts(&conversation), expected);
+    }
+    assert!(!conversation.undo());
+    for expected in steps.iter().skip(1) {
+        assert!(conversation.redo());
+        assert_eq!(&texts(&conversation), expected);
+    }
+    assert!(!conversation.redo());
+
+    assert_eq!(view.catch_up(), conversation.path());
+}
+
+#[test]
+fn undo_rating() {
+    let conversation = Conversation::default();
+    conversation.push(user("Question"));
+    conversation.push(assistant("Answer"));
+    let reply = conversation.path()[1];
+
+    conversation.rate(reply, Some(Rating::Good));
+    conversation.undo();
+    assert_eq!(conversation.messages.lock_ref()[1].metadata.rating, None);
+    conversation.redo();
+    assert_eq!(conversation.messages.lock_ref()[1].metadata.rating, Some(Rating::Good));
+}
+
+// a switch past the last branch changes nothing, so there's nothing to undo
+#[test]
+fn no_op_switch_is_not_undoable() {
+    let conversation = Conversation::default();
+    conversation.load(long_and_short().0, None, OverflowStrategy::default());
+
+    conversation.switch(conversation.path()[0], -1);
+    assert!(!conversation.undo());
+}
+
+#[test]
+fn new_change_drops_redo() {
+    let conversation = Conversation::default();
+    conversation.push(user("Question"));
+    conversation.undo();
+    conversation.push(user("Other question"));
+
+    assert!(!conversation.redo());
+    assert_eq!(texts(&conversation), vec!["Other question"]);
+}
+
+#[test]
+fn load_starts_over() {
+    let conversation = Conversation::default();
+    conversation.push(user("Question"));
+    conversation.load(long_and_short().0, Some(1), OverflowStrategy::default());
+
+    assert!(!conversation.undo());
+    assert_eq!(conversation.saved_id.get(), Some(1));
+}
+
+// undoing New reopens the saved chat with its strategy, unless it was deleted in the meantime
+#[test]
+fn undo_clear_and_forget_saved() {
+    let conversation = Conversation::default();
+    conversation.load(long_and_short().0, Some(1), OverflowStrategy::DropOldest);
+    let before = texts(&conversation);
+
+    conversation.clear();
+    assert!(texts(&conversation).is_empty());
+    assert_eq!(conversation.saved_id.get(), None);
+    assert_eq!(conversation.strategy.get(), OverflowStrategy::Error);
+    assert!(conversation.undo());
+    assert_eq!(texts(&conversation), before);
+    assert_eq!(conversation.saved_id.get(), Some(1));
+    assert_eq!(conversation.strategy.get(), OverflowStrategy::DropOldest);
+
+    conversation.clear();
+    conversation.forget_saved(1);
+    assert!(conversation.undo());
+    assert_eq!(texts(&conversation), before);
+    assert_eq!(conversation.saved_id.get(), None);
+}
@@ -0,0 +1,396 @@
+use futures_signals::{signal::{Mutable, SignalExt}, signal_vec::MutableVec};
+use gtk::{gio, glib::{self, clone}, prelude::*, Button, Entry, Label, ScrolledWindow};
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use serde_json::json;
+
+use crate::{
+    inspector::LogEntry,
+    settings::{APIKey, Provider, Settings},
+    submit::{api_url, azure_headers, openai_headers, send_request, DEFAULT_AZURE_API_VERSION},
+    util::get_buffer_content
+};
+
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+const NEIGHBOURS: usize = 3;
+const CELL_SIZE: f64 = 36.0;
+
+#[derive(Debug, Clone)]
+pub struct Embeddings {
+    pub model: String,
+    pub texts: Vec<String>,
+    pub vectors: Vec<Vec<f32>>,
+    pub input_tokens: Option<u64>
+}
+
+// Azure deploys each embedding model under its own deployment, so the model field names it
+fn embeddings_request(api_key: &APIKey, model: &str) -> Result<reqwest::RequestBuilder, String> {
+    let (url, mut headers) = match api_key.provider {
+        Provider::OpenAI => (api_url(api_key, "/embeddings"), openai_headers(&api_key.key)),
+        Provider::AzureOpenAI => {
+            let url = format!(
+                "{}/openai/deployments/{}/embeddings?api-version={}",
+                api_key.endpoint.as_deref().unwrap_or_default().trim_end_matches('/'),
+                model,
+                api_key.api_version.as_deref().unwrap_or(DEFAULT_AZURE_API_VERSION)
+            );
+            (url, azure_headers(&api_key.key)?)
+        },
+        provider => return Err(format!("{} keys don't serve OpenAI-compatible embeddings", provider.to_string()))
+    };
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    return Ok(reqwest::Client::new().post(url).headers(headers));
+}
+
+pub async fn fetch_embeddings(
+    api_key: &APIKey,
+    model: &str,
+    texts: &[String],
+    log: impl Fn(LogEntry)
+) -> Result<Embeddings, String> {
+    let body = json!({ "model": model, "input": texts }).to_string();
+    let request_builder = embeddings_request(api_key, model)?.body(body.clone());
+    let text = send_request(request_builder, &body, &log).await?;
+    let data: serde_json::Value = serde_json::from_str(&text).map_err(|err| err.to_string())?;
+
+    let mut vectors: Vec<Option<Vec<f32>>> = vec![None; texts.len()];
+    for item in data["data"].as_array().ok_or("The response has no embeddings")? {
+        let index = item["index"]
+            .as_u64()
+            .map(|index| index as usize)
+            .filter(|index| *index < texts.len())
+            .ok_or("An embedding has no valid index")?;
+        let vector = item["embedding"]
+            .as_array()
+            .ok_or("An embedding isn't an array")?
+            .iter()
+            .map(|value| value.as_f64().unwrap_or(0.0) as f32)
+            .collect();
+        vectors[index] = Some(vector);
+    }
+
+    // the export and the similarities both assume one vector per text, all the same length
+    let vectors: Vec<Vec<f32>> = vectors
+        .into_iter()
+        .enumerate()
+        .map(|(index, vector)| vector.ok_or(format!("The response has no embedding for text {}", index + 1)))
+        .collect::<Result<_, _>>()?;
+    let dimensions = vectors.first().map_or(0, |vector| vector.len());
+    if vectors.iter().any(|vector| vector.is_empty()) {
+        return Err("The response has an empty embedding".to_string());
+    }
+    if let Some(index) = vectors.iter().position(|vector| vector.len() != dimensions) {
+        return Err(format!(
+            "The embedding for text {} has {} dimensions instead of {}",
+            index + 1, vectors[index].len(), dimensions
+        ));
+    }
+
+    return Ok(Embeddings {
+        model: model.to_string(),
+        texts: texts.to_vec(),
+        vectors,
+        input_tokens: data["usage"]["prompt_tokens"].as_u64()
+    });
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm_a = a.iter().map(|a| a * a).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|b| b * b).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    return dot / (norm_a * norm_b);
+}
+
+pub fn similarity_matrix(vectors: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    return vectors
+        .iter()
+        .map(|a| vectors.iter().map(|b| cosine_similarity(a, b)).collect())
+        .collect();
+}
+
+// the closest other texts, most similar first
+pub fn nearest_neighbours(matrix: &[Vec<f32>], index: usize, count: usize) -> Vec<(usize, f32)> {
+    let mut neighbours: Vec<(usize, f32)> = matrix[index]
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(other, _)| *other != index)
+        .collect();
+    neighbours.sort_by(|a, b| b.1.total_cmp(&a.1));
+    neighbours.truncate(count);
+
+    return neighbours;
+}
+
+// NumPy's .npy format, version 1.0: magic, a little-endian header length, a Python dict
+// literal describing the array padded so the data starts on a 64 byte boundary, then the data
+pub fn to_npy(vectors: &[Vec<f32>]) -> Vec<u8> {
+    let dimensions = vectors.first().map_or(0, |vector| vector.len());
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+        vectors.len(),
+        dimensions
+    );
+    let unpadded = 10 + header.len() + 1;
+    header += &" ".repeat((64 - unpadded % 64) % 64);
+    header += "\n";
+
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    for value in vectors.iter().flatten() {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    return bytes;
+}
+
+pub fn to_json(embeddings: &Embeddings) -> String {
+    let items: Vec<serde_json::Value> = embeddings.texts
+        .iter()
+        .zip(&embeddings.vectors)
+        .map(|(text, vector)| json!({ "text": text, "embedding": vector }))
+        .collect();
+
+    return serde_json::to_string_pretty(&json!({ "model": embeddings.model, "embeddings": items })).unwrap();
+}
+
+// blue for dissimilar through red for similar, scaled to the spread of the off-diagonal values
+// because modern embedding models rarely produce similarities far below zero
+fn heat_color(value: f32, min: f32, max: f32) -> (f64, f64, f64) {
+    let t = if max > min { ((value - min) / (max - min)).clamp(0.0, 1.0) as f64 } else { 1.0 };
+    return (t, 0.25, 1.0 - t);
+}
+
+fn Heatmap(matrix: Vec<Vec<f32>>) -> gtk::DrawingArea {
+    let size = matrix.len();
+    let drawing_area = gtk::DrawingArea::new();
+    drawing_area.set_content_width(((size + 1) as f64 * CELL_SIZE) as i32);
+    drawing_area.set_content_height(((size + 1) as f64 * CELL_SIZE) as i32);
+    drawing_area.set_halign(gtk::Align::Start);
+
+    let off_diagonal: Vec<f32> = matrix
+        .iter()
+        .enumerate()
+        .flat_map(|(i, row)| row.iter().enumerate().filter(move |(j, _)| i != *j).map(|(_, value)| *value))
+        .collect();
+    let min = off_diagonal.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = off_diagonal.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+    drawing_area.set_draw_func(move |area, context, _, _| {
+        let foreground = area.color();
+        context.set_font_size(10.0);
+
+        // row and column headers number the texts as listed below the heatmap
+        for index in 0..size {
+            let position = (index + 1) as f64 * CELL_SIZE;
+            context.set_source_rgb(foreground.red() as f64, foreground.green() as f64, foreground.blue() as f64);
+            context.move_to(position + CELL_SIZE / 2.0 - 4.0, CELL_SIZE / 2.0 + 4.0);
+            let _ = context.show_text(&(index + 1).to_string());
+            context.move_to(CELL_SIZE / 2.0 - 4.0, position + CELL_SIZE / 2.0 + 4.0);
+            let _ = context.show_text(&(index + 1).to_string());
+        }
+
+        for (i, row) in matrix.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let x = (j + 1) as f64 * CELL_SIZE;
+                let y = (i + 1) as f64 * CELL_SIZE;
+                let (red, green, blue) = heat_color(*value, min, max);
+                context.set_source_rgb(red, green, blue);
+                context.rectangle(x, y, CELL_SIZE - 1.0, CELL_SIZE - 1.0);
+                let _ = context.fill();
+
+                context.set_source_rgb(1.0, 1.0, 1.0);
+                context.move_to(x + 4.0, y + CELL_SIZE / 2.0 + 4.0);
+                let _ = context.show_text(&format!("{:.2}", value));
+            }
+        }
+    });
+
+    return drawing_area;
+}
+
+fn Results(embeddings: &Embeddings) -> gtk::Box {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+
+    let dimensions = embeddings.vectors.first().map_or(0, |vector| vector.len());
+    let mut summary = format!("{} texts · {} dimensions", embeddings.texts.len(), dimensions);
+    if let Some(input_tokens) = embeddings.input_tokens {
+        summary += &format!(" · {} tokens", input_tokens);
+    }
+    let label = Label::new(Some(&summary));
+    label.set_xalign(0.0);
+    vbox.append(&label);
+
+    let matrix = similarity_matrix(&embeddings.vectors);
+    vbox.append(&Heatmap(matrix.clone()));
+
+    for (index, text) in embeddings.texts.iter().enumerate() {
+        let neighbours: Vec<String> = nearest_neighbours(&matrix, index, NEIGHBOURS)
+            .iter()
+            .map(|(other, similarity)| format!("{} ({:.3})", other + 1, similarity))
+            .collect();
+        let label = Label::new(Some(&format!("{}. {}\n    nearest: {}", index + 1, text, neighbours.join(", "))));
+        label.set_xalign(0.0);
+        label.set_wrap(true);
+        label.set_wrap_mode(gtk::pango::WrapMode::WordChar);
+        label.set_selectable(true);
+        vbox.append(&label);
+    }
+
+    return vbox;
+}
+
+fn save_file(button: &Button, initial_name: &str, contents: Vec<u8>, error: Mutable<String>) {
+    let dialog = gtk::FileDialog::new();
+    dialog.set_title("Export Embeddings");
+    dialog.set_initial_name(Some(initial_name));
+    let window = button.root().and_downcast::<gtk::Window>();
+    glib::spawn_future_local(async move {
+        let file: Option<gio::File> = dialog.save_future(window.as_ref()).await.ok();
+        if let Some(path) = file.and_then(|file| file.path()) {
+            if let Err(err) = std::fs::write(&path, contents) {
+                error.set(format!("Couldn't export to {}: {}", path.display(), err));
+            }
+        }
+    });
+}
+
+pub fn EmbeddingsPage(stack: gtk::Stack, settings: Mutable<Settings>, request_log: MutableVec<LogEntry>) -> gtk::Box {
+    let embeddings: Mutable<Option<Embeddings>> = Mutable::new(None);
+    let error = Mutable::new(String::new());
+    let loading = Mutable::new(false);
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    vbox.set_css_classes(&["top-level-box"]);
+
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let back_button = Button::with_label("Back");
+    back_button.connect_clicked(move |_| stack.set_visible_child_name("chat"));
+    hbox.append(&back_button);
+
+    let title = Label::new(Some("Embeddings"));
+    title.set_css_classes(&["title"]);
+    title.set_hexpand(true);
+    hbox.append(&title);
+    vbox.append(&hbox);
+
+    let error_label = Label::new(None);
+    error_label.set_css_classes(&["error-label"]);
+    error_label.set_wrap(true);
+    vbox.append(&error_label);
+
+    let model_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    model_box.append(&Label::new(Some("Model:")));
+    let model_entry = Entry::new();
+    model_entry.set_text(DEFAULT_EMBEDDING_MODEL);
+    model_entry.set_hexpand(true);
+    model_box.append(&model_entry);
+    vbox.append(&model_box);
+
+    let label = Label::new(Some("One text per line:"));
+    label.set_xalign(0.0);
+    vbox.append(&label);
+    let text_view = gtk::TextView::new();
+    text_view.set_height_request(100);
+    text_view.set_wrap_mode(gtk::WrapMode::WordChar);
+    text_view.set_css_classes(&["embedding-texts"]);
+    vbox.append(&text_view);
+
+    let scrolled_window = ScrolledWindow::new();
+    scrolled_window.set_vexpand(true);
+    vbox.append(&scrolled_window);
+
+    let action_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    action_box.set_halign(gtk::Align::End);
+    let npy_button = Button::with_label("Export .npy…");
+    action_box.append(&npy_button);
+    let json_button = Button::with_label("Export JSON…");
+    action_box.append(&json_button);
+    let embed_button = Button::with_label("Embed");
+    action_box.append(&embed_button);
+    vbox.append(&action_box);
+
+    embed_button.connect_clicked(clone!(
+        @strong settings,
+        @strong embeddings,
+        @strong error,
+        @strong loading,
+        @strong request_log => move |_| {
+            let settings = settings.lock_ref().clone();
+            let api_key = match settings.api_key {
+                Some(index) => settings.api_keys[index].clone(),
+                None => {
+                    error.set("No API key selected.".to_string());
+                    return;
+                }
+            };
+            let texts: Vec<String> = get_buffer_content(&text_view.buffer())
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect();
+            if texts.len() < 2 {
+                error.set("Enter at least two texts to compare.".to_string());
+                return;
+            }
+            let model = model_entry.text().trim().to_string();
+
+            error.set(String::new());
+            loading.set(true);
+            glib::spawn_future_local(clone!(
+                @strong embeddings,
+                @strong error,
+                @strong loading,
+                @strong request_log => async move {
+                    let log = |entry| request_log.lock_mut().push_cloned(entry);
+                    match fetch_embeddings(&api_key, &model, &texts, log).await {
+                        Ok(fetched) => embeddings.set(Some(fetched)),
+                        Err(err) => error.set(format!("Embedding failed: {}", err))
+                    }
+                    loading.set(false);
+                }
+            ));
+        }
+    ));
+
+    npy_button.connect_clicked(clone!(@strong embeddings, @strong error => move |button| {
+        if let Some(embeddings) = embeddings.lock_ref().as_ref() {
+            save_file(button, "embeddings.npy", to_npy(&embeddings.vectors), error.clone());
+        }
+    }));
+
+    json_button.connect_clicked(clone!(@strong embeddings, @strong error => move |button| {
+        if let Some(embeddings) = embeddings.lock_ref().as_ref() {
+            save_file(button, "embeddings.json", to_json(embeddings).into_bytes(), error.clone());
+        }
+    }));
+
+    glib::spawn_future_local(error.signal_cloned().for_each(move |error| {
+        error_label.set_text(&error);
+        error_label.set_visible(!error.is_empty());
+        async {}
+    }));
+
+    glib::spawn_future_local(loading.signal().for_each(move |loading| {
+        embed_button.set_sensitive(!loading);
+        embed_button.set_label(if loading { "Embedding…" } else { "Embed" });
+        async {}
+    }));
+
+    glib::spawn_future_local(embeddings.signal_cloned().for_each(move |embeddings| {
+        match &embeddings {
+            Some(embeddings) => scrolled_window.set_child(Some(&Results(embeddings))),
+            None => scrolled_window.set_child(None::<&gtk::Widget>)
+        }
+        npy_button.set_sensitive(embeddings.is_some());
+        json_button.set_sensitive(embeddings.is_some());
+        async {}
+    }));
+
+    return vbox;
+}
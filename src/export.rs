@@ -0,0 +1,283 @@
+// Writes the conversation on screen to a file, as Markdown for pasting into docs,
+// standalone HTML that looks like the chat, or JSON for tools.
+//
+// The JSON is a single object, version 1:
+//   format        always "llm-playground.conversation"
+//   version       1; fields are only added within a version
+//   title         the first line of the first user message
+//   exported_at   {"secs_since_epoch", "nanos_since_epoch"}, like every timestamp in the file
+//   settings      model, temperature, max_tokens, api_key_name, provider, openai_api and instructions
+//                 at the time of export; keys themselves are never written
+//   system_prompt the system messages' text joined by blank lines, or null without any
+//   messages      the active branch, oldest first, each with
+//                   role      "system", "user", "assistant" or "tool"
+//                   content   blocks tagged by "type": text {text}, image {media_type, data (base64)},
+//                             tool_use {id, name, input}, tool_result {tool_use_id, content}
+//                   metadata  model, timestamp, usage {input_tokens, output_tokens,
+//                             cache_read_tokens, cache_write_tokens, cost}, stop_reason; each may be null
+
+use std::{rc::Rc, time::SystemTime};
+
+use futures_signals::{signal::Mutable, signal_vec::MutableVec};
+use gtk::{gio, glib::{self, clone}, prelude::*};
+use pulldown_cmark::{html::push_html, CowStr, Event, Options, Parser, Tag};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    finetune::FineTuneWindow,
+    history::{default_title, ChatSummary, History, SettingsSnapshot},
+    message::{Message, Role},
+    settings::Settings
+};
+
+pub const EXPORT_FORMAT: &str = "llm-playground.conversation";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConversationExport {
+    pub format: String,
+    pub version: u32,
+    pub title: String,
+    pub exported_at: SystemTime,
+    pub settings: SettingsSnapshot,
+    pub system_prompt: Option<String>,
+    pub messages: Vec<Message>
+}
+
+// the same look as style.css, with the theme colors filled in
+const EXPORT_CSS: &str = "
+body {
+    font-family: sans-serif;
+    font-size: 10pt;
+    max-width: 50em;
+    margin: 2em auto;
+    color: #1e1e1e;
+    background-color: #fafafa;
+}
+
+.exchange {
+    margin-bottom: 10px;
+}
+
+.button-box {
+    padding: 2px 4px;
+    background-color: #d5d5d5;
+}
+
+.role-label {
+    font-size: 8pt;
+    font-weight: bold;
+    padding: 0 4px;
+}
+
+.usage-label {
+    font-size: 8pt;
+    padding: 0 4px;
+    opacity: 0.7;
+}
+
+.message-label {
+    padding: 0.5em;
+    background-color: #d5d5d5;
+    overflow-wrap: anywhere;
+}
+
+.raw {
+    white-space: pre-wrap;
+}
+
+pre {
+    font-family: monospace;
+    font-size: 9pt;
+    padding: 0.5em;
+    overflow-x: auto;
+    background-color: #bcbcbc;
+}
+
+table {
+    border-collapse: collapse;
+}
+
+td, th {
+    border: 1px solid #999;
+    padding: 2px 6px;
+}";
+
+fn system_prompt(messages: &[Message]) -> Option<String> {
+    let system: Vec<String> = messages
+        .iter()
+        .filter(|message| message.role == Role::System)
+        .map(|message| message.text_content())
+        .collect();
+
+    return (!system.is_empty()).then(|| system.join("\n\n"));
+}
+
+fn escape_html(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+// web and mail links are kept and relative ones can't leave the file; any other scheme,
+// like javascript: or data:, is replaced. Browsers ignore tabs and line breaks in a URL,
+// so they're dropped before looking for the scheme
+fn safe_url(url: CowStr) -> CowStr {
+    let cleaned: String = url.chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect();
+    let scheme = cleaned
+        .split_once(':')
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+        .filter(|scheme| !scheme.contains(|c| matches!(c, '/' | '?' | '#')));
+    match scheme {
+        Some(scheme) if !matches!(scheme.as_str(), "http" | "https" | "mailto") => CowStr::Borrowed("#"),
+        _ => url
+    }
+}
+
+// raw HTML in a reply is shown as text, as it is in the chat
+fn markdown_html(text: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+            Event::Start(Tag::Link { link_type, dest_url: safe_url(dest_url), title, id })
+        },
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+            Event::Start(Tag::Image { link_type, dest_url: safe_url(dest_url), title, id })
+        },
+        event => event
+    });
+    let mut html = String::new();
+    push_html(&mut html, events);
+
+    return html;
+}
+
+fn metadata_summary(message: &Message) -> String {
+    let metadata = &message.metadata;
+    let mut parts = vec![];
+    if let Some(model) = &metadata.model {
+        parts.push(model.clone());
+    }
+    if let Some(usage) = &metadata.usage {
+        parts.push(format!("{} in / {} out", usage.input_tokens, usage.output_tokens));
+    }
+    if let Some(stop_reason) = &metadata.stop_reason {
+        parts.push(stop_reason.clone());
+    }
+
+    return parts.join(" · ");
+}
+
+pub fn to_markdown(messages: &[Message]) -> String {
+    let mut markdown = format!("# {}\n", default_title(messages));
+    for message in messages {
+        markdown += &format!("\n## {}", message.role.to_string());
+        if let Some(model) = &message.metadata.model {
+            markdown += &format!(" ({})", model);
+        }
+        markdown += &format!("\n\n{}\n", message.display_text().trim_end());
+    }
+
+    return markdown;
+}
+
+pub fn to_html(messages: &[Message]) -> String {
+    let title = escape_html(&default_title(messages));
+    let mut body = String::new();
+    for message in messages {
+        // replies are rendered, everything else is shown as typed, like the chat's Raw default
+        let content = if message.role == Role::Assistant {
+            format!("<div class=\"message-label\">{}</div>", markdown_html(&message.display_text()))
+        } else {
+            format!("<div class=\"message-label raw\">{}</div>", escape_html(&message.display_text()))
+        };
+        body += &format!(
+            "<div class=\"exchange\">\n<div class=\"button-box\"><span class=\"role-label\">{}</span><span class=\"usage-label\">{}</span></div>\n{}\n</div>\n",
+            message.role.to_string(),
+            escape_html(&metadata_summary(message)),
+            content
+        );
+    }
+
+    return format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n{}</body>\n</html>\n",
+        title, EXPORT_CSS, title, body
+    );
+}
+
+pub fn to_json(messages: &[Message], settings: &Settings) -> String {
+    let export = ConversationExport {
+        format: EXPORT_FORMAT.to_string(),
+        version: 1,
+        title: default_title(messages),
+        exported_at: SystemTime::now(),
+        settings: SettingsSnapshot::of(settings),
+        system_prompt: system_prompt(messages),
+        messages: messages.to_vec()
+    };
+
+    return serde_json::to_string_pretty(&export).unwrap();
+}
+
+fn save_file(widget: &impl IsA<gtk::Widget>, initial_name: &str, contents: String, error: Mutable<String>) {
+    let dialog = gtk::FileDialog::new();
+    dialog.set_title("Export Conversation");
+    dialog.set_initial_name(Some(initial_name));
+    let window = widget.root().and_downcast::<gtk::Window>();
+    glib::spawn_future_local(async move {
+        let file: Option<gio::File> = dialog.save_future(window.as_ref()).await.ok();
+        if let Some(path) = file.and_then(|file| file.path()) {
+            if let Err(err) = std::fs::write(&path, contents) {
+                error.set(format!("Couldn't export to {}: {}", path.display(), err));
+            }
+        }
+    });
+}
+
+pub fn ExportButton(
+    messages: MutableVec<Message>,
+    settings: Mutable<Settings>,
+    history: Rc<History>,
+    chats: Mutable<Vec<ChatSummary>>,
+    chat_id: Mutable<Option<i64>>,
+    error: Mutable<String>
+) -> gtk::MenuButton {
+    let menu_button = gtk::MenuButton::new();
+    menu_button.set_label("Export");
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+    let popover = gtk::Popover::new();
+    popover.set_child(Some(&vbox));
+    menu_button.set_popover(Some(&popover));
+
+    let formats: [(&str, &str, fn(&[Message], &Settings) -> String); 3] = [
+        ("Markdown", "md", |messages, _| to_markdown(messages)),
+        ("HTML", "html", |messages, _| to_html(messages)),
+        ("JSON", "json", to_json)
+    ];
+    for (name, extension, export) in formats {
+        let button = gtk::Button::with_label(name);
+        button.connect_clicked(clone!(@strong messages, @strong settings, @strong error, @weak popover, @weak menu_button => move |_| {
+            popover.popdown();
+            let messages = messages.lock_ref().to_vec();
+            if messages.is_empty() {
+                error.set("Nothing to export yet.".to_string());
+                return;
+            }
+            let contents = export(&messages, &settings.lock_ref());
+            save_file(&menu_button, &format!("conversation.{}", extension), contents, error.clone());
+        }));
+        vbox.append(&button);
+    }
+
+    let fine_tune_button = gtk::Button::with_label("Fine-Tuning JSONL…");
+    fine_tune_button.connect_clicked(clone!(@weak popover => move |_| {
+        popover.popdown();
+        FineTuneWindow(messages.lock_ref().to_vec(), history.clone(), chats.get_cloned(), chat_id.get()).present();
+    }));
+    vbox.append(&fine_tune_button);
+
+    return menu_button;
+}
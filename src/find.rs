@@ -0,0 +1,255 @@
+use std::{cell::RefCell, rc::Rc};
+
+use futures_signals::signal_vec::{MutableVec, SignalVecExt};
+use gtk::{glib::{self, clone}, pango, prelude::*, Label};
+use regex::{Regex, RegexBuilder};
+
+use crate::message::Message;
+
+// byte range of a match in the label's text, which is what pango attributes index
+struct Match {
+    label: Label,
+    start: usize,
+    end: usize
+}
+
+#[derive(Default)]
+struct FindState {
+    matches: Vec<Match>,
+    current: Option<usize>,
+    // shown instead of the position when there's no current match
+    status: String
+}
+
+fn count_text(state: &FindState) -> String {
+    return match state.current {
+        Some(current) => format!("{}/{}", current + 1, state.matches.len()),
+        None => state.status.clone()
+    };
+}
+
+// message text and code blocks, in the order they're shown
+fn searchable_labels(widget: &gtk::Widget, labels: &mut Vec<Label>) {
+    if let Some(label) = widget.downcast_ref::<Label>() {
+        if label.has_css_class("message-label") || label.has_css_class("code-block") {
+            labels.push(label.clone());
+        }
+        return;
+    }
+    let mut child = widget.first_child();
+    while let Some(widget) = child {
+        searchable_labels(&widget, labels);
+        child = widget.next_sibling();
+    }
+}
+
+fn build_pattern(text: &str, case_sensitive: bool, regex: bool) -> Result<Regex, regex::Error> {
+    let pattern = if regex { text.to_string() } else { regex::escape(text) };
+    return RegexBuilder::new(&pattern).case_insensitive(!case_sensitive).build();
+}
+
+fn highlight_attribute(start: usize, end: usize, current: bool) -> pango::AttrColor {
+    let mut attribute = if current {
+        pango::AttrColor::new_background(0xffff, 0x9600, 0x0000)
+    } else {
+        pango::AttrColor::new_background(0xffff, 0xe400, 0x5c00)
+    };
+    attribute.set_start_index(start as u32);
+    attribute.set_end_index(end as u32);
+
+    return attribute;
+}
+
+// the label's own markup is kept; these attributes are merged on top of it
+fn highlight(matches: &[Match], current: Option<usize>) {
+    let mut lists: Vec<(Label, pango::AttrList)> = vec![];
+    for (index, found) in matches.iter().enumerate() {
+        if lists.last().is_none_or(|(label, _)| *label != found.label) {
+            lists.push((found.label.clone(), pango::AttrList::new()));
+        }
+        let (_, list) = lists.last().unwrap();
+        list.insert(highlight_attribute(found.start, found.end, current == Some(index)));
+        let mut foreground = pango::AttrColor::new_foreground(0, 0, 0);
+        foreground.set_start_index(found.start as u32);
+        foreground.set_end_index(found.end as u32);
+        list.insert(foreground);
+    }
+    for (label, list) in lists {
+        label.set_attributes(Some(&list));
+    }
+}
+
+fn clear_highlights(matches: &[Match]) {
+    for found in matches {
+        found.label.set_attributes(None);
+    }
+}
+
+// puts the match a third of the way down the view
+fn scroll_to(scrolled_window: &gtk::ScrolledWindow, content: &gtk::Box, found: &Match) {
+    let (_, y_offset) = found.label.layout_offsets();
+    let position = found.label.layout().index_to_pos(found.start as i32);
+    let y = y_offset as f32 + position.y() as f32 / pango::SCALE as f32;
+    if let Some(point) = found.label.compute_point(content, &gtk::graphene::Point::new(0.0, y)) {
+        let adjustment = scrolled_window.vadjustment();
+        adjustment.set_value(point.y() as f64 - adjustment.page_size() / 3.0);
+    }
+}
+
+fn FindOption(label: &str, tooltip: &str) -> gtk::ToggleButton {
+    let button = gtk::ToggleButton::with_label(label);
+    button.set_tooltip_text(Some(tooltip));
+    button.set_css_classes(&["flat", "exchange-header-button"]);
+
+    return button;
+}
+
+// Ctrl+F bar searching the text of every message shown in `content`
+pub fn FindBar(scrolled_window: gtk::ScrolledWindow, content: gtk::Box, messages: MutableVec<Message>) -> gtk::Box {
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    hbox.set_css_classes(&["find-bar"]);
+    hbox.set_visible(false);
+
+    let entry = gtk::SearchEntry::new();
+    entry.set_placeholder_text(Some("Find in chat"));
+    entry.set_hexpand(true);
+    hbox.append(&entry);
+    let case_button = FindOption("Aa", "Match case");
+    hbox.append(&case_button);
+    let regex_button = FindOption(".*", "Regular expression");
+    hbox.append(&regex_button);
+    let count_label = Label::new(None);
+    count_label.set_css_classes(&["usage-label"]);
+    hbox.append(&count_label);
+    let previous_button = gtk::Button::with_label("<");
+    previous_button.set_css_classes(&["flat", "exchange-header-button"]);
+    hbox.append(&previous_button);
+    let next_button = gtk::Button::with_label(">");
+    next_button.set_css_classes(&["flat", "exchange-header-button"]);
+    hbox.append(&next_button);
+    let close_button = gtk::Button::with_label("×");
+    close_button.set_css_classes(&["flat", "exchange-header-button"]);
+    hbox.append(&close_button);
+
+    let state = Rc::new(RefCell::new(FindState::default()));
+
+    // finds the matches again, keeping the current one's position if it still exists
+    let refresh = Rc::new(clone!(
+        @strong state,
+        @strong content,
+        @weak entry,
+        @weak case_button,
+        @weak regex_button,
+        @weak count_label => move || {
+            let mut state = state.borrow_mut();
+            clear_highlights(&state.matches);
+            state.matches.clear();
+            entry.remove_css_class("error");
+
+            let text = entry.text();
+            if text.is_empty() {
+                state.current = None;
+                state.status = String::new();
+                count_label.set_text("");
+                return;
+            }
+            let pattern = match build_pattern(&text, case_button.is_active(), regex_button.is_active()) {
+                Ok(pattern) => pattern,
+                Err(_) => {
+                    state.current = None;
+                    state.status = "Bad pattern".to_string();
+                    entry.add_css_class("error");
+                    count_label.set_text(&state.status);
+                    return;
+                }
+            };
+
+            let mut labels = vec![];
+            searchable_labels(content.upcast_ref(), &mut labels);
+            for label in labels {
+                let text = label.text();
+                for found in pattern.find_iter(&text).filter(|found| !found.is_empty()) {
+                    state.matches.push(Match { label: label.clone(), start: found.start(), end: found.end() });
+                }
+            }
+            state.current = match state.current {
+                _ if state.matches.is_empty() => None,
+                Some(current) => Some(current.min(state.matches.len() - 1)),
+                None => Some(0)
+            };
+            state.status = "No matches".to_string();
+            highlight(&state.matches, state.current);
+            count_label.set_text(&count_text(&state));
+        }
+    ));
+
+    let jump = Rc::new(clone!(@strong state, @strong refresh, @weak scrolled_window, @weak content, @weak count_label => move |offset: isize| {
+        // rendering switches and edits replace labels without touching the messages
+        if state.borrow().matches.iter().any(|found| found.label.root().is_none()) {
+            refresh();
+        }
+        let mut state = state.borrow_mut();
+        let count = state.matches.len() as isize;
+        if let Some(current) = state.current {
+            let current = (current as isize + offset).rem_euclid(count) as usize;
+            state.current = Some(current);
+            highlight(&state.matches, state.current);
+            scroll_to(&scrolled_window, &content, &state.matches[current]);
+            count_label.set_text(&count_text(&state));
+        }
+    }));
+
+    let search = Rc::new(clone!(@strong state, @strong refresh, @strong jump => move || {
+        state.borrow_mut().current = None;
+        refresh();
+        jump(0);
+    }));
+    entry.connect_search_changed(clone!(@strong search => move |_| search()));
+    case_button.connect_toggled(clone!(@strong search => move |_| search()));
+    regex_button.connect_toggled(clone!(@strong search => move |_| search()));
+
+    entry.connect_activate(clone!(@strong jump => move |_| jump(1)));
+    entry.connect_next_match(clone!(@strong jump => move |_| jump(1)));
+    entry.connect_previous_match(clone!(@strong jump => move |_| jump(-1)));
+    next_button.connect_clicked(clone!(@strong jump => move |_| jump(1)));
+    previous_button.connect_clicked(clone!(@strong jump => move |_| jump(-1)));
+
+    let close = Rc::new(clone!(@strong state, @weak hbox => move || {
+        let mut state = state.borrow_mut();
+        clear_highlights(&state.matches);
+        state.matches.clear();
+        state.current = None;
+        hbox.set_visible(false);
+    }));
+    entry.connect_stop_search(clone!(@strong close => move |_| close()));
+    close_button.connect_clicked(move |_| close());
+
+    hbox.connect_map(clone!(@strong search => move |_| search()));
+
+    // the exchanges are rebuilt after this runs, so look again once they're in place
+    glib::spawn_future_local(messages.signal_vec_cloned().for_each({
+        let hbox = hbox.clone();
+        move |_| {
+            if hbox.is_visible() {
+                glib::idle_add_local_once(clone!(@strong refresh => move || refresh()));
+            }
+            async {}
+        }
+    }));
+
+    return hbox;
+}
+
+// Ctrl+F opens the find bar, or moves focus back to it if it's already open
+pub fn FindShortcut(find_bar: &gtk::Box) -> gtk::ShortcutController {
+    let controller = gtk::ShortcutController::new();
+    controller.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let action = gtk::CallbackAction::new(clone!(@weak find_bar => @default-return glib::Propagation::Proceed, move |_, _| {
+        find_bar.set_visible(true);
+        find_bar.child_focus(gtk::DirectionType::TabForward);
+        return glib::Propagation::Stop;
+    }));
+    controller.add_shortcut(gtk::Shortcut::new(gtk::ShortcutTrigger::parse_string("<Control>f"), Some(action)));
+
+    return controller;
+}
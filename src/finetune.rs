@@ -0,0 +1,232 @@
+use std::rc::Rc;
+
+use futures_signals::signal::{Mutable, SignalExt};
+use gtk::{gio, glib::{self, clone}, prelude::*, Label, Window};
+use serde_json::json;
+
+use crate::{
+    history::{ChatSummary, History},
+    message::{Message, Rating, Role},
+    submit::{anthropic_message, openai_messages}
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DatasetFormat {
+    // {"messages": [...]} as taken by OpenAI chat fine-tuning
+    OpenAI,
+    // {"system": ..., "messages": [...]} in the shape of a Messages API request
+    Anthropic
+}
+
+const FORMATS: [(&str, DatasetFormat); 2] = [("OpenAI chat", DatasetFormat::OpenAI), ("Anthropic messages", DatasetFormat::Anthropic)];
+
+#[derive(Debug, Copy, Clone)]
+pub struct DatasetOptions {
+    pub format: DatasetFormat,
+    pub include_system_prompt: bool,
+    // train only on replies rated good; the rest are kept as context where the format allows
+    pub only_good: bool
+}
+
+fn is_good(message: &Message) -> bool {
+    return message.metadata.rating == Some(Rating::Good);
+}
+
+// an example ends with a reply, the last good one when only those count; turns after it
+// have nothing to teach, while the ones before it are the context the reply answered
+fn example_messages(messages: &[Message], only_good: bool) -> Vec<Message> {
+    let messages: Vec<Message> = messages.iter().filter(|message| message.role != Role::System).cloned().collect();
+    let end = messages
+        .iter()
+        .rposition(|message| message.role == Role::Assistant && (!only_good || is_good(message)))
+        .map_or(0, |index| index + 1);
+
+    return messages[..end].to_vec();
+}
+
+// one JSONL line for a conversation, or None if it has no reply to learn from
+pub fn dataset_line(messages: &[Message], options: DatasetOptions) -> Option<String> {
+    let example = example_messages(messages, options.only_good);
+    if example.is_empty() {
+        return None;
+    }
+    let system: Vec<&Message> = if options.include_system_prompt {
+        messages.iter().filter(|message| message.role == Role::System).collect()
+    } else {
+        vec![]
+    };
+
+    let line = match options.format {
+        // the whole conversation stays as context; replies that aren't good get a weight of 0
+        // so they're read but not trained on
+        DatasetFormat::OpenAI => {
+            let mut entries: Vec<serde_json::Value> = system.into_iter().flat_map(openai_messages).collect();
+            for message in &example {
+                let untrained = options.only_good && message.role == Role::Assistant && !is_good(message);
+                for mut entry in openai_messages(message) {
+                    if untrained && entry["role"] == "assistant" {
+                        entry["weight"] = json!(0);
+                    }
+                    entries.push(entry);
+                }
+            }
+            json!({ "messages": entries })
+        }
+        // every reply is trained on, so the example stops before the first one that isn't good,
+        // and a conversation whose first reply isn't good is left out
+        DatasetFormat::Anthropic => {
+            let untrained = example
+                .iter()
+                .position(|message| options.only_good && message.role == Role::Assistant && !is_good(message))
+                .unwrap_or(example.len());
+            let example = example_messages(&example[..untrained], false);
+            if example.is_empty() {
+                return None;
+            }
+            let entries: Vec<serde_json::Value> = example.iter().map(anthropic_message).collect();
+            let mut line = json!({ "messages": entries });
+            if !system.is_empty() {
+                let system: Vec<String> = system.iter().map(|message| message.text_content()).collect();
+                line["system"] = json!(system.join("\n\n"));
+            }
+            line
+        }
+    };
+
+    return Some(line.to_string());
+}
+
+// the dataset and the number of examples in it
+pub fn build_dataset(conversations: &[Vec<Message>], options: DatasetOptions) -> (String, usize) {
+    let lines: Vec<String> = conversations
+        .iter()
+        .filter_map(|messages| dataset_line(messages, options))
+        .collect();
+    let count = lines.len();
+
+    return (lines.into_iter().map(|line| line + "\n").collect(), count);
+}
+
+// picks conversations and options, then saves them as a .jsonl file
+pub fn FineTuneWindow(current: Vec<Message>, history: Rc<History>, chats: Vec<ChatSummary>, current_id: Option<i64>) -> Window {
+    let window = Window::new();
+    window.set_css_classes(&["popup-window"]);
+    window.set_title(Some("Export Fine-Tuning Data"));
+    window.set_default_size(400, 450);
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+
+    let status_label = Label::new(None);
+    status_label.set_css_classes(&["error-label"]);
+    status_label.set_visible(false);
+    vbox.append(&status_label);
+
+    let label = Label::new(Some("Conversations:"));
+    label.set_halign(gtk::Align::Start);
+    vbox.append(&label);
+
+    // the chat on screen comes first; the saved copy of it is left out to avoid a duplicate
+    let listbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+    listbox.set_css_classes(&["api-key-list"]);
+    let current_check = gtk::CheckButton::with_label("Current conversation");
+    current_check.set_active(!current.is_empty());
+    current_check.set_sensitive(!current.is_empty());
+    listbox.append(&current_check);
+    let chat_checks: Vec<(i64, gtk::CheckButton)> = chats
+        .iter()
+        .filter(|chat| Some(chat.id) != current_id)
+        .map(|chat| {
+            let check = gtk::CheckButton::with_label(&chat.title);
+            listbox.append(&check);
+            (chat.id, check)
+        })
+        .collect();
+    let scrolled_window = gtk::ScrolledWindow::new();
+    scrolled_window.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled_window.set_child(Some(&listbox));
+    scrolled_window.set_vexpand(true);
+    vbox.append(&scrolled_window);
+
+    let grid = gtk::Grid::new();
+    grid.set_css_classes(&["settings-box"]);
+    grid.set_row_spacing(10);
+    grid.set_column_spacing(10);
+    let label = Label::new(Some("Format:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 0, 1, 1);
+    let format_names: Vec<&str> = FORMATS.iter().map(|(name, _)| *name).collect();
+    let format_dropdown = gtk::DropDown::from_strings(&format_names);
+    grid.attach(&format_dropdown, 1, 0, 1, 1);
+    let system_check = gtk::CheckButton::with_label("Include the system prompt");
+    system_check.set_active(true);
+    grid.attach(&system_check, 0, 1, 2, 1);
+    let good_check = gtk::CheckButton::with_label("Only train on replies marked Good");
+    grid.attach(&good_check, 0, 2, 2, 1);
+    vbox.append(&grid);
+
+    let export_button = gtk::Button::with_label("Export");
+    export_button.set_halign(gtk::Align::End);
+    vbox.append(&export_button);
+
+    let error = Mutable::new(String::new());
+    glib::spawn_future_local(error.signal_cloned().for_each({
+        let status_label = status_label.clone();
+        move |error| {
+            status_label.set_text(&error);
+            status_label.set_visible(!error.is_empty());
+            async {}
+        }
+    }));
+
+    export_button.connect_clicked(clone!(@weak window => move |button| {
+        let options = DatasetOptions {
+            format: FORMATS[format_dropdown.selected() as usize].1,
+            include_system_prompt: system_check.is_active(),
+            only_good: good_check.is_active()
+        };
+
+        let mut conversations = vec![];
+        if current_check.is_active() {
+            conversations.push(current.clone());
+        }
+        for (id, check) in &chat_checks {
+            if check.is_active() {
+                match history.load(*id) {
+                    Ok((tree, _)) => conversations.push(tree.active_messages()),
+                    Err(err) => {
+                        error.set(format!("Couldn't load a saved chat: {}", err));
+                        return;
+                    }
+                }
+            }
+        }
+
+        let (dataset, count) = build_dataset(&conversations, options);
+        if count == 0 {
+            error.set("None of the selected conversations have a reply to train on.".to_string());
+            return;
+        }
+        error.set(String::new());
+
+        let dialog = gtk::FileDialog::new();
+        dialog.set_title("Export Fine-Tuning Data");
+        dialog.set_initial_name(Some("dataset.jsonl"));
+        let parent = button.root().and_downcast::<Window>();
+        let error = error.clone();
+        glib::spawn_future_local(async move {
+            let file: Option<gio::File> = dialog.save_future(parent.as_ref()).await.ok();
+            if let Some(path) = file.and_then(|file| file.path()) {
+                match std::fs::write(&path, dataset) {
+                    Ok(()) => window.close(),
+                    Err(err) => error.set(format!("Couldn't export to {}: {}", path.display(), err))
+                }
+            }
+        });
+    }));
+
+    window.set_child(Some(&vbox));
+    return window;
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,109 @@
+// Checks which replies each dataset format trains on, with and without "only good".
+
+use serde_json::{json, Value};
+
+use super::{build_dataset, dataset_line, DatasetFormat, DatasetOptions};
+use crate::message::{Message, Rating, Role};
+
+fn user(text: &str) -> Message {
+    return Message::text(Role::User, text);
+}
+
+fn reply(text: &str, rating: Option<Rating>) -> Message {
+    let mut message = Message::text(Role::Assistant, text);
+    message.metadata.rating = rating;
+    return message;
+}
+
+// an unrated reply, a good one, then a bad one
+fn mixed() -> Vec<Message> {
+    return vec![
+        Message::text(Role::System, "Be brief."),
+        user("One"),
+        reply("First", None),
+        user("Two"),
+        reply("Second", Some(Rating::Good)),
+        user("Three"),
+        reply("Third", Some(Rating::Bad))
+    ];
+}
+
+// good replies up to an unrated one, then a good one after it
+fn good_then_unrated() -> Vec<Message> {
+    return vec![
+        user("One"),
+        reply("First", Some(Rating::Good)),
+        user("Two"),
+        reply("Second", None),
+        user("Three"),
+        reply("Third", Some(Rating::Good))
+    ];
+}
+
+fn options(format: DatasetFormat, include_system_prompt: bool, only_good: bool) -> DatasetOptions {
+    return DatasetOptions { format, include_system_prompt, only_good };
+}
+
+fn line(messages: &[Message], options: DatasetOptions) -> Value {
+    return serde_json::from_str(&dataset_line(messages, options).unwrap()).unwrap();
+}
+
+// replies that aren't good stay as context with a weight of 0, up to the last good one
+#[test]
+fn openai_weights_untrained_replies() {
+    let line = line(&mixed(), options(DatasetFormat::OpenAI, true, true));
+
+    assert_eq!(line, json!({ "messages": [
+        { "role": "system", "content": "Be brief." },
+        { "role": "user", "content": "One" },
+        { "role": "assistant", "content": "First", "weight": 0 },
+        { "role": "user", "content": "Two" },
+        { "role": "assistant", "content": "Second" }
+    ] }));
+}
+
+#[test]
+fn openai_trains_on_everything_by_default() {
+    let line = line(&mixed(), options(DatasetFormat::OpenAI, false, false));
+
+    let messages = line["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 6);
+    assert_eq!(messages[0], json!({ "role": "user", "content": "One" }));
+    assert!(messages.iter().all(|message| message.get("weight").is_none()));
+}
+
+#[test]
+fn anthropic_stops_before_untrained_reply() {
+    let line = line(&good_then_unrated(), options(DatasetFormat::Anthropic, true, true));
+
+    assert_eq!(line, json!({ "messages": [
+        { "role": "user", "content": "One" },
+        { "role": "assistant", "content": "First" }
+    ] }));
+}
+
+// the first reply isn't good, so there's nothing this format can train on
+#[test]
+fn anthropic_drops_conversation_without_leading_good_reply() {
+    assert_eq!(dataset_line(&mixed(), options(DatasetFormat::Anthropic, true, true)), None);
+}
+
+#[test]
+fn anthropic_system_prompt_option() {
+    let with_system = line(&mixed(), options(DatasetFormat::Anthropic, true, false));
+    assert_eq!(with_system["system"], "Be brief.");
+    assert_eq!(with_system["messages"].as_array().unwrap().len(), 6);
+
+    let without_system = line(&mixed(), options(DatasetFormat::Anthropic, false, false));
+    assert!(without_system.get("system").is_none());
+}
+
+#[test]
+fn dataset_skips_conversations_without_replies() {
+    let conversations = vec![mixed(), vec![user("Unanswered")], good_then_unrated()];
+    let (dataset, count) = build_dataset(&conversations, options(DatasetFormat::Anthropic, false, true));
+
+    assert_eq!(count, 1);
+    assert_eq!(dataset.lines().count(), 1);
+    assert!(dataset.ends_with('\n'));
+}
@@ -0,0 +1,619 @@
+use std::{env, rc::Rc, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use futures_signals::{map_ref, signal::{Mutable, SignalExt}};
+use gtk::{glib::{self, clone, markup_escape_text}, prelude::*, Label};
+use rusqlite::{params, types::Type, Connection};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    context::OverflowStrategy,
+    conversation::{Conversation, ConversationTree},
+    message::{Message, Role},
+    settings::{OpenAIAPI, Provider, Settings, PROVIDERS}
+};
+
+// what a chat was sent with, minus the keys themselves
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SettingsSnapshot {
+    pub model: String,
+    pub temperature: f64,
+    pub max_tokens: u32,
+    pub api_key_name: Option<String>,
+    pub provider: Option<Provider>,
+    #[serde(default)]
+    pub openai_api: OpenAIAPI,
+    #[serde(default)]
+    pub instructions: String,
+    // the chat's own, rather than a setting; left at the default by `of`
+    #[serde(default)]
+    pub overflow_strategy: OverflowStrategy
+}
+
+impl SettingsSnapshot {
+    pub fn of(settings: &Settings) -> SettingsSnapshot {
+        let api_key = settings.api_key.map(|index| &settings.api_keys[index]);
+        return SettingsSnapshot {
+            model: settings.model.clone(),
+            temperature: settings.temperature,
+            max_tokens: settings.max_tokens,
+            api_key_name: api_key.map(|api_key| api_key.name.clone()),
+            provider: api_key.map(|api_key| api_key.provider),
+            openai_api: settings.openai_api,
+            instructions: settings.instructions.clone(),
+            overflow_strategy: OverflowStrategy::default()
+        };
+    }
+
+    // the key is matched by name and left alone if it has since been deleted
+    pub fn restore(&self, settings: &mut Settings) {
+        settings.model = self.model.clone();
+        settings.temperature = self.temperature;
+        settings.max_tokens = self.max_tokens;
+        settings.openai_api = self.openai_api;
+        settings.instructions = self.instructions.clone();
+        if let Some(index) = settings.api_keys.iter().position(|api_key| Some(&api_key.name) == self.api_key_name.as_ref()) {
+            settings.api_key = Some(index);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatSummary {
+    pub id: i64,
+    pub title: String,
+    // comma separated, lowercase
+    pub tags: String
+}
+
+// a message matching a search, with the matched words in <b> tags
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub conversation_id: i64,
+    pub node: usize,
+    pub title: String,
+    pub snippet: String
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilters {
+    // part of the model name
+    pub model: String,
+    pub provider: Option<Provider>,
+    // only messages from the last this many days
+    pub days: Option<u64>,
+    pub tag: String
+}
+
+const DATE_RANGES: [(&str, Option<u64>); 5] = [
+    ("Any time", None),
+    ("Past day", Some(1)),
+    ("Past week", Some(7)),
+    ("Past month", Some(31)),
+    ("Past year", Some(365))
+];
+
+fn to_unix_seconds(time: SystemTime) -> i64 {
+    return time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+}
+
+fn from_json<T: DeserializeOwned>(column: usize, json: &str) -> rusqlite::Result<T> {
+    return serde_json::from_str(json)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(err)));
+}
+
+pub fn normalize_tags(tags: &str) -> String {
+    return tags
+        .split(',')
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<String>>()
+        .join(",");
+}
+
+// quotes every word so that FTS5 syntax in the search box is matched literally;
+// the last word also matches as a prefix, since it may still be being typed
+fn fts_query(text: &str) -> String {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return String::new();
+    }
+
+    return terms.join(" ") + "*";
+}
+
+// the snippet comes back with \x02 and \x03 around matches, which become bold once the rest is escaped
+fn snippet_markup(snippet: &str) -> String {
+    let mut markup = String::new();
+    for (index, part) in snippet.split('\u{2}').enumerate() {
+        match part.split_once('\u{3}') {
+            Some((matched, rest)) if index > 0 => {
+                markup += &format!("<b>{}</b>{}", markup_escape_text(matched), markup_escape_text(rest));
+            }
+            _ => markup += markup_escape_text(part).as_str()
+        }
+    }
+
+    return markup;
+}
+
+// the first line of the first user message, shortened
+pub fn default_title(messages: &[Message]) -> String {
+    let text = messages
+        .iter()
+        .find(|message| message.role == Role::User)
+        .or(messages.first())
+        .map(|message| message.text_content())
+        .unwrap_or_default();
+    let line = text.lines().find(|line| !line.trim().is_empty()).unwrap_or("Untitled").trim();
+    if line.chars().count() > 48 {
+        return line.chars().take(47).collect::<String>() + "…";
+    }
+
+    return line.to_string();
+}
+
+// every chat is kept in ~/.local/share/llm-playground/history.db, written through as it changes
+pub struct History {
+    connection: Connection
+}
+
+impl History {
+    pub fn open() -> rusqlite::Result<History> {
+        let data_dir = env::var("HOME").unwrap() + "/.local/share/llm-playground";
+        let _ = std::fs::create_dir_all(&data_dir);
+        let connection = Connection::open(data_dir + "/history.db")?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS conversations (
+                id INTEGER PRIMARY KEY,
+                title TEXT NOT NULL,
+                created INTEGER NOT NULL,
+                updated INTEGER NOT NULL,
+                tree TEXT NOT NULL,
+                settings TEXT NOT NULL
+            );"
+        )?;
+
+        let history = History { connection };
+        let version: i64 = history.connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < 1 {
+            let transaction = history.connection.unchecked_transaction()?;
+            history.connection.execute_batch(
+                "ALTER TABLE conversations ADD COLUMN tags TEXT NOT NULL DEFAULT '';
+                CREATE VIRTUAL TABLE message_index USING fts5(
+                    text,
+                    model UNINDEXED,
+                    timestamp UNINDEXED,
+                    conversation_id UNINDEXED,
+                    node UNINDEXED
+                );"
+            )?;
+            // chats saved before there was an index
+            for chat in history.list()? {
+                let (tree, settings) = history.load(chat.id)?;
+                history.index(chat.id, &tree, &settings)?;
+            }
+            history.connection.execute_batch("PRAGMA user_version = 1;")?;
+            transaction.commit()?;
+        }
+
+        return Ok(history);
+    }
+
+    // replaces the chat's rows in the search index; messages without a model or
+    // timestamp of their own take the chat's model and the time of saving
+    fn index(&self, id: i64, tree: &ConversationTree, settings: &SettingsSnapshot) -> rusqlite::Result<()> {
+        self.connection.execute("DELETE FROM message_index WHERE conversation_id = ?1", params![id])?;
+        let mut statement = self.connection.prepare_cached(
+            "INSERT INTO message_index (text, model, timestamp, conversation_id, node) VALUES (?1, ?2, ?3, ?4, ?5)"
+        )?;
+        let now = to_unix_seconds(SystemTime::now());
+        for (node, message) in tree.messages() {
+            statement.execute(params![
+                message.display_text(),
+                message.metadata.model.as_ref().unwrap_or(&settings.model),
+                message.metadata.timestamp.map(to_unix_seconds).unwrap_or(now),
+                id,
+                node as i64
+            ])?;
+        }
+
+        return Ok(());
+    }
+
+    // the row and its index entries; callers wrap it in a transaction
+    fn insert(&self, title: &str, tree: &ConversationTree, settings: &SettingsSnapshot) -> rusqlite::Result<i64> {
+        let now = to_unix_seconds(SystemTime::now());
+        self.connection.execute(
+            "INSERT INTO conversations (title, created, updated, tree, settings) VALUES (?1, ?2, ?2, ?3, ?4)",
+            params![title, now, serde_json::to_string(tree).unwrap(), serde_json::to_string(settings).unwrap()]
+        )?;
+        let id = self.connection.last_insert_rowid();
+        self.index(id, tree, settings)?;
+
+        return Ok(id);
+    }
+
+    pub fn create(&self, title: &str, tree: &ConversationTree, settings: &SettingsSnapshot) -> rusqlite::Result<i64> {
+        let transaction = self.connection.unchecked_transaction()?;
+        let id = self.insert(title, tree, settings)?;
+        transaction.commit()?;
+
+        return Ok(id);
+    }
+
+    // all of the chats or, if any fails, none of them
+    pub fn create_all(&self, chats: &[(String, ConversationTree)], settings: &SettingsSnapshot) -> rusqlite::Result<Vec<i64>> {
+        let transaction = self.connection.unchecked_transaction()?;
+        let ids = chats
+            .iter()
+            .map(|(title, tree)| self.insert(title, tree, settings))
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        transaction.commit()?;
+
+        return Ok(ids);
+    }
+
+    // leaves the row alone when neither the tree nor the settings changed, so opening a chat doesn't move it to the top;
+    // whether the row changed, or None if there's no chat with that id anymore and nothing was saved
+    pub fn save(&self, id: i64, tree: &ConversationTree, settings: &SettingsSnapshot) -> rusqlite::Result<Option<bool>> {
+        let transaction = self.connection.unchecked_transaction()?;
+        let changed = self.connection.execute(
+            "UPDATE conversations SET tree = ?2, settings = ?3, updated = ?4 WHERE id = ?1 AND (tree != ?2 OR settings != ?3)",
+            params![
+                id,
+                serde_json::to_string(tree).unwrap(),
+                serde_json::to_string(settings).unwrap(),
+                to_unix_seconds(SystemTime::now())
+            ]
+        )?;
+        if changed > 0 {
+            self.index(id, tree, settings)?;
+        }
+        // an unchanged chat also updates nothing
+        let exists = changed > 0
+            || self.connection.query_row("SELECT EXISTS(SELECT 1 FROM conversations WHERE id = ?1)", params![id], |row| row.get(0))?;
+        transaction.commit()?;
+
+        return Ok(exists.then_some(changed > 0));
+    }
+
+    // most recently changed first
+    pub fn list(&self) -> rusqlite::Result<Vec<ChatSummary>> {
+        let mut statement = self.connection.prepare("SELECT id, title, tags FROM conversations ORDER BY updated DESC, id DESC")?;
+        let chats = statement.query_map([], |row| {
+            return Ok(ChatSummary { id: row.get(0)?, title: row.get(1)?, tags: row.get(2)? });
+        })?;
+
+        return chats.collect();
+    }
+
+    pub fn load(&self, id: i64) -> rusqlite::Result<(ConversationTree, SettingsSnapshot)> {
+        return self.connection.query_row(
+            "SELECT tree, settings FROM conversations WHERE id = ?1",
+            params![id],
+            |row| {
+                let tree: String = row.get(0)?;
+                let settings: String = row.get(1)?;
+                return Ok((from_json(0, &tree)?, from_json(1, &settings)?));
+            }
+        );
+    }
+
+    pub fn rename(&self, id: i64, title: &str) -> rusqlite::Result<()> {
+        self.connection.execute("UPDATE conversations SET title = ?2 WHERE id = ?1", params![id, title])?;
+        return Ok(());
+    }
+
+    pub fn set_tags(&self, id: i64, tags: &str) -> rusqlite::Result<()> {
+        self.connection.execute("UPDATE conversations SET tags = ?2 WHERE id = ?1", params![id, normalize_tags(tags)])?;
+        return Ok(());
+    }
+
+    pub fn delete(&self, id: i64) -> rusqlite::Result<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        self.connection.execute("DELETE FROM message_index WHERE conversation_id = ?1", params![id])?;
+        self.connection.execute("DELETE FROM conversations WHERE id = ?1", params![id])?;
+        transaction.commit()?;
+
+        return Ok(());
+    }
+
+    // best matches first, by bm25
+    pub fn search(&self, text: &str, filters: &SearchFilters) -> rusqlite::Result<Vec<SearchResult>> {
+        let query = fts_query(text);
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+        let model = (!filters.model.trim().is_empty()).then(|| format!("%{}%", filters.model.trim()));
+        let provider = filters.provider.map(|provider| serde_json::to_value(provider).unwrap().as_str().unwrap_or_default().to_string());
+        let since = filters.days.map(|days| to_unix_seconds(SystemTime::now() - Duration::from_secs(days * 86400)));
+        let tag = normalize_tags(&filters.tag);
+        let tag = (!tag.is_empty()).then_some(tag);
+
+        let mut statement = self.connection.prepare(
+            "SELECT message_index.conversation_id, message_index.node, conversations.title,
+                snippet(message_index, 0, char(2), char(3), '…', 16)
+            FROM message_index JOIN conversations ON conversations.id = message_index.conversation_id
+            WHERE message_index MATCH ?1
+                AND (?2 IS NULL OR message_index.model LIKE ?2)
+                AND (?3 IS NULL OR json_extract(conversations.settings, '$.provider') = ?3)
+                AND (?4 IS NULL OR message_index.timestamp >= ?4)
+                AND (?5 IS NULL OR ',' || conversations.tags || ',' LIKE '%,' || ?5 || ',%')
+            ORDER BY rank
+            LIMIT 100"
+        )?;
+        let results = statement.query_map(params![query, model, provider, since, tag], |row| {
+            let node: i64 = row.get(1)?;
+            let snippet: String = row.get(3)?;
+            return Ok(SearchResult {
+                conversation_id: row.get(0)?,
+                node: node as usize,
+                title: row.get(2)?,
+                snippet: snippet_markup(&snippet)
+            });
+        })?;
+
+        return results.collect();
+    }
+}
+
+fn ChatOptions(
+    history: Rc<History>,
+    chat: &ChatSummary,
+    chats: Mutable<Vec<ChatSummary>>,
+    error: Mutable<String>,
+    delete_chat: Rc<dyn Fn(i64)>
+) -> gtk::MenuButton {
+    let menu_button = gtk::MenuButton::new();
+    menu_button.set_label("…");
+    menu_button.set_has_frame(false);
+    menu_button.set_css_classes(&["exchange-header-button"]);
+
+    let grid = gtk::Grid::new();
+    grid.set_css_classes(&["settings-box"]);
+    grid.set_row_spacing(10);
+    grid.set_column_spacing(10);
+    let label = Label::new(Some("Title:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 0, 1, 1);
+    let title_entry = gtk::Entry::new();
+    title_entry.set_text(&chat.title);
+    grid.attach(&title_entry, 1, 0, 1, 1);
+    let label = Label::new(Some("Tags:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 1, 1, 1);
+    let tags_entry = gtk::Entry::new();
+    tags_entry.set_placeholder_text(Some("comma separated"));
+    tags_entry.set_text(&chat.tags.replace(',', ", "));
+    grid.attach(&tags_entry, 1, 1, 1, 1);
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let save_button = gtk::Button::with_label("Save");
+    hbox.append(&save_button);
+    let delete_button = gtk::Button::with_label("Delete");
+    hbox.append(&delete_button);
+    grid.attach(&hbox, 0, 2, 2, 1);
+
+    let popover = gtk::Popover::new();
+    popover.set_child(Some(&grid));
+    menu_button.set_popover(Some(&popover));
+
+    let id = chat.id;
+    let save = Rc::new(clone!(@strong history, @strong chats, @strong error, @weak popover, @weak title_entry, @weak tags_entry => move || {
+        let title = title_entry.text().trim().to_string();
+        if title.is_empty() {
+            return;
+        }
+        popover.popdown();
+        let saved = history.rename(id, &title).and_then(|_| history.set_tags(id, &tags_entry.text()));
+        match saved.and_then(|_| history.list()) {
+            Ok(list) => chats.set(list),
+            Err(err) => error.set(format!("Couldn't save the chat: {}", err))
+        }
+    }));
+    save_button.connect_clicked(clone!(@strong save => move |_| save()));
+    title_entry.connect_activate(clone!(@strong save => move |_| save()));
+    tags_entry.connect_activate(move |_| save());
+
+    delete_button.connect_clicked(clone!(@weak popover => move |_| {
+        popover.popdown();
+        delete_chat(id);
+    }));
+
+    return menu_button;
+}
+
+fn SearchFiltersOption(filters: Mutable<SearchFilters>) -> gtk::MenuButton {
+    let menu_button = gtk::MenuButton::new();
+    menu_button.set_label("Filters");
+    menu_button.set_css_classes(&["exchange-header-button"]);
+
+    let grid = gtk::Grid::new();
+    grid.set_css_classes(&["settings-box"]);
+    grid.set_row_spacing(10);
+    grid.set_column_spacing(10);
+
+    let label = Label::new(Some("Model:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 0, 1, 1);
+    let model_entry = gtk::Entry::new();
+    grid.attach(&model_entry, 1, 0, 1, 1);
+    model_entry.connect_changed(clone!(@strong filters => move |entry| {
+        filters.lock_mut().model = entry.text().to_string();
+    }));
+
+    let label = Label::new(Some("Provider:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 1, 1, 1);
+    let mut provider_names = vec!["Any".to_string()];
+    provider_names.extend(PROVIDERS.iter().map(|provider| provider.to_string()));
+    let provider_names: Vec<&str> = provider_names.iter().map(|name| name.as_str()).collect();
+    let provider_dropdown = gtk::DropDown::from_strings(&provider_names);
+    grid.attach(&provider_dropdown, 1, 1, 1, 1);
+    provider_dropdown.connect_selected_notify(clone!(@strong filters => move |dropdown| {
+        // the first entry is "Any"
+        let selected = dropdown.selected() as usize;
+        filters.lock_mut().provider = selected.checked_sub(1).and_then(|index| PROVIDERS.get(index).copied());
+    }));
+
+    let label = Label::new(Some("Date:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 2, 1, 1);
+    let range_names: Vec<&str> = DATE_RANGES.iter().map(|(name, _)| *name).collect();
+    let date_dropdown = gtk::DropDown::from_strings(&range_names);
+    grid.attach(&date_dropdown, 1, 2, 1, 1);
+    date_dropdown.connect_selected_notify(clone!(@strong filters => move |dropdown| {
+        filters.lock_mut().days = DATE_RANGES.get(dropdown.selected() as usize).and_then(|(_, days)| *days);
+    }));
+
+    let label = Label::new(Some("Tag:"));
+    label.set_halign(gtk::Align::Start);
+    grid.attach(&label, 0, 3, 1, 1);
+    let tag_entry = gtk::Entry::new();
+    grid.attach(&tag_entry, 1, 3, 1, 1);
+    tag_entry.connect_changed(move |entry| {
+        filters.lock_mut().tag = entry.text().to_string();
+    });
+
+    let popover = gtk::Popover::new();
+    popover.set_child(Some(&grid));
+    menu_button.set_popover(Some(&popover));
+
+    return menu_button;
+}
+
+fn SearchResultRow(result: &SearchResult, open_chat: Rc<dyn Fn(Option<i64>, Option<usize>)>) -> gtk::Button {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    let title_label = Label::new(Some(&result.title));
+    title_label.set_css_classes(&["search-result-title"]);
+    title_label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+    title_label.set_xalign(0.0);
+    vbox.append(&title_label);
+    let snippet_label = Label::new(None);
+    snippet_label.set_markup(&result.snippet);
+    snippet_label.set_wrap(true);
+    snippet_label.set_wrap_mode(gtk::pango::WrapMode::WordChar);
+    snippet_label.set_xalign(0.0);
+    vbox.append(&snippet_label);
+
+    let button = gtk::Button::new();
+    button.set_child(Some(&vbox));
+    button.set_has_frame(false);
+    let (id, node) = (result.conversation_id, result.node);
+    button.connect_clicked(move |_| open_chat(Some(id), Some(node)));
+
+    return button;
+}
+
+// the saved chats, newest first, or the messages matching a search;
+// clicking one opens it in place of the current chat
+pub fn HistorySidebar(
+    history: Rc<History>,
+    chats: Mutable<Vec<ChatSummary>>,
+    conversation: Conversation,
+    streaming: Mutable<bool>,
+    error: Mutable<String>,
+    open_chat: Rc<dyn Fn(Option<i64>, Option<usize>)>
+) -> gtk::Box {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+    vbox.set_css_classes(&["history-sidebar"]);
+    vbox.set_size_request(180, -1);
+
+    let title = Label::new(Some("Chats"));
+    title.set_css_classes(&["title"]);
+    title.set_halign(gtk::Align::Start);
+    vbox.append(&title);
+
+    let query = Mutable::new(String::new());
+    let filters = Mutable::new(SearchFilters::default());
+    let search_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let search_entry = gtk::SearchEntry::new();
+    search_entry.set_placeholder_text(Some("Search all chats"));
+    search_entry.set_hexpand(true);
+    search_entry.connect_search_changed(clone!(@strong query => move |entry| {
+        query.set(entry.text().to_string());
+    }));
+    search_box.append(&search_entry);
+    search_box.append(&SearchFiltersOption(filters.clone()));
+    vbox.append(&search_box);
+
+    let listbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    let scrolled_window = gtk::ScrolledWindow::new();
+    scrolled_window.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled_window.set_child(Some(&listbox));
+    scrolled_window.set_vexpand(true);
+    vbox.append(&scrolled_window);
+
+    // switching chats under a streaming reply would attach it to the wrong one
+    glib::spawn_future_local(streaming.signal().for_each({
+        let listbox = listbox.clone();
+        move |streaming| {
+            listbox.set_sensitive(!streaming);
+            async {}
+        }
+    }));
+
+    let chat_id = conversation.saved_id.clone();
+    let delete_chat: Rc<dyn Fn(i64)> = Rc::new(clone!(@strong history, @strong chats, @strong conversation, @strong chat_id, @strong error, @strong open_chat => move |id| {
+        match history.delete(id).and_then(|_| history.list()) {
+            Ok(list) => chats.set(list),
+            Err(err) => error.set(format!("Couldn't delete the chat: {}", err))
+        }
+        conversation.forget_saved(id);
+        if chat_id.get() == Some(id) {
+            open_chat(None, None);
+        }
+    }));
+
+    // saved chats change as messages come in, so searches rerun along with the list
+    let state = map_ref! {
+        let chats = chats.signal_cloned(),
+        let current = chat_id.signal(),
+        let query = query.signal_cloned(),
+        let filters = filters.signal_cloned() => (chats.clone(), *current, query.clone(), filters.clone())
+    };
+    glib::spawn_future_local(state.for_each(move |(chat_list, current, query, filters)| {
+        while let Some(child) = listbox.first_child() {
+            listbox.remove(&child);
+        }
+
+        if !query.trim().is_empty() {
+            match history.search(&query, &filters) {
+                Ok(results) if results.is_empty() => listbox.append(&Label::new(Some("No matches"))),
+                Ok(results) => {
+                    for result in results {
+                        listbox.append(&SearchResultRow(&result, open_chat.clone()));
+                    }
+                }
+                Err(err) => error.set(format!("Couldn't search: {}", err))
+            }
+        } else {
+            for chat in chat_list {
+                let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+
+                let title_label = Label::new(Some(&chat.title));
+                title_label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+                title_label.set_xalign(0.0);
+                let open_button = gtk::Button::new();
+                open_button.set_child(Some(&title_label));
+                open_button.set_has_frame(false);
+                open_button.set_hexpand(true);
+                if current == Some(chat.id) {
+                    open_button.add_css_class("current-chat");
+                }
+                let id = chat.id;
+                open_button.connect_clicked(clone!(@strong open_chat => move |_| open_chat(Some(id), None)));
+                hbox.append(&open_button);
+
+                hbox.append(&ChatOptions(history.clone(), &chat, chats.clone(), error.clone(), delete_chat.clone()));
+
+                listbox.append(&hbox);
+            }
+        }
+        async {}
+    }));
+
+    return vbox;
+}
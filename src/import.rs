@@ -0,0 +1,396 @@
+use std::{rc::Rc, time::{Duration, UNIX_EPOCH}};
+
+use futures_signals::signal::{Mutable, SignalExt};
+use gtk::{gio, glib::{self, clone}, prelude::*, Label, Window};
+use serde_json::Value;
+
+use crate::{
+    conversation::ConversationTree,
+    export::{ConversationExport, EXPORT_FORMAT},
+    history::{default_title, ChatSummary, History, SettingsSnapshot},
+    message::{ContentBlock, Message, Metadata, Role},
+    settings::Settings,
+    util::get_buffer_content
+};
+
+pub struct ImportedConversation {
+    pub title: String,
+    pub messages: Vec<Message>
+}
+
+fn role_of(role: &str) -> Option<Role> {
+    return match role {
+        "system" | "developer" => Some(Role::System),
+        "user" | "human" => Some(Role::User),
+        "assistant" => Some(Role::Assistant),
+        "tool" => Some(Role::Tool),
+        _ => None
+    };
+}
+
+// a string, or an array of text blocks as both APIs allow
+fn content_text(content: &Value) -> String {
+    return match content {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block["text"].as_str().or(block.as_str()))
+            .collect::<Vec<&str>>()
+            .join("\n\n"),
+        _ => String::new()
+    };
+}
+
+// one block of an OpenAI or Anthropic content array
+fn content_block(block: &Value) -> Option<ContentBlock> {
+    return match block["type"].as_str()? {
+        "text" | "input_text" | "output_text" => Some(ContentBlock::Text { text: block["text"].as_str()?.to_string() }),
+        "image_url" => {
+            let url = block["image_url"]["url"].as_str().or(block["image_url"].as_str())?;
+            match url.strip_prefix("data:").and_then(|url| url.split_once(";base64,")) {
+                Some((media_type, data)) => Some(ContentBlock::Image { media_type: media_type.to_string(), data: data.to_string() }),
+                // only inline images can be sent on to other providers
+                None => Some(ContentBlock::Text { text: format!("[image: {}]", url) })
+            }
+        }
+        "image" if block["source"]["type"] == "base64" => Some(ContentBlock::Image {
+            media_type: block["source"]["media_type"].as_str()?.to_string(),
+            data: block["source"]["data"].as_str()?.to_string()
+        }),
+        "tool_use" => Some(ContentBlock::ToolUse {
+            id: block["id"].as_str()?.to_string(),
+            name: block["name"].as_str()?.to_string(),
+            input: block["input"].clone()
+        }),
+        "tool_result" => Some(ContentBlock::ToolResult {
+            tool_use_id: block["tool_use_id"].as_str()?.to_string(),
+            content: content_text(&block["content"])
+        }),
+        _ => None
+    };
+}
+
+// an entry of an OpenAI or Anthropic messages array
+fn raw_message(value: &Value) -> Result<Message, String> {
+    let role = value["role"].as_str().and_then(role_of).ok_or(format!("Unknown role in {}", value))?;
+    let mut content: Vec<ContentBlock> = match &value["content"] {
+        Value::String(text) => vec![ContentBlock::Text { text: text.clone() }],
+        Value::Array(blocks) => blocks.iter().filter_map(content_block).collect(),
+        _ => vec![]
+    };
+    for call in value["tool_calls"].as_array().into_iter().flatten() {
+        let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+        content.push(ContentBlock::ToolUse {
+            id: call["id"].as_str().unwrap_or_default().to_string(),
+            name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+            input: serde_json::from_str(arguments).unwrap_or(Value::String(arguments.to_string()))
+        });
+    }
+    // chat completions send tool results as messages of their own
+    if let Some(tool_call_id) = value["tool_call_id"].as_str() {
+        content = vec![ContentBlock::ToolResult { tool_use_id: tool_call_id.to_string(), content: content_text(&value["content"]) }];
+    }
+
+    return Ok(Message { role, content, metadata: Metadata::default() });
+}
+
+// a bare messages array, or an object with one and maybe Anthropic's separate system prompt
+fn raw_conversation(value: &Value) -> Result<ImportedConversation, String> {
+    let entries = match value {
+        Value::Array(entries) => entries,
+        _ => value["messages"].as_array().ok_or("Expected a messages array.")?
+    };
+    let mut messages = vec![];
+    let system = content_text(&value["system"]);
+    if !system.is_empty() {
+        messages.push(Message::text(Role::System, &system));
+    }
+    for entry in entries {
+        messages.push(raw_message(entry)?);
+    }
+
+    return Ok(ImportedConversation { title: default_title(&messages), messages });
+}
+
+fn chatgpt_message(message: &Value) -> Option<Message> {
+    // tool calls inside ChatGPT have no ids to pair them with, so only the conversation itself is kept
+    let role = role_of(message["author"]["role"].as_str()?).filter(|role| *role != Role::Tool)?;
+    if message["metadata"]["is_visually_hidden_from_conversation"].as_bool() == Some(true) {
+        return None;
+    }
+    let content = &message["content"];
+    let text = match content["parts"].as_array() {
+        // parts that aren't strings are uploads, which the export doesn't include
+        Some(parts) => parts.iter().filter_map(|part| part.as_str()).collect::<Vec<&str>>().join("\n\n"),
+        None => content["text"].as_str().unwrap_or_default().to_string()
+    };
+    if text.trim().is_empty() {
+        return None;
+    }
+
+    return Some(Message {
+        role,
+        content: vec![ContentBlock::Text { text }],
+        metadata: Metadata {
+            model: message["metadata"]["model_slug"].as_str().map(|model| model.to_string()),
+            timestamp: message["create_time"].as_f64().map(|seconds| UNIX_EPOCH + Duration::from_secs_f64(seconds.max(0.0))),
+            ..Metadata::default()
+        }
+    });
+}
+
+// ChatGPT stores every edit as a tree; the branch that was last shown ends at current_node
+fn chatgpt_conversation(conversation: &Value) -> Result<ImportedConversation, String> {
+    let mapping = conversation["mapping"].as_object().ok_or("Expected a ChatGPT mapping.")?;
+    let leaf = conversation["current_node"].as_str().or_else(|| {
+        mapping.iter().rev().find(|(_, node)| node["children"].as_array().is_none_or(|children| children.is_empty())).map(|(id, _)| id.as_str())
+    });
+
+    let mut messages = vec![];
+    let mut node_id = leaf;
+    // the count guards against a malformed export whose parents loop
+    for _ in 0..mapping.len() {
+        let Some(node) = node_id.and_then(|id| mapping.get(id)) else {
+            break;
+        };
+        messages.extend(chatgpt_message(&node["message"]));
+        node_id = node["parent"].as_str();
+    }
+    messages.reverse();
+
+    let title = conversation["title"].as_str().map(|title| title.to_string()).unwrap_or(default_title(&messages));
+    return Ok(ImportedConversation { title, messages });
+}
+
+fn claude_conversation(conversation: &Value) -> Result<ImportedConversation, String> {
+    let chat_messages = conversation["chat_messages"].as_array().ok_or("Expected Claude chat_messages.")?;
+    let mut messages = vec![];
+    for chat_message in chat_messages {
+        let role = match chat_message["sender"].as_str() {
+            Some("human") => Role::User,
+            Some("assistant") => Role::Assistant,
+            _ => continue
+        };
+        let mut text = match chat_message["content"].as_array() {
+            Some(_) => content_text(&chat_message["content"]),
+            None => chat_message["text"].as_str().unwrap_or_default().to_string()
+        };
+        // pasted and uploaded files come with their extracted text
+        for attachment in chat_message["attachments"].as_array().into_iter().flatten() {
+            if let Some(extracted) = attachment["extracted_content"].as_str() {
+                text += &format!("\n\n[{}]\n{}", attachment["file_name"].as_str().unwrap_or("attachment"), extracted);
+            }
+        }
+        if !text.trim().is_empty() {
+            messages.push(Message { role, content: vec![ContentBlock::Text { text }], metadata: Metadata::default() });
+        }
+    }
+
+    let title = conversation["name"].as_str().filter(|name| !name.is_empty()).map(|name| name.to_string());
+    return Ok(ImportedConversation { title: title.unwrap_or(default_title(&messages)), messages });
+}
+
+fn parse_value(value: &Value) -> Result<Vec<ImportedConversation>, String> {
+    if value["format"] == EXPORT_FORMAT {
+        let export: ConversationExport = serde_json::from_value(value.clone()).map_err(|err| err.to_string())?;
+        return Ok(vec![ImportedConversation { title: export.title, messages: export.messages }]);
+    }
+
+    let items: Vec<&Value> = match value {
+        Value::Array(items) => items.iter().collect(),
+        _ => vec![value]
+    };
+    let Some(first) = items.first() else {
+        return Ok(vec![]);
+    };
+    if first.get("mapping").is_some() {
+        return items.into_iter().map(chatgpt_conversation).collect();
+    }
+    if first.get("chat_messages").is_some() {
+        return items.into_iter().map(claude_conversation).collect();
+    }
+    if first.get("role").is_some() {
+        return Ok(vec![raw_conversation(value)?]);
+    }
+    if first.get("messages").is_some() {
+        return items.into_iter().map(raw_conversation).collect();
+    }
+
+    return Err("Couldn't tell what kind of export this is.".to_string());
+}
+
+// recognizes a ChatGPT or Claude.ai data export, an OpenAI or Anthropic messages array
+// or request body, a fine-tuning JSONL file, or this app's own JSON export
+pub fn parse_import(text: &str) -> Result<Vec<ImportedConversation>, String> {
+    let conversations = match serde_json::from_str::<Value>(text.trim()) {
+        Ok(value) => parse_value(&value)?,
+        Err(err) => {
+            // JSONL holds one conversation per line
+            let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+            let values: Vec<Value> = lines
+                .iter()
+                .map(|line| serde_json::from_str(line))
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("Not valid JSON: {}", err))?;
+            let mut conversations = vec![];
+            for value in &values {
+                conversations.extend(parse_value(value)?);
+            }
+            conversations
+        }
+    };
+
+    let conversations: Vec<ImportedConversation> = conversations
+        .into_iter()
+        .filter(|conversation| !conversation.messages.is_empty())
+        .collect();
+    if conversations.is_empty() {
+        return Err("There are no messages to import.".to_string());
+    }
+
+    return Ok(conversations);
+}
+
+// saves the conversations as chats and returns the id of the first
+fn save_imported(
+    conversations: Vec<ImportedConversation>,
+    history: &History,
+    settings: &Settings,
+    chats: &Mutable<Vec<ChatSummary>>
+) -> rusqlite::Result<i64> {
+    let snapshot = SettingsSnapshot::of(settings);
+    let trees: Vec<(String, ConversationTree)> = conversations
+        .into_iter()
+        .map(|conversation| {
+            let mut tree = ConversationTree::default();
+            for message in conversation.messages {
+                tree.push(message);
+            }
+            (conversation.title, tree)
+        })
+        .collect();
+    let ids = history.create_all(&trees, &snapshot)?;
+    chats.set(history.list()?);
+
+    return Ok(ids.first().copied().unwrap_or_default());
+}
+
+fn ImportWindow(
+    history: Rc<History>,
+    settings: Mutable<Settings>,
+    chats: Mutable<Vec<ChatSummary>>,
+    open_chat: Rc<dyn Fn(Option<i64>, Option<usize>)>
+) -> Window {
+    let window = Window::new();
+    window.set_css_classes(&["popup-window"]);
+    window.set_title(Some("Import Conversations"));
+    window.set_default_size(500, 450);
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+
+    let error_label = Label::new(None);
+    error_label.set_css_classes(&["error-label"]);
+    error_label.set_wrap(true);
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let label = Label::new(Some(
+        "Paste a ChatGPT or Claude.ai export, or an OpenAI or Anthropic messages array, or open a file."
+    ));
+    label.set_wrap(true);
+    label.set_xalign(0.0);
+    vbox.append(&label);
+
+    let text_view = gtk::TextView::new();
+    text_view.set_monospace(true);
+    text_view.set_wrap_mode(gtk::WrapMode::WordChar);
+    let scrolled_window = gtk::ScrolledWindow::new();
+    scrolled_window.set_child(Some(&text_view));
+    scrolled_window.set_vexpand(true);
+    vbox.append(&scrolled_window);
+
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let file_button = gtk::Button::with_label("Open File…");
+    hbox.append(&file_button);
+    let spacer = Label::new(None);
+    spacer.set_hexpand(true);
+    hbox.append(&spacer);
+    let import_button = gtk::Button::with_label("Import");
+    hbox.append(&import_button);
+    vbox.append(&hbox);
+
+    let error = Mutable::new(String::new());
+    glib::spawn_future_local(error.signal_cloned().for_each({
+        let error_label = error_label.clone();
+        move |error| {
+            error_label.set_text(&error);
+            error_label.set_visible(!error.is_empty());
+            async {}
+        }
+    }));
+
+    let import = Rc::new(clone!(@weak window, @strong error => move |text: &str| {
+        let saved = parse_import(text).and_then(|conversations| {
+            save_imported(conversations, &history, &settings.lock_ref(), &chats).map_err(|err| err.to_string())
+        });
+        match saved {
+            Ok(id) => {
+                open_chat(Some(id), None);
+                window.close();
+            }
+            Err(err) => error.set(err)
+        }
+    }));
+
+    import_button.connect_clicked(clone!(@strong import => move |_| {
+        import(&get_buffer_content(&text_view.buffer()));
+    }));
+
+    // data exports can be far too large to paste, so files are imported straight away
+    file_button.connect_clicked(clone!(@weak window => move |_| {
+        let dialog = gtk::FileDialog::new();
+        dialog.set_title("Import Conversations");
+        let import = import.clone();
+        let error = error.clone();
+        glib::spawn_future_local(async move {
+            let file: Option<gio::File> = dialog.open_future(Some(&window)).await.ok();
+            if let Some(path) = file.and_then(|file| file.path()) {
+                match std::fs::read_to_string(&path) {
+                    Ok(text) => import(&text),
+                    Err(err) => error.set(format!("Couldn't read {}: {}", path.display(), err))
+                }
+            }
+        });
+    }));
+
+    window.set_child(Some(&vbox));
+    return window;
+}
+
+pub fn ImportButton(
+    history: Rc<History>,
+    settings: Mutable<Settings>,
+    chats: Mutable<Vec<ChatSummary>>,
+    streaming: Mutable<bool>,
+    open_chat: Rc<dyn Fn(Option<i64>, Option<usize>)>
+) -> gtk::Button {
+    let button = gtk::Button::with_label("Import");
+
+    // opening the imported chat would pull the conversation out from under a streaming reply
+    glib::spawn_future_local(streaming.signal().for_each({
+        let button = button.clone();
+        move |streaming| {
+            button.set_sensitive(!streaming);
+            async {}
+        }
+    }));
+
+    button.connect_clicked(move |_| {
+        ImportWindow(history.clone(), settings.clone(), chats.clone(), open_chat.clone()).present();
+    });
+
+    return button;
+}
+
+#[cfg(test)]
+mod tests;
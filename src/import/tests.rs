@@ -0,0 +1,110 @@
+// Parses trimmed-down exports from each supported source, kept under tests/imports.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::json;
+
+use super::parse_import;
+use crate::{history::default_title, message::{ContentBlock, Role}};
+
+const CHATGPT: &str = include_str!("../../tests/imports/chatgpt.json");
+const CLAUDE: &str = include_str!("../../tests/imports/claude.json");
+const OPENAI_TOOLS: &str = include_str!("../../tests/imports/openai_tools.json");
+const ANTHROPIC: &str = include_str!("../../tests/imports/anthropic.json");
+const FINETUNE: &str = include_str!("../../tests/imports/finetune.jsonl");
+
+fn text(text: &str) -> ContentBlock {
+    return ContentBlock::Text { text: text.to_string() };
+}
+
+// follows current_node past the regenerated reply, leaving out the hidden system prompt,
+// the tool message and the uploaded image
+#[test]
+fn chatgpt_current_branch() {
+    let conversations = parse_import(CHATGPT).unwrap();
+    assert_eq!(conversations.len(), 1);
+    let conversation = &conversations[0];
+
+    assert_eq!(conversation.title, "Trip planning");
+    let roles: Vec<Role> = conversation.messages.iter().map(|message| message.role).collect();
+    assert_eq!(roles, vec![Role::User, Role::Assistant]);
+    assert_eq!(conversation.messages[0].content, vec![text("Where should I go in May?")]);
+    assert_eq!(conversation.messages[0].metadata.timestamp, Some(UNIX_EPOCH + Duration::from_secs_f64(1_700_000_000.5)));
+    assert_eq!(conversation.messages[1].content, vec![text("Try Lisbon.")]);
+    assert_eq!(conversation.messages[1].metadata.model.as_deref(), Some("gpt-4o"));
+}
+
+#[test]
+fn claude_attachments() {
+    let conversations = parse_import(CLAUDE).unwrap();
+    assert_eq!(conversations.len(), 2);
+
+    let first = &conversations[0];
+    assert_eq!(first.title, "Bug report");
+    assert_eq!(first.messages[0].role, Role::User);
+    assert_eq!(first.messages[0].content, vec![text("Why does this fail?\n\n[log.txt]\nError: boom")]);
+    assert_eq!(first.messages[1].role, Role::Assistant);
+    assert_eq!(first.messages[1].content, vec![text("The config is missing.")]);
+
+    // an unnamed chat is titled like a new one
+    let second = &conversations[1];
+    assert_eq!(second.messages[0].content, vec![text("Hello there")]);
+    assert_eq!(second.title, default_title(&second.messages));
+}
+
+#[test]
+fn openai_tool_calls() {
+    let conversations = parse_import(OPENAI_TOOLS).unwrap();
+    assert_eq!(conversations.len(), 1);
+    let messages = &conversations[0].messages;
+
+    let roles: Vec<Role> = messages.iter().map(|message| message.role).collect();
+    assert_eq!(roles, vec![Role::System, Role::User, Role::Assistant, Role::Tool, Role::Assistant]);
+    assert_eq!(
+        messages[2].content,
+        vec![ContentBlock::ToolUse { id: "call_1".to_string(), name: "get_weather".to_string(), input: json!({ "city": "Paris" }) }]
+    );
+    assert_eq!(
+        messages[3].content,
+        vec![ContentBlock::ToolResult { tool_use_id: "call_1".to_string(), content: "18°C and sunny".to_string() }]
+    );
+    assert_eq!(conversations[0].title, "Weather in Paris?");
+}
+
+#[test]
+fn anthropic_request_with_system() {
+    let conversations = parse_import(ANTHROPIC).unwrap();
+    assert_eq!(conversations.len(), 1);
+    let messages = &conversations[0].messages;
+
+    assert_eq!(messages[0].role, Role::System);
+    assert_eq!(messages[0].content, vec![text("You are terse.")]);
+    assert_eq!(
+        messages[1].content,
+        vec![
+            text("What is this?"),
+            ContentBlock::Image { media_type: "image/png".to_string(), data: "iVBORw0KGgo=".to_string() }
+        ]
+    );
+    assert_eq!(messages[2].role, Role::Assistant);
+    assert_eq!(messages[2].content, vec![text("A PNG header.")]);
+}
+
+// one conversation per line, with blank lines skipped
+#[test]
+fn jsonl_lines() {
+    let conversations = parse_import(FINETUNE).unwrap();
+    assert_eq!(conversations.len(), 2);
+
+    assert_eq!(conversations[0].messages.len(), 3);
+    assert_eq!(conversations[0].messages[0].role, Role::System);
+    assert_eq!(conversations[0].title, "Hello");
+    assert_eq!(conversations[1].messages[1].content, vec![text("Merci")]);
+}
+
+#[test]
+fn rejects_unknown_and_empty() {
+    assert_eq!(parse_import(r#"{"foo": 1}"#).err(), Some("Couldn't tell what kind of export this is.".to_string()));
+    assert_eq!(parse_import("[]").err(), Some("There are no messages to import.".to_string()));
+    assert!(parse_import("{ not json").unwrap_err().starts_with("Not valid JSON:"));
+}
@@ -0,0 +1,188 @@
+use std::time::SystemTime;
+
+use futures_signals::signal_vec::{MutableVec, SignalVecExt, VecDiff};
+use gtk::{glib, prelude::*, Button, Label, ScrolledWindow};
+
+use crate::util::format_timestamp;
+
+#[derive(Debug, Clone)]
+pub enum LogEntry {
+    Request {
+        timestamp: SystemTime,
+        url: String,
+        headers: Vec<(String, String)>,
+        body: String
+    },
+    Event {
+        timestamp: SystemTime,
+        event: String,
+        data: String
+    },
+    Status {
+        timestamp: SystemTime,
+        status: String
+    }
+}
+
+impl LogEntry {
+    pub fn request(request: &reqwest::Request, body: &str) -> LogEntry {
+        let headers = request.headers()
+            .iter()
+            .map(|(name, value)| (
+                name.to_string(),
+                redact_header(name.as_str(), value.to_str().unwrap_or("<binary>"))
+            ))
+            .collect();
+
+        return LogEntry::Request {
+            timestamp: SystemTime::now(),
+            url: format!("{} {}", request.method(), request.url()),
+            headers,
+            body: body.to_string()
+        };
+    }
+
+    pub fn event(event: &str, data: &str) -> LogEntry {
+        return LogEntry::Event {
+            timestamp: SystemTime::now(),
+            event: event.to_string(),
+            data: data.to_string()
+        };
+    }
+
+    pub fn status(status: impl ToString) -> LogEntry {
+        return LogEntry::Status { timestamp: SystemTime::now(), status: status.to_string() };
+    }
+
+    fn title(&self) -> String {
+        match self {
+            LogEntry::Request { timestamp, url, .. } => format!("{}  Request  {}", format_timestamp(*timestamp), url),
+            LogEntry::Event { timestamp, event, .. } => format!("{}  Event  {}", format_timestamp(*timestamp), event),
+            LogEntry::Status { timestamp, status } => format!("{}  Status  {}", format_timestamp(*timestamp), status)
+        }
+    }
+
+    fn content(&self) -> String {
+        match self {
+            LogEntry::Request { url, headers, body, .. } => {
+                let mut content = url.clone() + "\n";
+                for (name, value) in headers {
+                    content += &format!("{}: {}\n", name, value);
+                }
+                content + "\n" + body
+            },
+            LogEntry::Event { data, .. } => data.clone(),
+            LogEntry::Status { status, .. } => status.clone()
+        }
+    }
+}
+
+fn redact_header(name: &str, value: &str) -> String {
+    match name.to_lowercase().as_str() {
+        "authorization" | "x-api-key" | "api-key" | "x-amz-security-token" => "[redacted]".to_string(),
+        _ => value.to_string()
+    }
+}
+
+fn copy_to_clipboard(widget: &impl IsA<gtk::Widget>, text: &str) {
+    widget.clipboard().set_text(text);
+}
+
+fn LogEntryRow(entry: &LogEntry) -> gtk::Box {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    vbox.set_css_classes(&["inspector-entry"]);
+
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let title = Label::new(Some(&entry.title()));
+    title.set_xalign(0.0);
+    title.set_hexpand(true);
+    title.set_ellipsize(gtk::pango::EllipsizeMode::End);
+    hbox.append(&title);
+
+    let copy_button = Button::with_label("Copy");
+    copy_button.set_css_classes(&["flat", "exchange-header-button"]);
+    let content = entry.content();
+    copy_button.connect_clicked({
+        let content = content.clone();
+        move |button| copy_to_clipboard(button, &content)
+    });
+    hbox.append(&copy_button);
+    vbox.append(&hbox);
+
+    let label = Label::new(Some(&content));
+    label.set_css_classes(&["inspector-content"]);
+    label.set_xalign(0.0);
+    label.set_wrap(true);
+    label.set_wrap_mode(gtk::pango::WrapMode::WordChar);
+    label.set_selectable(true);
+    vbox.append(&label);
+
+    return vbox;
+}
+
+pub fn Inspector(stack: gtk::Stack, log: MutableVec<LogEntry>) -> gtk::Box {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    vbox.set_css_classes(&["top-level-box"]);
+
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+    let back_button = Button::with_label("Back");
+    back_button.connect_clicked(move |_| stack.set_visible_child_name("chat"));
+    hbox.append(&back_button);
+
+    let title = Label::new(Some("Inspector"));
+    title.set_css_classes(&["title"]);
+    title.set_hexpand(true);
+    hbox.append(&title);
+
+    let copy_all_button = Button::with_label("Copy all");
+    copy_all_button.connect_clicked({
+        let log = log.clone();
+        move |button| {
+            let text = log.lock_ref()
+                .iter()
+                .map(|entry| entry.title() + "\n" + &entry.content())
+                .collect::<Vec<String>>()
+                .join("\n\n");
+            copy_to_clipboard(button, &text);
+        }
+    });
+    hbox.append(&copy_all_button);
+
+    let clear_button = Button::with_label("Clear");
+    clear_button.connect_clicked({
+        let log = log.clone();
+        move |_| log.lock_mut().clear()
+    });
+    hbox.append(&clear_button);
+    vbox.append(&hbox);
+
+    let listbox = gtk::Box::new(gtk::Orientation::Vertical, 8);
+    let scrolled_window = ScrolledWindow::new();
+    scrolled_window.set_policy(gtk::PolicyType::Never, gtk::PolicyType::Automatic);
+    scrolled_window.set_child(Some(&listbox));
+    scrolled_window.set_vexpand(true);
+    vbox.append(&scrolled_window);
+
+    glib::spawn_future_local(log.signal_vec_cloned().for_each(move |vd| {
+        match vd {
+            VecDiff::Push { value: entry } => listbox.append(&LogEntryRow(&entry)),
+            VecDiff::Replace { values } => {
+                while let Some(child) = listbox.first_child() {
+                    listbox.remove(&child);
+                }
+                for entry in values {
+                    listbox.append(&LogEntryRow(&entry));
+                }
+            },
+            VecDiff::Clear {} => {
+                while let Some(child) = listbox.first_child() {
+                    listbox.remove(&child);
+                }
+            },
+            _ => panic!("Not supported: {:?}", vd)
+        }
+        async {}
+    }));
+
+    return vbox;
+}
@@ -1,5 +1,5 @@
 #![allow(non_snake_case)]
-use futures_signals::signal::SignalExt;
+use futures_signals::{signal::SignalExt, signal_vec::MutableVec};
 use gtk::{gdk, glib, prelude::*};
 use settings::{load_settings, save_settings};
 
@@ -7,14 +7,54 @@ mod util;
 
 mod settings;
 
+mod pricing;
+
+mod tokens;
+
+mod context;
+
+mod message;
+
+mod markdown;
+
+mod conversation;
+
+mod history;
+
+mod find;
+
+mod export;
+
+mod finetune;
+
+mod import;
+
 mod submit;
 
+mod bedrock;
+
+mod mock;
+
 mod chat;
 use crate::chat::Chat;
 
 mod settings_menu;
 use crate::settings_menu::SettingsMenu;
 
+mod inspector;
+use crate::inspector::Inspector;
+
+mod snippets;
+
+mod batch;
+use crate::batch::BatchPage;
+
+mod embeddings;
+use crate::embeddings::EmbeddingsPage;
+
+#[cfg(test)]
+mod test_server;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let application = gtk::Application::builder()
@@ -54,13 +94,21 @@ fn App(application: &gtk::Application) {
 
     let settings = load_settings();
 
+    let request_log = MutableVec::new();
+
     let stack = gtk::Stack::new();
 
-    let chat = Chat(stack.clone(), settings.clone());
+    let chat = Chat(stack.clone(), settings.clone(), request_log.clone());
     let settings_page = SettingsMenu(stack.clone(), settings.clone());
+    let inspector = Inspector(stack.clone(), request_log.clone());
+    let batch = BatchPage(stack.clone(), settings.clone(), request_log.clone());
+    let embeddings = EmbeddingsPage(stack.clone(), settings.clone(), request_log);
 
     stack.add_titled(&chat, Some("chat"), "Chat");
     stack.add_titled(&settings_page, Some("settings"), "Settings");
+    stack.add_titled(&inspector, Some("inspector"), "Inspector");
+    stack.add_titled(&batch, Some("batch"), "Batch");
+    stack.add_titled(&embeddings, Some("embeddings"), "Embeddings");
     stack.set_visible_child_name("chat");
 
     window.set_child(Some(&stack));
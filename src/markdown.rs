@@ -0,0 +1,415 @@
+#![allow(non_snake_case)]
+use gtk::{gio, glib::{self, clone, markup_escape_text}, prelude::*, Label};
+use lazy_static::lazy_static;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use syntect::{easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet, util::LinesWithEndings};
+
+use crate::util::DummyLabel;
+
+lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
+}
+
+// a message splits into runs of Pango markup and the code blocks between them
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Markup(String),
+    Code { language: String, code: String }
+}
+
+#[derive(Default)]
+struct Table {
+    // markup and visible width of each cell
+    rows: Vec<Vec<(String, usize)>>,
+    row: Vec<(String, usize)>,
+    cell: Option<(String, usize)>,
+    header_rows: usize
+}
+
+impl Table {
+    // Pango has no tables, so columns are padded out in monospace
+    fn to_markup(&self) -> String {
+        let columns = self.rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|column| self.rows.iter().filter_map(|row| row.get(column)).map(|(_, width)| *width).max().unwrap_or(0))
+            .collect();
+
+        let mut lines = vec![];
+        for (index, row) in self.rows.iter().enumerate() {
+            let cells: Vec<String> = widths
+                .iter()
+                .enumerate()
+                .map(|(column, width)| {
+                    let (markup, cell_width) = row.get(column).cloned().unwrap_or_default();
+                    let markup = if index < self.header_rows { format!("<b>{}</b>", markup) } else { markup };
+                    format!("{}{}", markup, " ".repeat(width - cell_width))
+                })
+                .collect();
+            lines.push(cells.join(" │ "));
+            if index + 1 == self.header_rows {
+                let rule: Vec<String> = widths.iter().map(|width| "─".repeat(*width)).collect();
+                lines.push(rule.join("─┼─"));
+            }
+        }
+
+        return format!("<tt>{}</tt>\n\n", lines.join("\n"));
+    }
+}
+
+#[derive(Default)]
+struct Renderer {
+    blocks: Vec<Block>,
+    markup: String,
+    // the next number of each open list, None for bullet lists
+    lists: Vec<Option<u64>>,
+    quote_depth: usize,
+    code: Option<(String, String)>,
+    table: Option<Table>
+}
+
+impl Renderer {
+    fn push(&mut self, markup: &str, width: usize) {
+        match self.table.as_mut().and_then(|table| table.cell.as_mut()) {
+            Some(cell) => {
+                cell.0 += markup;
+                cell.1 += width;
+            },
+            None => self.markup += markup
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        self.push(markup_escape_text(text).as_str(), text.chars().count());
+    }
+
+    fn end_line(&mut self) {
+        if !self.markup.is_empty() && !self.markup.ends_with('\n') {
+            self.markup.push('\n');
+        }
+    }
+
+    fn flush(&mut self) {
+        let markup = self.markup.trim_end().to_string();
+        if !markup.is_empty() {
+            self.blocks.push(Block::Markup(markup));
+        }
+        self.markup.clear();
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => {
+                if self.quote_depth > 0 {
+                    self.push(&"│ ".repeat(self.quote_depth), 0);
+                }
+            },
+            Tag::Heading { level, .. } => {
+                let size = match level {
+                    HeadingLevel::H1 => "x-large",
+                    HeadingLevel::H2 => "large",
+                    _ => "medium"
+                };
+                self.push(&format!("<span size=\"{}\" weight=\"bold\">", size), 0);
+            },
+            Tag::BlockQuote => self.quote_depth += 1,
+            Tag::CodeBlock(kind) => {
+                self.flush();
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or_default().to_string(),
+                    CodeBlockKind::Indented => String::new()
+                };
+                self.code = Some((language, String::new()));
+            },
+            Tag::List(start) => {
+                self.end_line();
+                self.lists.push(start);
+            },
+            Tag::Item => {
+                let indent = "    ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    },
+                    _ => "• ".to_string()
+                };
+                self.push(&format!("{}{}", indent, marker), 0);
+            },
+            Tag::Table(_) => {
+                self.end_line();
+                self.table = Some(Table::default());
+            },
+            Tag::TableCell => {
+                if let Some(table) = self.table.as_mut() {
+                    table.cell = Some((String::new(), 0));
+                }
+            },
+            Tag::Emphasis => self.push("<i>", 0),
+            Tag::Strong => self.push("<b>", 0),
+            Tag::Strikethrough => self.push("<s>", 0),
+            Tag::Link { dest_url, .. } => self.push(&format!("<a href=\"{}\">", markup_escape_text(&dest_url)), 0),
+            Tag::Image { .. } => self.push("[", 1),
+            _ => ()
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => {
+                if self.lists.is_empty() {
+                    self.push("\n\n", 0);
+                } else {
+                    self.end_line();
+                }
+            },
+            TagEnd::Heading(_) => self.push("</span>\n\n", 0),
+            TagEnd::BlockQuote => self.quote_depth -= 1,
+            TagEnd::CodeBlock => {
+                if let Some((language, code)) = self.code.take() {
+                    let code = code.strip_suffix('\n').unwrap_or(&code).to_string();
+                    self.blocks.push(Block::Code { language, code });
+                }
+            },
+            TagEnd::List(_) => {
+                self.lists.pop();
+                self.end_line();
+                if self.lists.is_empty() {
+                    self.push("\n", 0);
+                }
+            },
+            TagEnd::Item => self.end_line(),
+            TagEnd::TableCell => {
+                if let Some(table) = self.table.as_mut() {
+                    if let Some(cell) = table.cell.take() {
+                        table.row.push(cell);
+                    }
+                }
+            },
+            TagEnd::TableHead | TagEnd::TableRow => {
+                if let Some(table) = self.table.as_mut() {
+                    let row = std::mem::take(&mut table.row);
+                    table.rows.push(row);
+                    if tag == TagEnd::TableHead {
+                        table.header_rows = table.rows.len();
+                    }
+                }
+            },
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.markup += &table.to_markup();
+                }
+            },
+            TagEnd::Emphasis => self.push("</i>", 0),
+            TagEnd::Strong => self.push("</b>", 0),
+            TagEnd::Strikethrough => self.push("</s>", 0),
+            TagEnd::Link => self.push("</a>", 0),
+            TagEnd::Image => self.push("]", 1),
+            _ => ()
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match self.code.as_mut() {
+                Some((_, code)) => code.push_str(&text),
+                None => self.push_text(&text)
+            },
+            Event::Code(code) => {
+                self.push(&format!("<tt>{}</tt>", markup_escape_text(&code)), code.chars().count());
+            },
+            Event::Html(html) | Event::InlineHtml(html) => self.push_text(&html),
+            Event::SoftBreak => self.push(" ", 1),
+            Event::HardBreak => self.push("\n", 0),
+            Event::Rule => self.push("──────────\n\n", 0),
+            Event::TaskListMarker(checked) => self.push(if checked { "☑ " } else { "☐ " }, 2),
+            Event::FootnoteReference(label) => self.push_text(&format!("[{}]", label)),
+            _ => ()
+        }
+    }
+}
+
+pub fn parse_markdown(text: &str) -> Vec<Block> {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut renderer = Renderer::default();
+    for event in Parser::new_ext(text, options) {
+        renderer.event(event);
+    }
+    renderer.flush();
+
+    return renderer.blocks;
+}
+
+// the length of the text up to the end of its last complete block; a streamed reply
+// is only rendered that far, since anything after it can still change meaning
+pub fn stable_prefix_len(text: &str) -> usize {
+    let mut in_fence = false;
+    let mut stable_len = 0;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if !line.ends_with('\n') {
+            break;
+        }
+        offset += line.len();
+
+        let line = line.trim();
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_fence = !in_fence;
+            if !in_fence {
+                stable_len = offset;
+            }
+        } else if line.is_empty() && !in_fence {
+            stable_len = offset;
+        }
+    }
+
+    return stable_len;
+}
+
+fn MarkupLabel(markup: &str) -> Label {
+    let label = Label::new(None);
+    label.set_markup(markup);
+    label.set_css_classes(&["message-label"]);
+    label.set_xalign(0.0);
+    label.set_wrap(true);
+    label.set_wrap_mode(gtk::pango::WrapMode::WordChar);
+    label.set_selectable(true);
+
+    return label;
+}
+
+fn prefers_dark() -> bool {
+    return gtk::Settings::default()
+        .map(|settings| {
+            settings.is_gtk_application_prefer_dark_theme()
+                || settings.gtk_theme_name().is_some_and(|name| name.to_lowercase().contains("dark"))
+        })
+        .unwrap_or(false);
+}
+
+// Pango markup for the code, or None when the language isn't one syntect knows
+pub fn highlight_code(code: &str, language: &str, dark: bool) -> Option<String> {
+    if language.is_empty() {
+        return None;
+    }
+    let syntax = SYNTAX_SET.find_syntax_by_token(language)?;
+    let theme = &THEME_SET.themes[if dark { "base16-ocean.dark" } else { "InspiredGitHub" }];
+
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut markup = String::new();
+    for line in LinesWithEndings::from(code) {
+        for (style, text) in highlighter.highlight_line(line, &SYNTAX_SET).ok()? {
+            let color = style.foreground;
+            markup += &format!(
+                "<span foreground=\"#{:02x}{:02x}{:02x}\">{}</span>",
+                color.r, color.g, color.b, markup_escape_text(text)
+            );
+        }
+    }
+
+    return Some(markup);
+}
+
+fn file_extension(language: &str) -> String {
+    return SYNTAX_SET
+        .find_syntax_by_token(language)
+        .and_then(|syntax| syntax.file_extensions.first().cloned())
+        .unwrap_or("txt".to_string());
+}
+
+fn CodeBlockOption(label: &str) -> gtk::Button {
+    let button = gtk::Button::with_label(label);
+    button.set_css_classes(&["flat", "exchange-header-button"]);
+
+    return button;
+}
+
+fn show_status(label: &Label, status: &str) {
+    label.set_text(status);
+    let label = label.clone();
+    let status = status.to_string();
+    glib::spawn_future_local(async move {
+        glib::timeout_future_seconds(3).await;
+        // unless something newer is showing
+        if label.text() == status {
+            label.set_text("");
+        }
+    });
+}
+
+fn CodeBlock(language: &str, code: &str) -> gtk::Box {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+
+    let header = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    header.set_css_classes(&["code-block-header"]);
+    let language_label = Label::new(Some(if language.is_empty() { "text" } else { language }));
+    header.append(&language_label);
+    header.append(&DummyLabel(gtk::Orientation::Horizontal));
+    let status_label = Label::new(None);
+    header.append(&status_label);
+
+    let copy_button = CodeBlockOption("Copy");
+    copy_button.connect_clicked(clone!(@strong code, @weak status_label => move |button| {
+        button.clipboard().set_text(&code);
+        show_status(&status_label, "Copied");
+    }));
+    header.append(&copy_button);
+
+    let save_button = CodeBlockOption("Save");
+    let initial_name = format!("snippet.{}", file_extension(language));
+    save_button.connect_clicked(clone!(@strong code, @weak status_label => move |button| {
+        let dialog = gtk::FileDialog::new();
+        dialog.set_title("Save Code");
+        dialog.set_initial_name(Some(&initial_name));
+        let window = button.root().and_downcast::<gtk::Window>();
+        let code = code.clone();
+        let status_label = status_label.clone();
+        glib::spawn_future_local(async move {
+            let file: Option<gio::File> = dialog.save_future(window.as_ref()).await.ok();
+            if let Some(path) = file.and_then(|file| file.path()) {
+                match std::fs::write(&path, code) {
+                    Ok(()) => show_status(&status_label, "Saved"),
+                    Err(err) => show_status(&status_label, &format!("Couldn't save to {}: {}", path.display(), err))
+                }
+            }
+        });
+    }));
+    header.append(&save_button);
+    vbox.append(&header);
+
+    let label = Label::new(None);
+    match highlight_code(code, language, prefers_dark()) {
+        Some(markup) => label.set_markup(&markup),
+        None => label.set_text(code)
+    }
+    label.set_css_classes(&["code-block"]);
+    label.set_xalign(0.0);
+    label.set_selectable(true);
+
+    // long lines scroll sideways instead of wrapping
+    let scrolled_window = gtk::ScrolledWindow::new();
+    scrolled_window.set_policy(gtk::PolicyType::Automatic, gtk::PolicyType::Never);
+    scrolled_window.set_propagate_natural_height(true);
+    scrolled_window.set_child(Some(&label));
+    vbox.append(&scrolled_window);
+
+    return vbox;
+}
+
+pub fn append_markdown(container: &gtk::Box, text: &str) {
+    for block in parse_markdown(text) {
+        match block {
+            Block::Markup(markup) => container.append(&MarkupLabel(&markup)),
+            Block::Code { language, code } => container.append(&CodeBlock(&language, &code))
+        }
+    }
+}
+
+pub fn Markdown(text: &str) -> gtk::Box {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    append_markdown(&vbox, text);
+
+    return vbox;
+}
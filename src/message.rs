@@ -0,0 +1,109 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::pricing::Usage;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool
+}
+
+impl ToString for Role {
+    fn to_string(&self) -> String {
+        match *self {
+            Role::System => "System".to_string(),
+            Role::User => "User".to_string(),
+            Role::Assistant => "Assistant".to_string(),
+            Role::Tool => "Tool".to_string()
+        }
+    }
+}
+
+// the roles a message can be added as by hand; tool messages only come from imports
+pub const ROLES: [Role; 3] = [Role::User, Role::Assistant, Role::System];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text { text: String },
+    // base64 encoded
+    Image { media_type: String, data: String },
+    ToolUse { id: String, name: String, input: serde_json::Value },
+    ToolResult { tool_use_id: String, content: String }
+}
+
+// a hand-given verdict on a reply, used to pick fine-tuning examples
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Good,
+    Bad
+}
+
+// what's known about how a message came to be; empty for messages typed in by hand
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    pub model: Option<String>,
+    pub timestamp: Option<SystemTime>,
+    pub usage: Option<Usage>,
+    pub stop_reason: Option<String>,
+    #[serde(default)]
+    pub rating: Option<Rating>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Message {
+    pub role: Role,
+    pub content: Vec<ContentBlock>,
+    #[serde(default)]
+    pub metadata: Metadata
+}
+
+impl Message {
+    pub fn text(role: Role, text: &str) -> Message {
+        return Message {
+            role,
+            content: vec![ContentBlock::Text { text: text.to_string() }],
+            metadata: Metadata { timestamp: Some(SystemTime::now()), ..Metadata::default() }
+        };
+    }
+
+    // the text blocks only, which is what gets edited and counted
+    pub fn text_content(&self) -> String {
+        return self.content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text.as_str()),
+                _ => None
+            })
+            .collect::<Vec<&str>>()
+            .join("\n\n");
+    }
+
+    // every block, with the ones that aren't text described in brackets
+    pub fn display_text(&self) -> String {
+        return self.content
+            .iter()
+            .map(|block| match block {
+                ContentBlock::Text { text } => text.clone(),
+                ContentBlock::Image { media_type, .. } => format!("[{} image]", media_type),
+                ContentBlock::ToolUse { name, input, .. } => format!("[tool call: {}({})]", name, input),
+                ContentBlock::ToolResult { content, .. } => format!("[tool result: {}]", content)
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n");
+    }
+
+    // replaces the text blocks with `text`, keeping attachments and tool blocks after it
+    pub fn with_text(&self, text: &str) -> Message {
+        let mut content = vec![ContentBlock::Text { text: text.to_string() }];
+        content.extend(self.content.iter().filter(|block| !matches!(block, ContentBlock::Text { .. })).cloned());
+
+        return Message { role: self.role, content, metadata: self.metadata.clone() };
+    }
+}
@@ -0,0 +1,151 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use crate::{inspector::LogEntry, tokens::count_tokens};
+
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub enum MockError {
+    #[default]
+    None,
+    RateLimit,
+    Disconnect,
+    MalformedJson
+}
+
+impl ToString for MockError {
+    fn to_string(&self) -> String {
+        match *self {
+            MockError::None => "None".to_string(),
+            MockError::RateLimit => "HTTP 429".to_string(),
+            MockError::Disconnect => "Mid-stream disconnect".to_string(),
+            MockError::MalformedJson => "Malformed JSON".to_string()
+        }
+    }
+}
+
+pub const MOCK_ERRORS: [MockError; 4] = [
+    MockError::None,
+    MockError::RateLimit,
+    MockError::Disconnect,
+    MockError::MalformedJson
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MockConfig {
+    // replies played back in turn; the prompt is echoed when there are none
+    #[serde(default)]
+    pub script: Vec<String>,
+    pub delay_ms: u64,
+    // characters per streamed chunk
+    pub chunk_size: usize,
+    #[serde(default)]
+    pub error: MockError,
+    // report token counts as a real provider would
+    #[serde(default)]
+    pub usage: bool
+}
+
+impl Default for MockConfig {
+    fn default() -> MockConfig {
+        return MockConfig { script: vec![], delay_ms: 30, chunk_size: 4, error: MockError::None, usage: true };
+    }
+}
+
+fn message_text(message: &serde_json::Value) -> String {
+    return message["content"].as_str().unwrap_or_default().to_string();
+}
+
+fn chunk_text(text: &str, chunk_size: usize) -> Vec<String> {
+    let characters: Vec<char> = text.chars().collect();
+    return characters
+        .chunks(chunk_size.max(1))
+        .map(|chunk| chunk.iter().collect())
+        .collect();
+}
+
+// the mock answers in OpenAI's chat completion chunk format, so its events go
+// through the same decoder and callbacks as a real stream
+pub async fn stream_response(
+    config: &MockConfig,
+    body: &serde_json::Value,
+    cancel: CancellationToken,
+    mut on_event: impl FnMut(&serde_json::Value),
+    err: impl Fn(String),
+    log: impl Fn(LogEntry)
+) {
+    log(LogEntry::Request {
+        timestamp: SystemTime::now(),
+        url: "POST mock://chat/completions".to_string(),
+        headers: vec![],
+        body: body.to_string()
+    });
+
+    if config.error == MockError::RateLimit {
+        log(LogEntry::status("429 Too Many Requests"));
+        err("Invalid status code: 429 Too Many Requests".to_string());
+        return;
+    }
+    log(LogEntry::status("Connection opened"));
+
+    let messages = body["messages"].as_array().cloned().unwrap_or_default();
+    let prompt = messages.last().map(message_text).unwrap_or_default();
+    let turn = messages.iter().filter(|message| message["role"] == "user").count().saturating_sub(1);
+    let reply = if config.script.is_empty() {
+        prompt.clone()
+    } else {
+        config.script[turn % config.script.len()].clone()
+    };
+
+    let chunks = chunk_text(&reply, config.chunk_size);
+    let failure_point = chunks.len() / 2;
+    for (index, chunk) in chunks.iter().enumerate() {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(config.delay_ms)) => (),
+            _ = cancel.cancelled() => {
+                log(LogEntry::status("Cancelled"));
+                return;
+            }
+        }
+
+        if index == failure_point {
+            match config.error {
+                MockError::Disconnect => {
+                    log(LogEntry::status("Connection reset"));
+                    err("Transport error: connection closed before message completed".to_string());
+                    return;
+                },
+                MockError::MalformedJson => {
+                    // real streams skip data that doesn't parse, and so does this chunk
+                    let data = format!("{{\"choices\": [{{\"delta\": {{\"content\": {:?}", chunk);
+                    log(LogEntry::event("message", &data));
+                    continue;
+                },
+                _ => ()
+            }
+        }
+
+        let data = json!({ "choices": [{ "index": 0, "delta": { "content": chunk } }] }).to_string();
+        log(LogEntry::event("message", &data));
+        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&data) {
+            on_event(&data);
+        }
+    }
+
+    if config.usage {
+        let model = body["model"].as_str().unwrap_or_default();
+        let input: String = messages.iter().map(message_text).collect();
+        let data = json!({
+            "choices": [],
+            "usage": {
+                "prompt_tokens": count_tokens(model, &input),
+                "completion_tokens": count_tokens(model, &reply)
+            }
+        });
+        log(LogEntry::event("message", &data.to_string()));
+        on_event(&data);
+    }
+    log(LogEntry::status("Stream ended"));
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use maplit::hashmap;
+use serde::{Deserialize, Serialize};
+
+use crate::util::is_model_version;
+
+// token counts reported by the provider for a single reply
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    // dollar cost at the prices in effect when the reply completed, if the model was priced
+    pub cost: Option<f64>
+}
+
+// prices in dollars per million tokens
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+    #[serde(default)]
+    pub cache_read: f64,
+    #[serde(default)]
+    pub cache_write: f64
+}
+
+impl ModelPricing {
+    const fn new(input: f64, output: f64, cache_read: f64, cache_write: f64) -> ModelPricing {
+        return ModelPricing { input, output, cache_read, cache_write };
+    }
+
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let cost = usage.input_tokens as f64 * self.input
+            + usage.output_tokens as f64 * self.output
+            + usage.cache_read_tokens as f64 * self.cache_read
+            + usage.cache_write_tokens as f64 * self.cache_write;
+
+        return cost / 1_000_000.0;
+    }
+}
+
+fn default_pricing() -> HashMap<&'static str, ModelPricing> {
+    return hashmap! {
+        "gpt-3.5-turbo" => ModelPricing::new(0.5, 1.5, 0.0, 0.0),
+        "gpt-4" => ModelPricing::new(30.0, 60.0, 0.0, 0.0),
+        "gpt-4-32k" => ModelPricing::new(60.0, 120.0, 0.0, 0.0),
+        "gpt-4-turbo" => ModelPricing::new(10.0, 30.0, 0.0, 0.0),
+        "gpt-4o" => ModelPricing::new(2.5, 10.0, 1.25, 0.0),
+        "gpt-4o-mini" => ModelPricing::new(0.15, 0.6, 0.075, 0.0),
+        "gpt-4.1" => ModelPricing::new(2.0, 8.0, 0.5, 0.0),
+        "gpt-4.1-mini" => ModelPricing::new(0.4, 1.6, 0.1, 0.0),
+        "gpt-4.1-nano" => ModelPricing::new(0.1, 0.4, 0.025, 0.0),
+        "o1" => ModelPricing::new(15.0, 60.0, 7.5, 0.0),
+        "o1-mini" => ModelPricing::new(1.1, 4.4, 0.55, 0.0),
+        "o3-mini" => ModelPricing::new(1.1, 4.4, 0.55, 0.0),
+        "claude-3-haiku" => ModelPricing::new(0.25, 1.25, 0.03, 0.3),
+        "claude-3-sonnet" => ModelPricing::new(3.0, 15.0, 0.3, 3.75),
+        "claude-3-opus" => ModelPricing::new(15.0, 75.0, 1.5, 18.75),
+        "claude-3-5-haiku" => ModelPricing::new(0.8, 4.0, 0.08, 1.0),
+        "claude-3-5-sonnet" => ModelPricing::new(3.0, 15.0, 0.3, 3.75),
+        "claude-3-7-sonnet" => ModelPricing::new(3.0, 15.0, 0.3, 3.75),
+        "claude-sonnet-4" => ModelPricing::new(3.0, 15.0, 0.3, 3.75),
+        "claude-opus-4" => ModelPricing::new(15.0, 75.0, 1.5, 18.75)
+    };
+}
+
+// exact matches win, then dated snapshots such as gpt-4o-2024-08-06 take their model's price;
+// an override beats the default for the same model, and other variants stay unpriced
+pub fn model_pricing(overrides: &HashMap<String, ModelPricing>, model: &str) -> Option<ModelPricing> {
+    if let Some(pricing) = overrides.get(model) {
+        return Some(*pricing);
+    }
+    if let Some((_, pricing)) = overrides.iter().find(|(name, _)| is_model_version(name, model)) {
+        return Some(*pricing);
+    }
+
+    return default_pricing()
+        .into_iter()
+        .find(|(name, _)| is_model_version(name, model))
+        .map(|(_, pricing)| pricing);
+}
+
+pub fn format_cost(cost: f64) -> String {
+    if cost < 0.01 {
+        return format!("${:.4}", cost);
+    }
+
+    return format!("${:.2}", cost);
+}
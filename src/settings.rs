@@ -1,19 +1,52 @@
-use std::env;
+use std::{collections::HashMap, env};
 
 use futures_signals::signal::Mutable;
 use serde::{Deserialize, Serialize};
 
+use crate::{mock::MockConfig, pricing::ModelPricing};
+
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub enum Provider {
     OpenAI,
-    Anthropic
+    Anthropic,
+    AzureOpenAI,
+    Bedrock,
+    Mock
 }
 
 impl ToString for Provider {
     fn to_string(&self) -> String {
         match *self {
             Provider::OpenAI => "OpenAI".to_string(),
-            Provider::Anthropic => "Anthropic".to_string()
+            Provider::Anthropic => "Anthropic".to_string(),
+            Provider::AzureOpenAI => "Azure OpenAI".to_string(),
+            Provider::Bedrock => "AWS Bedrock".to_string(),
+            Provider::Mock => "Mock".to_string()
+        }
+    }
+}
+
+pub const PROVIDERS: [Provider; 5] = [
+    Provider::OpenAI,
+    Provider::Anthropic,
+    Provider::AzureOpenAI,
+    Provider::Bedrock,
+    Provider::Mock
+];
+
+// the OpenAI endpoint requests go to; newer models and features only ship on Responses
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq)]
+pub enum OpenAIAPI {
+    #[default]
+    ChatCompletions,
+    Responses
+}
+
+impl ToString for OpenAIAPI {
+    fn to_string(&self) -> String {
+        match *self {
+            OpenAIAPI::ChatCompletions => "Chat Completions".to_string(),
+            OpenAIAPI::Responses => "Responses".to_string()
         }
     }
 }
@@ -22,7 +55,26 @@ impl ToString for Provider {
 pub struct APIKey {
     pub name: String,
     pub key: String,
-    pub provider: Provider
+    pub provider: Provider,
+    // overrides the provider's API root, e.g. http://localhost:8080/v1
+    #[serde(default)]
+    pub base_url: Option<String>,
+    // Azure OpenAI resource endpoint, e.g. https://my-resource.openai.azure.com
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub deployment: Option<String>,
+    #[serde(default)]
+    pub api_version: Option<String>,
+    // for Bedrock, key holds the AWS access key id
+    #[serde(default)]
+    pub secret_key: Option<String>,
+    #[serde(default)]
+    pub session_token: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub mock: Option<MockConfig>
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -31,7 +83,19 @@ pub struct Settings {
     pub max_tokens: u32,
     pub model: String,
     pub api_key: Option<usize>,
-    pub api_keys: Vec<APIKey>
+    pub api_keys: Vec<APIKey>,
+    // overrides of the built-in pricing table, keyed by model id; a key also covers its dated snapshots
+    #[serde(default)]
+    pub pricing: HashMap<String, ModelPricing>,
+    // only used by OpenAI keys
+    #[serde(default)]
+    pub openai_api: OpenAIAPI,
+    // sent as the Responses API's instructions
+    #[serde(default)]
+    pub instructions: String,
+    // send only the new prompt and let the server recall the rest through previous_response_id
+    #[serde(default)]
+    pub chain_responses: bool
 }
 
 pub fn load_settings() -> Mutable<Settings> {
@@ -45,7 +109,11 @@ pub fn load_settings() -> Mutable<Settings> {
             max_tokens: 1024,
             model: "".to_string(),
             api_key: None,
-            api_keys: vec![]
+            api_keys: vec![],
+            pricing: HashMap::new(),
+            openai_api: OpenAIAPI::ChatCompletions,
+            instructions: String::new(),
+            chain_responses: false
         };
         save_settings(&settings);
     }
@@ -3,7 +3,12 @@ use futures_signals::signal::{Mutable, SignalExt};
 use gtk::{glib::{self, clone}, prelude::*, Button, DropDown, Entry, Label, Scale, ScrolledWindow, Window};
 use maplit::hashmap;
 
-use crate::{settings::{APIKey, Provider, Settings}, util::{center, DummyLabel}};
+use crate::{
+    mock::{MockConfig, MOCK_ERRORS},
+    settings::{APIKey, OpenAIAPI, Provider, Settings},
+    submit::DEFAULT_AZURE_API_VERSION,
+    util::{center, get_buffer_content, DummyLabel}
+};
 
 fn TemperatureSlider(
     temperature: Mutable<f64>,
@@ -109,6 +114,110 @@ fn ModelEntry(
     return hbox;
 }
 
+const OPENAI_APIS: [OpenAIAPI; 2] = [OpenAIAPI::ChatCompletions, OpenAIAPI::Responses];
+
+fn OpenAIAPIDropDown(
+    openai_api: Mutable<OpenAIAPI>,
+    mut openai_api_recv: mpsc::UnboundedReceiver<OpenAIAPI>,
+    changes_made: Mutable<bool>
+) -> gtk::Box {
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 20);
+
+    let label = Label::new(Some("OpenAI API:"));
+    hbox.append(&label);
+
+    let api_names: Vec<String> = OPENAI_APIS.iter().map(|api| api.to_string()).collect();
+    let api_names: Vec<&str> = api_names.iter().map(|name| name.as_str()).collect();
+    let store = gtk::StringList::new(&api_names);
+    let dropdown = DropDown::new(Some(store), None::<&gtk::Expression>);
+    let position = OPENAI_APIS.iter().position(|api| *api == openai_api.get()).unwrap();
+    dropdown.set_selected(position as u32);
+    hbox.append(&dropdown);
+
+    dropdown.connect_notify(Some("selected"), clone!(@strong openai_api => move |dropdown, _| {
+        openai_api.set_neq(OPENAI_APIS[dropdown.selected() as usize]);
+        *changes_made.lock_mut() = true;
+    }));
+
+    glib::spawn_future_local(async move {
+        while let Some(openai_api) = openai_api_recv.next().await {
+            let position = OPENAI_APIS.iter().position(|api| *api == openai_api).unwrap();
+            dropdown.set_selected(position as u32);
+        }
+    });
+
+    return hbox;
+}
+
+fn InstructionsEntry(
+    instructions: Mutable<String>,
+    mut instructions_recv: mpsc::UnboundedReceiver<String>,
+    openai_api: Mutable<OpenAIAPI>,
+    changes_made: Mutable<bool>
+) -> gtk::Box {
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 20);
+
+    let label = Label::new(Some("Instructions:"));
+    hbox.append(&label);
+
+    let entry = Entry::new();
+    entry.set_text(&instructions.lock_ref());
+    entry.set_placeholder_text(Some("Responses API only"));
+    hbox.append(&entry);
+
+    entry.connect_changed(clone!(@strong instructions => move |entry| {
+        instructions.set(entry.text().to_string());
+        *changes_made.lock_mut() = true;
+    }));
+
+    glib::spawn_future_local(openai_api.signal().for_each({
+        let hbox = hbox.clone();
+        move |openai_api| {
+            hbox.set_sensitive(openai_api == OpenAIAPI::Responses);
+            async {}
+        }
+    }));
+
+    glib::spawn_future_local(async move {
+        while let Some(instructions) = instructions_recv.next().await {
+            entry.set_text(&instructions);
+        }
+    });
+
+    return hbox;
+}
+
+fn ChainResponsesCheck(
+    chain_responses: Mutable<bool>,
+    mut chain_responses_recv: mpsc::UnboundedReceiver<bool>,
+    openai_api: Mutable<OpenAIAPI>,
+    changes_made: Mutable<bool>
+) -> gtk::CheckButton {
+    let check_button = gtk::CheckButton::with_label("Chain responses with previous_response_id");
+    check_button.set_active(chain_responses.get());
+
+    check_button.connect_toggled(clone!(@strong chain_responses => move |check_button| {
+        chain_responses.set(check_button.is_active());
+        *changes_made.lock_mut() = true;
+    }));
+
+    glib::spawn_future_local(openai_api.signal().for_each({
+        let check_button = check_button.clone();
+        move |openai_api| {
+            check_button.set_sensitive(openai_api == OpenAIAPI::Responses);
+            async {}
+        }
+    }));
+
+    glib::spawn_future_local(clone!(@weak check_button => async move {
+        while let Some(chain_responses) = chain_responses_recv.next().await {
+            check_button.set_active(chain_responses);
+        }
+    }));
+
+    return check_button;
+}
+
 fn APIKeyDropDown(
     api_key: Mutable<Option<usize>>,
     mut api_key_recv: mpsc::UnboundedReceiver<Option<usize>>,
@@ -202,13 +311,142 @@ fn Popup(api_keys: Mutable<Vec<APIKey>>) -> Window {
     let providers = hashmap! {
         "OpenAI" => Provider::OpenAI,
         "Anthropic" => Provider::Anthropic,
+        "Azure OpenAI" => Provider::AzureOpenAI,
+        "AWS Bedrock" => Provider::Bedrock,
+        "Mock" => Provider::Mock,
     };
     let provider_names: Vec<&str> = providers.keys().map(|x| *x).collect();
     let store = gtk::StringList::new(&provider_names);
     let provider_dropdown = DropDown::new(Some(store), None::<&gtk::Expression>);
     grid.attach(&provider_dropdown, 1, 2, 1, 1);
 
+    let base_url_grid = gtk::Grid::new();
+    base_url_grid.set_row_spacing(10);
+    base_url_grid.set_column_spacing(10);
+
+    let label = Label::new(Some("Base URL: "));
+    label.set_halign(gtk::Align::Start);
+    base_url_grid.attach(&label, 0, 0, 1, 1);
+    let base_url_entry = Entry::new();
+    base_url_entry.set_placeholder_text(Some("Optional"));
+    base_url_grid.attach(&base_url_entry, 1, 0, 1, 1);
+
+    // Azure keys are scoped to a resource and a deployment
+    let azure_grid = gtk::Grid::new();
+    azure_grid.set_row_spacing(10);
+    azure_grid.set_column_spacing(10);
+
+    let label = Label::new(Some("Endpoint: "));
+    label.set_halign(gtk::Align::Start);
+    azure_grid.attach(&label, 0, 0, 1, 1);
+    let endpoint_entry = Entry::new();
+    endpoint_entry.set_placeholder_text(Some("https://my-resource.openai.azure.com"));
+    azure_grid.attach(&endpoint_entry, 1, 0, 1, 1);
+
+    let label = Label::new(Some("Deployment: "));
+    label.set_halign(gtk::Align::Start);
+    azure_grid.attach(&label, 0, 1, 1, 1);
+    let deployment_entry = Entry::new();
+    azure_grid.attach(&deployment_entry, 1, 1, 1, 1);
+
+    let label = Label::new(Some("API version: "));
+    label.set_halign(gtk::Align::Start);
+    azure_grid.attach(&label, 0, 2, 1, 1);
+    let api_version_entry = Entry::new();
+    api_version_entry.set_text(DEFAULT_AZURE_API_VERSION);
+    azure_grid.attach(&api_version_entry, 1, 2, 1, 1);
+
+    // Bedrock uses the key field for the access key id and signs with the secret
+    let bedrock_grid = gtk::Grid::new();
+    bedrock_grid.set_row_spacing(10);
+    bedrock_grid.set_column_spacing(10);
+
+    let label = Label::new(Some("Secret key: "));
+    label.set_halign(gtk::Align::Start);
+    bedrock_grid.attach(&label, 0, 0, 1, 1);
+    let secret_key_entry = Entry::new();
+    bedrock_grid.attach(&secret_key_entry, 1, 0, 1, 1);
+
+    let label = Label::new(Some("Session token: "));
+    label.set_halign(gtk::Align::Start);
+    bedrock_grid.attach(&label, 0, 1, 1, 1);
+    let session_token_entry = Entry::new();
+    session_token_entry.set_placeholder_text(Some("Optional"));
+    bedrock_grid.attach(&session_token_entry, 1, 1, 1, 1);
+
+    let label = Label::new(Some("Region: "));
+    label.set_halign(gtk::Align::Start);
+    bedrock_grid.attach(&label, 0, 2, 1, 1);
+    let region_entry = Entry::new();
+    region_entry.set_text("us-east-1");
+    bedrock_grid.attach(&region_entry, 1, 2, 1, 1);
+
+    // the mock provider needs no key, only a description of how to misbehave
+    let mock_defaults = MockConfig::default();
+    let mock_grid = gtk::Grid::new();
+    mock_grid.set_row_spacing(10);
+    mock_grid.set_column_spacing(10);
+
+    let label = Label::new(Some("Replies: "));
+    label.set_halign(gtk::Align::Start);
+    label.set_valign(gtk::Align::Start);
+    mock_grid.attach(&label, 0, 0, 1, 1);
+    let script_view = gtk::TextView::new();
+    script_view.set_height_request(60);
+    script_view.set_wrap_mode(gtk::WrapMode::WordChar);
+    script_view.set_tooltip_text(Some("Separate replies with a line of ---. Leave empty to echo the prompt."));
+    mock_grid.attach(&script_view, 1, 0, 1, 1);
+
+    let label = Label::new(Some("Delay (ms): "));
+    label.set_halign(gtk::Align::Start);
+    mock_grid.attach(&label, 0, 1, 1, 1);
+    let delay_entry = gtk::SpinButton::with_range(0.0, 5000.0, 10.0);
+    delay_entry.set_value(mock_defaults.delay_ms as f64);
+    mock_grid.attach(&delay_entry, 1, 1, 1, 1);
+
+    let label = Label::new(Some("Chunk size: "));
+    label.set_halign(gtk::Align::Start);
+    mock_grid.attach(&label, 0, 2, 1, 1);
+    let chunk_size_entry = gtk::SpinButton::with_range(1.0, 1000.0, 1.0);
+    chunk_size_entry.set_value(mock_defaults.chunk_size as f64);
+    mock_grid.attach(&chunk_size_entry, 1, 2, 1, 1);
+
+    let label = Label::new(Some("Inject error: "));
+    label.set_halign(gtk::Align::Start);
+    mock_grid.attach(&label, 0, 3, 1, 1);
+    let error_names: Vec<String> = MOCK_ERRORS.iter().map(|error| error.to_string()).collect();
+    let error_names: Vec<&str> = error_names.iter().map(|name| name.as_str()).collect();
+    let store = gtk::StringList::new(&error_names);
+    let mock_error_dropdown = DropDown::new(Some(store), None::<&gtk::Expression>);
+    mock_grid.attach(&mock_error_dropdown, 1, 3, 1, 1);
+
+    let usage_check = gtk::CheckButton::with_label("Report fake usage");
+    usage_check.set_active(mock_defaults.usage);
+    mock_grid.attach(&usage_check, 1, 4, 1, 1);
+
     vbox.append(&grid);
+    vbox.append(&base_url_grid);
+    vbox.append(&azure_grid);
+    vbox.append(&bedrock_grid);
+    vbox.append(&mock_grid);
+
+    let show_provider_fields = {
+        let providers = providers.clone();
+        let provider_names = provider_names.clone();
+        let azure_grid = azure_grid.clone();
+        let bedrock_grid = bedrock_grid.clone();
+        let mock_grid = mock_grid.clone();
+        let base_url_grid = base_url_grid.clone();
+        move |dropdown: &DropDown| {
+            let provider = *providers.get(&provider_names[dropdown.selected() as usize]).unwrap();
+            azure_grid.set_visible(matches!(provider, Provider::AzureOpenAI));
+            bedrock_grid.set_visible(matches!(provider, Provider::Bedrock));
+            mock_grid.set_visible(matches!(provider, Provider::Mock));
+            base_url_grid.set_visible(matches!(provider, Provider::OpenAI | Provider::Anthropic));
+        }
+    };
+    show_provider_fields(&provider_dropdown);
+    provider_dropdown.connect_notify(Some("selected"), move |dropdown, _| show_provider_fields(dropdown));
 
     let add_button = Button::new();
     add_button.set_halign(gtk::Align::End);
@@ -220,14 +458,67 @@ fn Popup(api_keys: Mutable<Vec<APIKey>>) -> Window {
             let key = key_entry.text().to_string();
             let provider_name = provider_names[provider_dropdown.selected() as usize];
             let provider = *providers.get(&provider_name).unwrap();
+            let optional_text = |entry: &Entry| Some(entry.text().trim().to_string())
+                .filter(|text| !text.is_empty());
+            let base_url = match provider {
+                Provider::OpenAI | Provider::Anthropic => optional_text(&base_url_entry),
+                _ => None
+            };
+            let (endpoint, deployment, api_version) = match provider {
+                Provider::AzureOpenAI => (
+                    optional_text(&endpoint_entry),
+                    optional_text(&deployment_entry),
+                    optional_text(&api_version_entry)
+                ),
+                _ => (None, None, None)
+            };
+            let (secret_key, session_token, region) = match provider {
+                Provider::Bedrock => (
+                    optional_text(&secret_key_entry),
+                    optional_text(&session_token_entry),
+                    optional_text(&region_entry)
+                ),
+                _ => (None, None, None)
+            };
+            let mock = match provider {
+                Provider::Mock => Some(MockConfig {
+                    script: get_buffer_content(&script_view.buffer())
+                        .split("\n---\n")
+                        .map(|reply| reply.trim().to_string())
+                        .filter(|reply| !reply.is_empty())
+                        .collect(),
+                    delay_ms: delay_entry.value() as u64,
+                    chunk_size: chunk_size_entry.value() as usize,
+                    error: MOCK_ERRORS[mock_error_dropdown.selected() as usize],
+                    usage: usage_check.is_active()
+                }),
+                _ => None
+            };
             if api_keys.lock_ref().iter().any(|k| k.name == name) {
                 error_label.set_label("API key name already exists");
                 error_label.set_visible(true);
+            } else if matches!(provider, Provider::AzureOpenAI) && (endpoint.is_none() || deployment.is_none()) {
+                error_label.set_label("Azure keys need an endpoint and a deployment");
+                error_label.set_visible(true);
+            } else if matches!(provider, Provider::AzureOpenAI) && !is_https_url(endpoint.as_deref().unwrap_or_default()) {
+                error_label.set_label("The endpoint should be a URL like https://my-resource.openai.azure.com");
+                error_label.set_visible(true);
+            } else if matches!(provider, Provider::Bedrock) && secret_key.is_none() {
+                error_label.set_label("Bedrock keys need a secret access key");
+                error_label.set_visible(true);
             } else {
                 api_keys.lock_mut().push(APIKey {
                     name,
                     key,
                     provider,
+                    base_url,
+                    endpoint,
+                    deployment,
+                    api_version,
+                    secret_key,
+                    session_token,
+                    region,
+                    mock
                 });
                 popup_window.close();
             }
@@ -240,6 +531,11 @@ fn Popup(api_keys: Mutable<Vec<APIKey>>) -> Window {
     return popup_window;
 }
 
+// requests are built by appending to the endpoint, so it has to be absolute
+fn is_https_url(text: &str) -> bool {
+    return reqwest::Url::parse(text).is_ok_and(|url| url.scheme() == "https" && url.host_str().is_some());
+}
+
 fn APIKeyList(api_keys: Mutable<Vec<APIKey>>, changes_made: Mutable<bool>) -> ScrolledWindow {
     let scrolled_window = ScrolledWindow::new();
     scrolled_window.set_vexpand(true);
@@ -307,6 +603,15 @@ fn SettingsBody(settings: Mutable<Settings>) -> gtk::Box {
 
     let api_keys: Mutable<Vec<APIKey>> = Mutable::new(settings.lock_ref().api_keys.clone());
 
+    let openai_api = Mutable::new(settings.lock_ref().openai_api);
+    let (openai_api_send, openai_api_recv) = mpsc::unbounded();
+
+    let instructions = Mutable::new(settings.lock_ref().instructions.clone());
+    let (instructions_send, instructions_recv) = mpsc::unbounded();
+
+    let chain_responses = Mutable::new(settings.lock_ref().chain_responses);
+    let (chain_responses_send, chain_responses_recv) = mpsc::unbounded();
+
     let changes_made = Mutable::new(false);
 
     let vbox = gtk::Box::new(gtk::Orientation::Vertical, 15);
@@ -314,6 +619,9 @@ fn SettingsBody(settings: Mutable<Settings>) -> gtk::Box {
     vbox.append(&MaxTokensEntry(max_tokens.clone(), max_tokens_recv, changes_made.clone()));
     vbox.append(&ModelEntry(model.clone(), model_recv, changes_made.clone()));
     vbox.append(&APIKeyDropDown(api_key.clone(), api_key_recv, api_keys.clone(), changes_made.clone()));
+    vbox.append(&OpenAIAPIDropDown(openai_api.clone(), openai_api_recv, changes_made.clone()));
+    vbox.append(&InstructionsEntry(instructions.clone(), instructions_recv, openai_api.clone(), changes_made.clone()));
+    vbox.append(&ChainResponsesCheck(chain_responses.clone(), chain_responses_recv, openai_api.clone(), changes_made.clone()));
 
     vbox.append(&gtk::Separator::new(gtk::Orientation::Horizontal));
 
@@ -347,6 +655,9 @@ fn SettingsBody(settings: Mutable<Settings>) -> gtk::Box {
             max_tokens_send.unbounded_send(settings.lock_ref().max_tokens).unwrap();
             model_send.unbounded_send(settings.lock_ref().model.clone()).unwrap();
             api_key_send.unbounded_send(settings.lock_ref().api_key.clone()).unwrap();
+            openai_api_send.unbounded_send(settings.lock_ref().openai_api).unwrap();
+            instructions_send.unbounded_send(settings.lock_ref().instructions.clone()).unwrap();
+            chain_responses_send.unbounded_send(settings.lock_ref().chain_responses).unwrap();
             api_keys.lock_mut().clone_from(&settings.lock_ref().api_keys);
             let changes_made = changes_made.clone();
             glib::spawn_future_local(async move { *changes_made.lock_mut() = false; });
@@ -357,12 +668,17 @@ fn SettingsBody(settings: Mutable<Settings>) -> gtk::Box {
         @weak hbox,
         @strong changes_made => move |_| {
             *changes_made.lock_mut() = false;
+            let pricing = settings.lock_ref().pricing.clone();
             *settings.lock_mut() = Settings {
                 temperature: *temperature.lock_ref(),
                 max_tokens: *max_tokens.lock_ref(),
                 model: model.lock_ref().clone(),
                 api_key: *api_key.lock_ref(),
-                api_keys: api_keys.lock_ref().clone()
+                api_keys: api_keys.lock_ref().clone(),
+                pricing,
+                openai_api: openai_api.get(),
+                instructions: instructions.lock_ref().clone(),
+                chain_responses: chain_responses.get()
             };
         }
     ));
@@ -0,0 +1,390 @@
+use gtk::{prelude::*, Button, DropDown, Label, ScrolledWindow, Window};
+use serde_json::Value;
+
+use crate::{
+    bedrock::{bedrock_body, bedrock_host, bedrock_path},
+    settings::{APIKey, OpenAIAPI, Provider},
+    submit::{api_url, azure_openai_url, DEFAULT_AZURE_API_VERSION},
+    util::get_buffer_content
+};
+
+#[derive(Debug, Copy, Clone)]
+pub enum Language {
+    Curl,
+    Python,
+    TypeScript
+}
+
+impl ToString for Language {
+    fn to_string(&self) -> String {
+        match *self {
+            Language::Curl => "cURL".to_string(),
+            Language::Python => "Python".to_string(),
+            Language::TypeScript => "TypeScript".to_string()
+        }
+    }
+}
+
+const LANGUAGES: [Language; 3] = [Language::Curl, Language::Python, Language::TypeScript];
+
+fn key_variable(provider: Provider) -> &'static str {
+    match provider {
+        Provider::OpenAI => "OPENAI_API_KEY",
+        Provider::Anthropic => "ANTHROPIC_API_KEY",
+        Provider::AzureOpenAI => "AZURE_OPENAI_API_KEY",
+        Provider::Bedrock => "AWS_ACCESS_KEY_ID",
+        Provider::Mock => ""
+    }
+}
+
+const MOCK_SNIPPET: &str = "The mock provider answers in-process, so there is no API to call.\n";
+
+// JSON literals are valid Python apart from true, false and null
+fn python_literal(value: &Value, indent: usize) -> String {
+    let padding = "    ".repeat(indent + 1);
+    let closing_padding = "    ".repeat(indent);
+    match value {
+        Value::Null => "None".to_string(),
+        Value::Bool(true) => "True".to_string(),
+        Value::Bool(false) => "False".to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::String(string) => serde_json::to_string(string).unwrap(),
+        Value::Array(values) if values.is_empty() => "[]".to_string(),
+        Value::Array(values) => {
+            let items: Vec<String> = values
+                .iter()
+                .map(|value| format!("{}{},\n", padding, python_literal(value, indent + 1)))
+                .collect();
+            format!("[\n{}{}]", items.concat(), closing_padding)
+        },
+        Value::Object(map) if map.is_empty() => "{}".to_string(),
+        Value::Object(map) => {
+            let items: Vec<String> = map
+                .iter()
+                .map(|(key, value)| format!(
+                    "{}{}: {},\n",
+                    padding,
+                    serde_json::to_string(key).unwrap(),
+                    python_literal(value, indent + 1)
+                ))
+                .collect();
+            format!("{{\n{}{}}}", items.concat(), closing_padding)
+        }
+    }
+}
+
+fn python_kwargs(body: &Value) -> String {
+    let mut kwargs = String::new();
+    for (key, value) in body.as_object().unwrap() {
+        kwargs += &format!("    {}={},\n", key, python_literal(value, 1));
+    }
+
+    return kwargs;
+}
+
+// the SDKs append their own paths to a custom base URL, just as api_url does
+fn base_url_argument(api_key: &APIKey, language: Language) -> String {
+    match (&api_key.base_url, language) {
+        (Some(base_url), Language::Python) => format!(", base_url=\"{}\"", base_url),
+        (Some(base_url), Language::TypeScript) => format!(", baseURL: \"{}\"", base_url),
+        _ => String::new()
+    }
+}
+
+fn indent_lines(text: &str, indent: &str) -> String {
+    return text.lines().collect::<Vec<&str>>().join(&format!("\n{}", indent));
+}
+
+fn curl_snippet(api_key: &APIKey, openai_api: OpenAIAPI, body: &Value) -> String {
+    let provider = api_key.provider;
+    let (url, headers) = match provider {
+        Provider::Mock => return MOCK_SNIPPET.to_string(),
+        Provider::OpenAI => (
+            match openai_api {
+                OpenAIAPI::ChatCompletions => api_url(api_key, "/chat/completions"),
+                OpenAIAPI::Responses => api_url(api_key, "/responses")
+            },
+            vec![format!("Authorization: Bearer ${}", key_variable(provider))]
+        ),
+        Provider::Anthropic => (
+            api_url(api_key, "/messages"),
+            vec![
+                format!("x-api-key: ${}", key_variable(provider)),
+                "anthropic-version: 2023-06-01".to_string()
+            ]
+        ),
+        Provider::AzureOpenAI => (
+            azure_openai_url(api_key),
+            vec![format!("api-key: ${}", key_variable(provider))]
+        ),
+        Provider::Bedrock => {
+            let mut headers = vec![];
+            if api_key.session_token.is_some() {
+                headers.push("x-amz-security-token: $AWS_SESSION_TOKEN".to_string());
+            }
+            let model = body["model"].as_str().unwrap_or_default();
+            (format!("https://{}{}", bedrock_host(api_key), bedrock_path(model)), headers)
+        }
+    };
+
+    let mut snippet = format!("curl \"{}\" \\\n", url);
+    if let Provider::Bedrock = provider {
+        // curl signs the request itself; the response is binary event-stream framing
+        snippet += &format!(
+            "  --aws-sigv4 \"aws:amz:{}:bedrock\" \\\n  --user \"${}:$AWS_SECRET_ACCESS_KEY\" \\\n  --output - \\\n",
+            api_key.region.as_deref().unwrap_or("us-east-1"),
+            key_variable(provider)
+        );
+    }
+    for header in headers {
+        snippet += &format!("  -H \"{}\" \\\n", header);
+    }
+    snippet += "  -H \"Content-Type: application/json\" \\\n";
+    let body = match provider {
+        Provider::Bedrock => bedrock_body(body),
+        _ => body.clone()
+    };
+    let body = serde_json::to_string_pretty(&body).unwrap().replace('\'', "'\\''");
+    snippet += &format!("  -d '{}'\n", indent_lines(&body, "  "));
+
+    return snippet;
+}
+
+fn python_snippet(api_key: &APIKey, openai_api: OpenAIAPI, body: &Value) -> String {
+    let provider = api_key.provider;
+    match provider {
+        Provider::Mock => MOCK_SNIPPET.to_string(),
+        Provider::OpenAI if openai_api == OpenAIAPI::Responses => format!(
+            concat!(
+                "import os\n",
+                "from openai import OpenAI\n\n",
+                "client = OpenAI(api_key=os.environ[\"{}\"]{})\n\n",
+                "stream = client.responses.create(\n",
+                "{}",
+                ")\n",
+                "for event in stream:\n",
+                "    if event.type == \"response.output_text.delta\":\n",
+                "        print(event.delta, end=\"\", flush=True)\n"
+            ),
+            key_variable(provider),
+            base_url_argument(api_key, Language::Python),
+            python_kwargs(body)
+        ),
+        Provider::OpenAI => format!(
+            concat!(
+                "import os\n",
+                "from openai import OpenAI\n\n",
+                "client = OpenAI(api_key=os.environ[\"{}\"]{})\n\n",
+                "stream = client.chat.completions.create(\n",
+                "{}",
+                ")\n",
+                "for chunk in stream:\n",
+                "    if chunk.choices:\n",
+                "        print(chunk.choices[0].delta.content or \"\", end=\"\", flush=True)\n"
+            ),
+            key_variable(provider),
+            base_url_argument(api_key, Language::Python),
+            python_kwargs(body)
+        ),
+        Provider::Anthropic | Provider::Bedrock => {
+            // messages.stream implies streaming and rejects the flag
+            let mut body = body.clone();
+            body.as_object_mut().unwrap().remove("stream");
+            // the Bedrock client picks up AWS credentials from the environment
+            let client = match provider {
+                Provider::Bedrock => format!(
+                    "client = anthropic.AnthropicBedrock(aws_region=\"{}\")",
+                    api_key.region.as_deref().unwrap_or("us-east-1")
+                ),
+                _ => format!(
+                    "client = anthropic.Anthropic(api_key=os.environ[\"{}\"]{})",
+                    key_variable(provider),
+                    base_url_argument(api_key, Language::Python)
+                )
+            };
+            format!(
+                concat!(
+                    "import os\n",
+                    "import anthropic\n\n",
+                    "{}\n\n",
+                    "with client.messages.stream(\n",
+                    "{}",
+                    ") as stream:\n",
+                    "    for text in stream.text_stream:\n",
+                    "        print(text, end=\"\", flush=True)\n"
+                ),
+                client,
+                python_kwargs(&body)
+            )
+        },
+        Provider::AzureOpenAI => {
+            // the SDK takes the deployment name in place of the model
+            let mut body = body.clone();
+            body["model"] = Value::String(api_key.deployment.clone().unwrap_or_default());
+            format!(
+                concat!(
+                    "import os\n",
+                    "from openai import AzureOpenAI\n\n",
+                    "client = AzureOpenAI(\n",
+                    "    api_key=os.environ[\"{}\"],\n",
+                    "    api_version=\"{}\",\n",
+                    "    azure_endpoint=\"{}\",\n",
+                    ")\n\n",
+                    "stream = client.chat.completions.create(\n",
+                    "{}",
+                    ")\n",
+                    "for chunk in stream:\n",
+                    "    if chunk.choices:\n",
+                    "        print(chunk.choices[0].delta.content or \"\", end=\"\", flush=True)\n"
+                ),
+                key_variable(provider),
+                api_key.api_version.as_deref().unwrap_or(DEFAULT_AZURE_API_VERSION),
+                api_key.endpoint.as_deref().unwrap_or_default(),
+                python_kwargs(&body)
+            )
+        }
+    }
+}
+
+fn typescript_snippet(api_key: &APIKey, openai_api: OpenAIAPI, body: &Value) -> String {
+    let provider = api_key.provider;
+    match provider {
+        Provider::Mock => MOCK_SNIPPET.to_string(),
+        Provider::OpenAI if openai_api == OpenAIAPI::Responses => format!(
+            concat!(
+                "import OpenAI from \"openai\";\n\n",
+                "const client = new OpenAI({{ apiKey: process.env.{}{} }});\n\n",
+                "const stream = await client.responses.create({});\n",
+                "for await (const event of stream) {{\n",
+                "  if (event.type === \"response.output_text.delta\") {{\n",
+                "    process.stdout.write(event.delta);\n",
+                "  }}\n",
+                "}}\n"
+            ),
+            key_variable(provider),
+            base_url_argument(api_key, Language::TypeScript),
+            serde_json::to_string_pretty(body).unwrap()
+        ),
+        Provider::OpenAI => format!(
+            concat!(
+                "import OpenAI from \"openai\";\n\n",
+                "const client = new OpenAI({{ apiKey: process.env.{}{} }});\n\n",
+                "const stream = await client.chat.completions.create({});\n",
+                "for await (const chunk of stream) {{\n",
+                "  process.stdout.write(chunk.choices[0]?.delta?.content ?? \"\");\n",
+                "}}\n"
+            ),
+            key_variable(provider),
+            base_url_argument(api_key, Language::TypeScript),
+            serde_json::to_string_pretty(body).unwrap()
+        ),
+        Provider::Anthropic | Provider::Bedrock => {
+            let mut body = body.clone();
+            body.as_object_mut().unwrap().remove("stream");
+            let client = match provider {
+                Provider::Bedrock => format!(
+                    concat!(
+                        "import {{ AnthropicBedrock }} from \"@anthropic-ai/bedrock-sdk\";\n\n",
+                        "const client = new AnthropicBedrock({{ awsRegion: \"{}\" }});"
+                    ),
+                    api_key.region.as_deref().unwrap_or("us-east-1")
+                ),
+                _ => format!(
+                    concat!(
+                        "import Anthropic from \"@anthropic-ai/sdk\";\n\n",
+                        "const client = new Anthropic({{ apiKey: process.env.{}{} }});"
+                    ),
+                    key_variable(provider),
+                    base_url_argument(api_key, Language::TypeScript)
+                )
+            };
+            format!(
+                concat!(
+                    "{}\n\n",
+                    "const stream = client.messages.stream({});\n",
+                    "stream.on(\"text\", (text) => process.stdout.write(text));\n",
+                    "await stream.finalMessage();\n"
+                ),
+                client,
+                serde_json::to_string_pretty(&body).unwrap()
+            )
+        },
+        Provider::AzureOpenAI => {
+            let mut body = body.clone();
+            body["model"] = Value::String(api_key.deployment.clone().unwrap_or_default());
+            format!(
+                concat!(
+                    "import {{ AzureOpenAI }} from \"openai\";\n\n",
+                    "const client = new AzureOpenAI({{\n",
+                    "  apiKey: process.env.{},\n",
+                    "  apiVersion: \"{}\",\n",
+                    "  endpoint: \"{}\",\n",
+                    "}});\n\n",
+                    "const stream = await client.chat.completions.create({});\n",
+                    "for await (const chunk of stream) {{\n",
+                    "  process.stdout.write(chunk.choices[0]?.delta?.content ?? \"\");\n",
+                    "}}\n"
+                ),
+                key_variable(provider),
+                api_key.api_version.as_deref().unwrap_or(DEFAULT_AZURE_API_VERSION),
+                api_key.endpoint.as_deref().unwrap_or_default(),
+                serde_json::to_string_pretty(&body).unwrap()
+            )
+        }
+    }
+}
+
+pub fn generate_snippet(language: Language, api_key: &APIKey, openai_api: OpenAIAPI, body: &Value) -> String {
+    match language {
+        Language::Curl => curl_snippet(api_key, openai_api, body),
+        Language::Python => python_snippet(api_key, openai_api, body),
+        Language::TypeScript => typescript_snippet(api_key, openai_api, body)
+    }
+}
+
+pub fn CodeWindow(api_key: APIKey, openai_api: OpenAIAPI, body: Value) -> Window {
+    let window = Window::new();
+    window.set_css_classes(&["popup-window"]);
+    window.set_title(Some("Export as Code"));
+    window.set_default_size(500, 500);
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 10);
+
+    let hbox = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    hbox.append(&Label::new(Some("Language:")));
+    let language_names: Vec<String> = LANGUAGES.iter().map(|language| language.to_string()).collect();
+    let language_names: Vec<&str> = language_names.iter().map(|name| name.as_str()).collect();
+    let store = gtk::StringList::new(&language_names);
+    let language_dropdown = DropDown::new(Some(store), None::<&gtk::Expression>);
+    hbox.append(&language_dropdown);
+    vbox.append(&hbox);
+
+    let text_view = gtk::TextView::new();
+    text_view.set_editable(false);
+    text_view.set_monospace(true);
+    text_view.set_wrap_mode(gtk::WrapMode::WordChar);
+    text_view.buffer().set_text(&generate_snippet(LANGUAGES[0], &api_key, openai_api, &body));
+
+    let scrolled_window = ScrolledWindow::new();
+    scrolled_window.set_child(Some(&text_view));
+    scrolled_window.set_vexpand(true);
+    vbox.append(&scrolled_window);
+
+    language_dropdown.connect_notify(Some("selected"), {
+        let text_view = text_view.clone();
+        move |dropdown, _| {
+            let language = LANGUAGES[dropdown.selected() as usize];
+            text_view.buffer().set_text(&generate_snippet(language, &api_key, openai_api, &body));
+        }
+    });
+
+    let copy_button = Button::with_label("Copy");
+    copy_button.set_halign(gtk::Align::End);
+    copy_button.connect_clicked(move |button| {
+        button.clipboard().set_text(&get_buffer_content(&text_view.buffer()));
+    });
+    vbox.append(&copy_button);
+
+    window.set_child(Some(&vbox));
+    return window;
+}
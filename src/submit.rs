@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc, time::SystemTime};
 
 use futures::StreamExt;
 use gtk::{glib::{self, clone}, prelude::*};
@@ -7,61 +7,294 @@ use reqwest::{header::{HeaderMap, HeaderValue, CONTENT_TYPE}, RequestBuilder};
 use reqwest_eventsource::{Event, EventSource};
 use serde_json::json;
 use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
 
-use crate::settings::{Provider, Settings};
+use crate::{
+    bedrock,
+    context::{plan_context, OverflowStrategy, SUMMARY_TOKENS},
+    conversation::Conversation,
+    inspector::LogEntry,
+    message::{ContentBlock, Message, Metadata, Role},
+    mock,
+    pricing::{model_pricing, Usage},
+    settings::{APIKey, OpenAIAPI, Provider, Settings},
+    tokens::{count_message_tokens, count_prompt_tokens, ContextFit}
+};
 
-fn build_openai_request(key: &str) -> RequestBuilder {
+const SUMMARY_PROMPT: &str = "Summarize our conversation so far. Keep every fact, decision and open question \
+    needed to continue it, and nothing else.";
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+pub const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+
+// a key's base URL can point at a proxy, a compatible server or a local test server
+pub fn api_url(api_key: &APIKey, path: &str) -> String {
+    let default_base_url = match api_key.provider {
+        Provider::Anthropic => ANTHROPIC_BASE_URL,
+        _ => OPENAI_BASE_URL
+    };
+    let base_url = api_key.base_url.as_deref().unwrap_or(default_base_url);
+
+    return format!("{}{}", base_url.trim_end_matches('/'), path);
+}
+
+pub fn openai_headers(key: &str) -> HeaderMap {
     let mut headers = HeaderMap::new();
     headers.insert("Authorization", HeaderValue::from_str(&format!("Bearer {}", key)).unwrap());
+
+    return headers;
+}
+
+pub fn anthropic_headers(key: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-api-key", HeaderValue::from_str(key).unwrap());
+    headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+
+    return headers;
+}
+
+fn build_openai_request(api_key: &APIKey, path: &str) -> RequestBuilder {
+    let mut headers = openai_headers(&api_key.key);
     headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
     let request_builder = reqwest::Client::new()
-        .post("https://api.openai.com/v1/chat/completions")
+        .post(api_url(api_key, path))
         .headers(headers);
 
     return request_builder;
 }
 
-fn build_anthropic_request(key: &str) -> RequestBuilder {
-    let mut headers = HeaderMap::new();
-    headers.insert("x-api-key", HeaderValue::from_str(key).unwrap());
-    headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+fn build_anthropic_request(api_key: &APIKey) -> RequestBuilder {
+    let mut headers = anthropic_headers(&api_key.key);
     headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
     let request_builder = reqwest::Client::new()
-        .post("https://api.anthropic.com/v1/messages")
+        .post(api_url(api_key, "/messages"))
         .headers(headers);
 
     return request_builder;
 }
 
-async fn fetch_response_tokens(
-    settings: Mutable<Settings>,
-    exchanges: &[(String, String)],
-    prompt: &str, streaming: Mutable<bool>,
-    res: impl Fn(&str),
-    err: impl Fn(String)
-) {
-    let settings = settings.lock_ref().clone();
+// the first GA version that streams usage when asked to
+pub const DEFAULT_AZURE_API_VERSION: &str = "2024-10-21";
 
-    let mut messages: Vec<serde_json::Value> = vec![];
-    for (prompt, response) in exchanges {
-        messages.push(json!({
-            "role": "user",
-            "content": prompt
-        }));
-
-        messages.push(json!({
-            "role": "assistant",
-            "content": response
-        }));
-    }
-    messages.push(json!({
-        "role": "user",
-        "content": prompt
-    }));
+// keys saved with an older version would have stream_options rejected
+fn azure_streams_usage(api_key: &APIKey) -> bool {
+    let api_version = api_key.api_version.as_deref().unwrap_or(DEFAULT_AZURE_API_VERSION);
+    return api_version >= "2024-09-01";
+}
+
+// Azure routes by deployment rather than by the model in the body
+pub fn azure_openai_url(api_key: &APIKey) -> String {
+    return format!(
+        "{}/openai/deployments/{}/chat/completions?api-version={}",
+        api_key.endpoint.as_deref().unwrap_or_default().trim_end_matches('/'),
+        api_key.deployment.as_deref().unwrap_or_default(),
+        api_key.api_version.as_deref().unwrap_or(DEFAULT_AZURE_API_VERSION)
+    );
+}
 
-    let body = json!({
+// a key pasted with a line break or other control character can't go in a header
+pub fn azure_headers(key: &str) -> Result<HeaderMap, String> {
+    let mut headers = HeaderMap::new();
+    let value = HeaderValue::from_str(key).map_err(|_| "Invalid characters in the API key; check the Azure key.".to_string())?;
+    headers.insert("api-key", value);
+
+    return Ok(headers);
+}
+
+fn build_azure_openai_request(api_key: &APIKey) -> Result<RequestBuilder, String> {
+    let mut headers = azure_headers(&api_key.key)?;
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    let request_builder = reqwest::Client::new()
+        .post(azure_openai_url(api_key))
+        .headers(headers);
+
+    return Ok(request_builder);
+}
+
+pub fn uses_responses_api(settings: &Settings) -> bool {
+    let provider = settings.api_key.map(|index| settings.api_keys[index].provider);
+    return matches!(provider, Some(Provider::OpenAI)) && settings.openai_api == OpenAIAPI::Responses;
+}
+
+fn only_text(message: &Message) -> bool {
+    return message.content.iter().all(|block| matches!(block, ContentBlock::Text { .. }));
+}
+
+// chat completions take tool results as messages of their own and tool calls beside the content
+pub fn openai_messages(message: &Message) -> Vec<serde_json::Value> {
+    let role = match message.role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::Tool => "tool"
+    };
+
+    let mut parts = vec![];
+    let mut tool_calls = vec![];
+    let mut tool_results = vec![];
+    for block in &message.content {
+        match block {
+            ContentBlock::Text { text } => parts.push(json!({ "type": "text", "text": text })),
+            ContentBlock::Image { media_type, data } => parts.push(json!({
+                "type": "image_url",
+                "image_url": { "url": format!("data:{};base64,{}", media_type, data) }
+            })),
+            ContentBlock::ToolUse { id, name, input } => tool_calls.push(json!({
+                "id": id,
+                "type": "function",
+                "function": { "name": name, "arguments": input.to_string() }
+            })),
+            ContentBlock::ToolResult { tool_use_id, content } => tool_results.push(json!({
+                "role": "tool",
+                "tool_call_id": tool_use_id,
+                "content": content
+            }))
+        }
+    }
+
+    let mut messages = vec![];
+    if !parts.is_empty() || !tool_calls.is_empty() {
+        // plain text stays a string, which every compatible server understands
+        let content = if only_text(message) { json!(message.text_content()) } else { json!(parts) };
+        let mut entry = json!({ "role": role, "content": content });
+        if !tool_calls.is_empty() {
+            entry["tool_calls"] = json!(tool_calls);
+        }
+        messages.push(entry);
+    }
+    messages.extend(tool_results);
+
+    return messages;
+}
+
+// system messages go in the separate system field, and tool results are user content
+pub fn anthropic_message(message: &Message) -> serde_json::Value {
+    let role = match message.role {
+        Role::Assistant => "assistant",
+        _ => "user"
+    };
+    if only_text(message) {
+        return json!({ "role": role, "content": message.text_content() });
+    }
+
+    let content: Vec<serde_json::Value> = message.content
+        .iter()
+        .map(|block| match block {
+            ContentBlock::Text { text } => json!({ "type": "text", "text": text }),
+            ContentBlock::Image { media_type, data } => json!({
+                "type": "image",
+                "source": { "type": "base64", "media_type": media_type, "data": data }
+            }),
+            ContentBlock::ToolUse { id, name, input } => json!({ "type": "tool_use", "id": id, "name": name, "input": input }),
+            ContentBlock::ToolResult { tool_use_id, content } => json!({
+                "type": "tool_result",
+                "tool_use_id": tool_use_id,
+                "content": content
+            })
+        })
+        .collect();
+
+    return json!({ "role": role, "content": content });
+}
+
+// the Responses API takes tool calls and their outputs as input items next to the messages
+fn responses_items(message: &Message) -> Vec<serde_json::Value> {
+    let (role, text_type) = match message.role {
+        Role::System => ("system", "input_text"),
+        Role::User | Role::Tool => ("user", "input_text"),
+        Role::Assistant => ("assistant", "output_text")
+    };
+
+    let mut parts = vec![];
+    let mut items = vec![];
+    for block in &message.content {
+        match block {
+            ContentBlock::Text { text } => parts.push(json!({ "type": text_type, "text": text })),
+            ContentBlock::Image { media_type, data } => parts.push(json!({
+                "type": "input_image",
+                "image_url": format!("data:{};base64,{}", media_type, data)
+            })),
+            ContentBlock::ToolUse { id, name, input } => items.push(json!({
+                "type": "function_call",
+                "call_id": id,
+                "name": name,
+                "arguments": input.to_string()
+            })),
+            ContentBlock::ToolResult { tool_use_id, content } => items.push(json!({
+                "type": "function_call_output",
+                "call_id": tool_use_id,
+                "output": content
+            }))
+        }
+    }
+
+    if !parts.is_empty() {
+        let content = if only_text(message) { json!(message.text_content()) } else { json!(parts) };
+        items.insert(0, json!({ "role": role, "content": content }));
+    }
+
+    return items;
+}
+
+// with a previous response id the server already holds the history,
+// so messages should only be the ones added since that response
+pub fn build_request_body(
+    settings: &Settings,
+    messages: &[Message],
+    previous_response_id: Option<&str>
+) -> serde_json::Value {
+    let provider = settings.api_key.map(|index| settings.api_keys[index].provider);
+
+    if uses_responses_api(settings) {
+        let input: Vec<serde_json::Value> = messages.iter().flat_map(responses_items).collect();
+        let mut body = json!({
+            "model": settings.model,
+            "max_output_tokens": settings.max_tokens,
+            "temperature": settings.temperature,
+            "stream": true,
+            "input": input
+        });
+        if !settings.instructions.is_empty() {
+            body["instructions"] = json!(settings.instructions);
+        }
+        if let Some(previous_response_id) = previous_response_id {
+            body["previous_response_id"] = json!(previous_response_id);
+        }
+
+        return body;
+    }
+
+    if let Some(Provider::Anthropic) | Some(Provider::Bedrock) = provider {
+        let system: Vec<String> = messages
+            .iter()
+            .filter(|message| message.role == Role::System)
+            .map(|message| message.text_content())
+            .collect();
+        let messages: Vec<serde_json::Value> = messages
+            .iter()
+            .filter(|message| message.role != Role::System)
+            .map(anthropic_message)
+            .collect();
+
+        let mut body = json!({
+            "model": settings.model,
+            "max_tokens": settings.max_tokens,
+            "temperature": settings.temperature,
+            "stream": true,
+            "messages": messages
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system.join("\n\n"));
+        }
+
+        return body;
+    }
+
+    let messages: Vec<serde_json::Value> = messages.iter().flat_map(openai_messages).collect();
+    let mut body = json!({
         "model": settings.model,
         "max_tokens": settings.max_tokens,
         "temperature": settings.temperature,
@@ -69,41 +302,211 @@ async fn fetch_response_tokens(
         "messages": messages
     });
 
-    let api_key = &settings.api_keys[settings.api_key.expect("No key available.")];
-    let request_builder = match api_key.provider {
-        Provider::OpenAI => build_openai_request(&api_key.key)
-            .body(body.to_string()),
-        Provider::Anthropic => build_anthropic_request(&api_key.key)
-            .body(body.to_string())
+    // OpenAI and Azure only report usage for streamed responses when asked to
+    let streams_usage = match settings.api_key.map(|index| &settings.api_keys[index]) {
+        Some(api_key) => match api_key.provider {
+            Provider::OpenAI => true,
+            Provider::AzureOpenAI => azure_streams_usage(api_key),
+            _ => false
+        },
+        None => false
+    };
+    if streams_usage {
+        body["stream_options"] = json!({ "include_usage": true });
+    }
+
+    return body;
+}
+
+// the readers take the usage object itself, which sits in a different place
+// in streamed events than in batch results
+pub fn read_openai_usage(reported: &serde_json::Value, usage: &mut Usage) {
+    let cached_tokens = reported["prompt_tokens_details"]["cached_tokens"].as_u64().unwrap_or(0);
+    if let Some(prompt_tokens) = reported["prompt_tokens"].as_u64() {
+        usage.input_tokens = prompt_tokens - cached_tokens.min(prompt_tokens);
+        usage.cache_read_tokens = cached_tokens;
+    }
+    if let Some(completion_tokens) = reported["completion_tokens"].as_u64() {
+        usage.output_tokens = completion_tokens;
+    }
+}
+
+pub fn read_responses_usage(reported: &serde_json::Value, usage: &mut Usage) {
+    let cached_tokens = reported["input_tokens_details"]["cached_tokens"].as_u64().unwrap_or(0);
+    if let Some(input_tokens) = reported["input_tokens"].as_u64() {
+        usage.input_tokens = input_tokens - cached_tokens.min(input_tokens);
+        usage.cache_read_tokens = cached_tokens;
+    }
+    if let Some(output_tokens) = reported["output_tokens"].as_u64() {
+        usage.output_tokens = output_tokens;
+    }
+}
+
+pub fn read_anthropic_usage(reported: &serde_json::Value, usage: &mut Usage) {
+    if let Some(input_tokens) = reported["input_tokens"].as_u64() {
+        usage.input_tokens = input_tokens;
+    }
+    if let Some(cache_read_tokens) = reported["cache_read_input_tokens"].as_u64() {
+        usage.cache_read_tokens = cache_read_tokens;
+    }
+    if let Some(cache_write_tokens) = reported["cache_creation_input_tokens"].as_u64() {
+        usage.cache_write_tokens = cache_write_tokens;
+    }
+    if let Some(output_tokens) = reported["output_tokens"].as_u64() {
+        usage.output_tokens = output_tokens;
+    }
+}
+
+fn handle_openai_event(data: &serde_json::Value, res: impl Fn(&str), err: impl Fn(String), metadata: &mut Metadata) {
+    // errors that happen after the stream has started arrive as an event
+    if let Some(message) = data["error"]["message"].as_str() {
+        err(message.to_string());
+    }
+    // Azure sends chunks with empty choices for its content filter results,
+    // which index to null and are skipped
+    if let Some(token) = data["choices"][0]["delta"]["content"].as_str() {
+        res(token);
+    }
+    if let Some(stop_reason) = data["choices"][0]["finish_reason"].as_str() {
+        metadata.stop_reason = Some(stop_reason.to_string());
+    }
+    if data["usage"].is_object() {
+        read_openai_usage(&data["usage"], metadata.usage.get_or_insert_with(Usage::default));
+    }
+}
+
+// the Responses API streams typed events rather than chat completion chunks
+fn handle_responses_event(
+    data: &serde_json::Value,
+    res: impl Fn(&str),
+    err: impl Fn(String),
+    metadata: &mut Metadata,
+    response_id: &mut Option<String>
+) {
+    match data["type"].as_str() {
+        Some("response.output_text.delta") => {
+            if let Some(token) = data["delta"].as_str() {
+                res(token);
+            }
+        },
+        // a response cut short by max_output_tokens is still stored and can be chained from
+        Some("response.completed") | Some("response.incomplete") => {
+            *response_id = data["response"]["id"].as_str().map(|id| id.to_string());
+            metadata.stop_reason = data["response"]["incomplete_details"]["reason"]
+                .as_str()
+                .or(data["response"]["status"].as_str())
+                .map(|stop_reason| stop_reason.to_string());
+            read_responses_usage(&data["response"]["usage"], metadata.usage.get_or_insert_with(Usage::default));
+        },
+        Some("response.failed") => {
+            let message = data["response"]["error"]["message"].as_str().unwrap_or("Response failed");
+            err(message.to_string());
+        },
+        Some("error") => {
+            let message = data["message"].as_str().unwrap_or("Unknown error");
+            err(message.to_string());
+        },
+        _ => ()
+    }
+}
+
+fn handle_anthropic_event(data: &serde_json::Value, res: impl Fn(&str), err: impl Fn(String), metadata: &mut Metadata) {
+    if let Some(token) = data["delta"]["text"].as_str() {
+        res(token);
+    }
+    // e.g. overloaded_error, sent in place of the rest of the response
+    if data["type"] == "error" {
+        err(data["error"]["message"].as_str().unwrap_or("Unknown error").to_string());
+        return;
+    }
+    if let Some(stop_reason) = data["delta"]["stop_reason"].as_str() {
+        metadata.stop_reason = Some(stop_reason.to_string());
+    }
+
+    // message_start carries the input usage, message_delta the running output count
+    let reported = match data["type"].as_str() {
+        Some("message_start") => &data["message"]["usage"],
+        Some("message_delta") => &data["usage"],
+        _ => return
     };
+    read_anthropic_usage(reported, metadata.usage.get_or_insert_with(Usage::default));
+}
+
+// a plain request/response round trip, for the endpoints that don't stream
+pub async fn send_request(request_builder: RequestBuilder, body: &str, log: &impl Fn(LogEntry)) -> Result<String, String> {
+    let (client, request) = request_builder.build_split();
+    let request = request.map_err(|err| err.to_string())?;
+    log(LogEntry::request(&request, body));
+
+    let response = client.execute(request).await.map_err(|err| err.to_string())?;
+    let status = response.status();
+    let text = response.text().await.map_err(|err| err.to_string())?;
+    log(LogEntry::status(status));
+
+    if !status.is_success() {
+        let message = serde_json::from_str::<serde_json::Value>(&text)
+            .ok()
+            .and_then(|data| data["error"]["message"].as_str().map(|message| message.to_string()))
+            .unwrap_or(text);
+        return Err(format!("{}: {}", status, message));
+    }
 
-    let mut es = EventSource::new(request_builder).unwrap();
-    while let Some(event) = es.next().await {
-        if !(*streaming.lock_ref()) {
-            es.close();
-            break;
+    return Ok(text);
+}
+
+async fn stream_sse(
+    request_builder: RequestBuilder,
+    body: &str,
+    cancel: CancellationToken,
+    mut on_event: impl FnMut(&serde_json::Value),
+    err: impl Fn(String),
+    log: impl Fn(LogEntry)
+) {
+    let request_builder = request_builder.body(body.to_string());
+
+    if let Some(Ok(request)) = request_builder.try_clone().map(|builder| builder.build()) {
+        log(LogEntry::request(&request, body));
+    }
+
+    // fails when the body can't be cloned for a retry, as with a URL that isn't absolute
+    let mut es = match EventSource::new(request_builder) {
+        Ok(es) => es,
+        Err(_) => {
+            let err_msg = "Couldn't build the request; check the key's URL.".to_string();
+            log(LogEntry::status(&err_msg));
+            err(err_msg);
+            return;
         }
+    };
+    loop {
+        // racing the stream against the token drops a stalled connection right away
+        let event = tokio::select! {
+            event = es.next() => event,
+            _ = cancel.cancelled() => {
+                log(LogEntry::status("Cancelled"));
+                es.close();
+                break;
+            }
+        };
+        let event = match event {
+            Some(event) => event,
+            None => break
+        };
 
         match event {
-            Ok(Event::Open) => (),
+            Ok(Event::Open) => log(LogEntry::status("Connection opened")),
             Ok(Event::Message(message)) => {
+                log(LogEntry::event(&message.event, &message.data));
                 if let Ok(data) = serde_json::from_str::<serde_json::Value>(&message.data) {
-                    match api_key.provider {
-                        Provider::OpenAI => {
-                            if let Some(token) = data["choices"][0]["delta"]["content"].as_str() {
-                                res(token);
-                            }
-                        },
-                        Provider::Anthropic => {
-                            if let Some(token) = data["delta"]["text"].as_str() {
-                                res(token);
-                            }
-                        }
-                    };
+                    on_event(&data);
                 }
             },
-            Err(reqwest_eventsource::Error::StreamEnded) => { es.close(); },
+            Err(reqwest_eventsource::Error::StreamEnded) => {
+                log(LogEntry::status("Stream ended"));
+                es.close();
+            },
             Err(err_msg) => {
+                log(LogEntry::status(&err_msg));
                 err(err_msg.to_string());
                 es.close();
             }
@@ -111,14 +514,242 @@ async fn fetch_response_tokens(
     }
 }
 
+// returns the reply's metadata and, for the Responses API, the id later requests can chain from
+async fn fetch_response_tokens(
+    settings: Mutable<Settings>,
+    messages: &[Message],
+    previous_response_id: Option<&str>,
+    cancel: CancellationToken,
+    res: impl Fn(&str),
+    err: impl Fn(String),
+    log: impl Fn(LogEntry)
+) -> (Metadata, Option<String>) {
+    let settings = settings.lock_ref().clone();
+    let mut metadata = Metadata {
+        model: Some(settings.model.clone()),
+        timestamp: Some(SystemTime::now()),
+        ..Metadata::default()
+    };
+    let mut response_id: Option<String> = None;
+
+    let body = build_request_body(&settings, messages, previous_response_id);
+    let api_key = &settings.api_keys[settings.api_key.expect("No key available.")];
+    match api_key.provider {
+        Provider::OpenAI if uses_responses_api(&settings) => stream_sse(
+            build_openai_request(api_key, "/responses"),
+            &body.to_string(),
+            cancel,
+            |data| handle_responses_event(data, &res, &err, &mut metadata, &mut response_id),
+            &err,
+            &log
+        ).await,
+        Provider::OpenAI => stream_sse(
+            build_openai_request(api_key, "/chat/completions"),
+            &body.to_string(),
+            cancel,
+            |data| handle_openai_event(data, &res, &err, &mut metadata),
+            &err,
+            &log
+        ).await,
+        Provider::AzureOpenAI => match build_azure_openai_request(api_key) {
+            Ok(request_builder) => stream_sse(
+                request_builder,
+                &body.to_string(),
+                cancel,
+                |data| handle_openai_event(data, &res, &err, &mut metadata),
+                &err,
+                &log
+            ).await,
+            Err(err_msg) => {
+                log(LogEntry::status(&err_msg));
+                err(err_msg);
+            }
+        },
+        Provider::Anthropic => stream_sse(
+            build_anthropic_request(api_key),
+            &body.to_string(),
+            cancel,
+            |data| handle_anthropic_event(data, &res, &err, &mut metadata),
+            &err,
+            &log
+        ).await,
+        Provider::Bedrock => bedrock::stream_response(
+            api_key,
+            &settings.model,
+            &body,
+            cancel,
+            |data| handle_anthropic_event(data, &res, &err, &mut metadata),
+            &err,
+            &log
+        ).await,
+        Provider::Mock => mock::stream_response(
+            &api_key.mock.clone().unwrap_or_default(),
+            &body,
+            cancel,
+            |data| handle_openai_event(data, &res, &err, &mut metadata),
+            &err,
+            &log
+        ).await
+    }
+
+    if let Some(usage) = metadata.usage.as_mut() {
+        let cost = model_pricing(&settings.pricing, &settings.model).map(|pricing| pricing.cost(usage));
+        usage.cost = cost;
+    }
+
+    return (metadata, response_id);
+}
+
+// stands in for the messages a summary covers
+fn summary_messages(summary: &str) -> [Message; 2] {
+    return [
+        Message::text(Role::User, &format!("Summary of our earlier conversation:\n\n{}", summary)),
+        Message::text(Role::Assistant, "Understood.")
+    ];
+}
+
+async fn summarize_messages(
+    settings: &Settings,
+    messages: &[Message],
+    cancel: CancellationToken,
+    log: impl Fn(LogEntry)
+) -> Result<(String, Option<Usage>), String> {
+    let mut settings = settings.clone();
+    settings.max_tokens = SUMMARY_TOKENS;
+
+    let summary = RefCell::new(String::new());
+    let error: RefCell<Option<String>> = RefCell::new(None);
+    let mut messages = messages.to_vec();
+    messages.push(Message::text(Role::User, SUMMARY_PROMPT));
+    let (metadata, _) = fetch_response_tokens(
+        Mutable::new(settings),
+        &messages,
+        None,
+        cancel.clone(),
+        |token| summary.borrow_mut().push_str(token),
+        |err| { *error.borrow_mut() = Some(err); },
+        log
+    ).await;
+
+    if let Some(err) = error.into_inner() {
+        return Err(err);
+    }
+    // whatever streamed before a cancel is only part of a summary
+    if cancel.is_cancelled() {
+        return Err("Cancelled.".to_string());
+    }
+    let summary = summary.into_inner();
+    if summary.trim().is_empty() {
+        return Err("The summary came back empty.".to_string());
+    }
+
+    return Ok((summary, metadata.usage));
+}
+
+// the messages `included` keeps, and the ones it leaves out
+fn split_context(history: &[Message], included: &[bool]) -> (Vec<Message>, Vec<Message>) {
+    let mut kept = vec![];
+    let mut dropped = vec![];
+    for (message, included) in history.iter().zip(included) {
+        if *included {
+            kept.push(message.clone());
+        } else {
+            dropped.push(message.clone());
+        }
+    }
+
+    return (kept, dropped);
+}
+
+// the messages `included` keeps, after a summary of the ones it leaves out when summarizing
+#[allow(clippy::too_many_arguments)]
+async fn build_context(
+    conversation: &Conversation,
+    settings: &Settings,
+    strategy: OverflowStrategy,
+    history: &[Message],
+    included: &[bool],
+    token: &CancellationToken,
+    request_log: &MutableVec<LogEntry>,
+    session_cost: &Mutable<f64>
+) -> Result<Vec<Message>, String> {
+    let (mut context, dropped) = split_context(history, included);
+    if strategy != OverflowStrategy::Summarize || dropped.is_empty() {
+        return Ok(context);
+    }
+
+    // messages only ever fall out from the front, so an earlier summary still
+    // covers the start and just the ones dropped since need summarizing
+    let cached = conversation.summary
+        .borrow()
+        .clone()
+        .filter(|(covered, _)| dropped.starts_with(covered));
+    let (summary, usage) = match cached {
+        Some((covered, summary)) if covered.len() == dropped.len() => (summary, None),
+        cached => {
+            let messages: Vec<Message> = match &cached {
+                Some((covered, summary)) => summary_messages(summary)
+                    .into_iter()
+                    .chain(dropped[covered.len()..].iter().cloned())
+                    .collect(),
+                None => dropped.clone()
+            };
+            summarize_messages(
+                settings,
+                &messages,
+                token.clone(),
+                |entry| request_log.lock_mut().push_cloned(entry)
+            ).await.map_err(|err| format!("Summarizing older messages failed: {}", err))?
+        }
+    };
+
+    if let Some(cost) = usage.and_then(|usage| usage.cost) {
+        *session_cost.lock_mut() += cost;
+    }
+    context.splice(0..0, summary_messages(&summary));
+    *conversation.summary.borrow_mut() = Some((dropped, summary));
+
+    return Ok(context);
+}
+
+// what build_context would send for `messages` with nothing more typed, for showing as code;
+// a summary that hasn't been made yet is stood in for by a note saying what it would cover
+pub fn preview_context(conversation: &Conversation, settings: &Settings, strategy: OverflowStrategy, messages: &[Message]) -> Vec<Message> {
+    let (_, included) = plan_context(
+        settings,
+        &strategy,
+        &count_message_tokens(&settings.model, messages),
+        count_prompt_tokens(&settings.model, "")
+    );
+    let (mut context, dropped) = split_context(messages, &included);
+    if strategy != OverflowStrategy::Summarize || dropped.is_empty() {
+        return context;
+    }
+
+    let summary = match conversation.summary.borrow().as_ref() {
+        Some((covered, summary)) if *covered == dropped => summary.clone(),
+        _ => format!("[a summary of the {} messages left out, made when the request is sent]", dropped.len())
+    };
+    context.splice(0..0, summary_messages(&summary));
+
+    return context;
+}
+
+// a non-empty prompt is added as a user message before the request goes out,
+// so an empty one sends the conversation as it stands
 pub fn SubmitButton(
-    exchanges: MutableVec<(String, String)>,
+    conversation: Conversation,
     prompt: impl Fn() -> String + 'static,
     settings: Mutable<Settings>,
     clear_prompt: Rc<Notify>,
     response_tokens: MutableVec<String>,
     error: Mutable<String>,
-    streaming: Mutable<bool>
+    streaming: Mutable<bool>,
+    cancel: Mutable<CancellationToken>,
+    request_log: MutableVec<LogEntry>,
+    session_cost: Mutable<f64>,
+    strategy: Mutable<OverflowStrategy>,
+    previous_response: Mutable<Option<(String, usize)>>
 ) -> impl IsA<gtk::Widget> {
     let button = gtk::Button::builder()
         .label("Submit")
@@ -135,30 +766,104 @@ pub fn SubmitButton(
     button.connect_clicked(move |_| {
         *error.lock_mut() = String::new();
         let prompt = prompt();
+        let prompt = (!prompt.trim().is_empty()).then(|| Message::text(Role::User, &prompt));
+
+        let strategy = strategy.get();
+        let history = conversation.messages.lock_ref().to_vec();
+        if history.is_empty() && prompt.is_none() {
+            *error.lock_mut() = "Nothing to send.".to_string();
+            return;
+        }
+        let (estimate, included) = {
+            let settings = settings.lock_ref();
+            let message_tokens = count_message_tokens(&settings.model, &history);
+            let prompt_text = prompt.as_ref().map(|prompt| prompt.text_content()).unwrap_or_default();
+            let prompt_tokens = count_prompt_tokens(&settings.model, &prompt_text);
+            plan_context(&settings, &strategy, &message_tokens, prompt_tokens)
+        };
+        if estimate.fit() == ContextFit::Overflow {
+            *error.lock_mut() = format!("Request won't fit in the model's context window ({}).", estimate.describe());
+            return;
+        }
+
+        // chaining hands the server the whole conversation, so it only applies when nothing is left out;
+        // the server then only needs the messages added since the response
+        let chained_response = {
+            let settings = settings.lock_ref();
+            if uses_responses_api(&settings) && settings.chain_responses && !included.contains(&false) {
+                previous_response.get_cloned()
+            } else {
+                None
+            }
+        };
+
+        if let Some(prompt) = &prompt {
+            conversation.push(prompt.clone());
+            clear_prompt.notify_one();
+        }
 
         glib::spawn_future_local(clone!(
             @strong settings,
-            @strong exchanges,
-            @strong clear_prompt,
+            @strong conversation,
             @strong response_tokens,
             @strong error,
-            @strong streaming => async move {
+            @strong streaming,
+            @strong cancel,
+            @strong request_log,
+            @strong session_cost,
+            @strong previous_response => async move {
                 assert!(response_tokens.lock_ref().is_empty());
                 assert_eq!(*streaming.lock_ref(), false);
+                let token = CancellationToken::new();
+                cancel.set(token.clone());
                 *streaming.lock_mut() = true;
-                fetch_response_tokens(
+
+                let mut context: Vec<Message> = match &chained_response {
+                    Some((_, covered)) => history.iter().skip(*covered).cloned().collect(),
+                    None => {
+                        let current = settings.lock_ref().clone();
+                        let context = build_context(
+                            &conversation, &current, strategy, &history, &included, &token, &request_log, &session_cost
+                        ).await;
+                        match context {
+                            Ok(context) => context,
+                            Err(err) => {
+                                // cancelled while summarizing
+                                if !token.is_cancelled() {
+                                    *error.lock_mut() = err;
+                                }
+                                *streaming.lock_mut() = false;
+                                return;
+                            }
+                        }
+                    }
+                };
+                context.extend(prompt);
+
+                let (metadata, response_id) = fetch_response_tokens(
                     settings,
-                    exchanges.lock_ref().as_ref(),
-                    &prompt,
-                    streaming.clone(),
+                    &context,
+                    chained_response.as_ref().map(|(id, _)| id.as_str()),
+                    token,
                     |token| response_tokens.lock_mut().push_cloned(token.to_string()),
-                    |err| { *error.lock_mut() = err; }
+                    |err| { *error.lock_mut() = err; },
+                    |entry| request_log.lock_mut().push_cloned(entry)
                 ).await;
 
                 *streaming.lock_mut() = false;
+                if let Some(cost) = metadata.usage.and_then(|usage| usage.cost) {
+                    *session_cost.lock_mut() += cost;
+                }
                 if !response_tokens.lock_ref().is_empty() {    // response may be empty if cancel button is pressed before receiving first token
-                    exchanges.lock_mut().push_cloned((prompt, response_tokens.lock_ref().concat()));
-                    clear_prompt.notify_one();
+                    let response = response_tokens.lock_ref().concat();
+                    conversation.push(Message {
+                        role: Role::Assistant,
+                        content: vec![ContentBlock::Text { text: response }],
+                        metadata
+                    });
+                    // set after the push, which is the one change that keeps the chain intact
+                    let covered = conversation.messages.lock_ref().len();
+                    previous_response.set(response_id.map(|id| (id, covered)));
                     response_tokens.lock_mut().clear();
                 }
             }
@@ -166,4 +871,86 @@ pub fn SubmitButton(
     });
 
     return button;
-}
\ No newline at end of file
+}
+
+// streams a new version of an assistant message, which then replaces the old one as its sibling
+#[allow(clippy::too_many_arguments)]
+pub fn regenerate_message(
+    conversation: Conversation,
+    id: usize,
+    settings: Settings,
+    response_tokens: MutableVec<String>,
+    error: Mutable<String>,
+    streaming: Mutable<bool>,
+    cancel: Mutable<CancellationToken>,
+    request_log: MutableVec<LogEntry>,
+    session_cost: Mutable<f64>,
+    strategy: OverflowStrategy
+) {
+    if streaming.get() {
+        return;
+    }
+    *error.lock_mut() = String::new();
+
+    // the same selection Submit makes, over the messages before the reply
+    let history = conversation.history(id);
+    let (estimate, included) = plan_context(
+        &settings,
+        &strategy,
+        &count_message_tokens(&settings.model, &history),
+        count_prompt_tokens(&settings.model, "")
+    );
+    if estimate.fit() == ContextFit::Overflow {
+        *error.lock_mut() = format!("Request won't fit in the model's context window ({}).", estimate.describe());
+        return;
+    }
+
+    glib::spawn_future_local(async move {
+        assert!(response_tokens.lock_ref().is_empty());
+        let token = CancellationToken::new();
+        cancel.set(token.clone());
+        *streaming.lock_mut() = true;
+
+        let context = build_context(
+            &conversation, &settings, strategy, &history, &included, &token, &request_log, &session_cost
+        ).await;
+        let context = match context {
+            Ok(context) => context,
+            Err(err) => {
+                // cancelled while summarizing
+                if !token.is_cancelled() {
+                    *error.lock_mut() = err;
+                }
+                *streaming.lock_mut() = false;
+                return;
+            }
+        };
+
+        let (metadata, _) = fetch_response_tokens(
+            Mutable::new(settings),
+            &context,
+            None,
+            token,
+            |token| response_tokens.lock_mut().push_cloned(token.to_string()),
+            |err| { *error.lock_mut() = err; },
+            |entry| request_log.lock_mut().push_cloned(entry)
+        ).await;
+
+        *streaming.lock_mut() = false;
+        if let Some(cost) = metadata.usage.and_then(|usage| usage.cost) {
+            *session_cost.lock_mut() += cost;
+        }
+        if !response_tokens.lock_ref().is_empty() {
+            let response = response_tokens.lock_ref().concat();
+            conversation.regenerate(id, Message {
+                role: Role::Assistant,
+                content: vec![ContentBlock::Text { text: response }],
+                metadata
+            });
+            response_tokens.lock_mut().clear();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,284 @@
+// Replays recorded SSE transcripts from a local server through fetch_response_tokens,
+// whole or cut at arbitrary bytes.
+
+use std::cell::RefCell;
+
+use futures_signals::{signal::Mutable, signal_vec::MutableVec};
+use tokio_util::sync::CancellationToken;
+
+use super::{build_context, build_request_body, fetch_response_tokens};
+use crate::{
+    context::OverflowStrategy,
+    conversation::Conversation,
+    message::{Message, Role},
+    pricing::Usage,
+    settings::Provider,
+    test_server::{serve, test_settings, Reply}
+};
+
+const OPENAI_CHAT: &str = include_str!("../../tests/transcripts/openai_chat.sse");
+const OPENAI_ERROR: &str = include_str!("../../tests/transcripts/openai_error.sse");
+const ANTHROPIC_MESSAGES: &str = include_str!("../../tests/transcripts/anthropic_messages.sse");
+const ANTHROPIC_ERROR: &str = include_str!("../../tests/transcripts/anthropic_error.sse");
+
+struct Outcome {
+    tokens: Vec<String>,
+    errors: Vec<String>,
+    usage: Option<Usage>,
+    stop_reason: Option<String>,
+    // the raw request the server received
+    request: String
+}
+
+// one chunk per event, the way providers usually flush
+fn by_event(transcript: &str) -> Vec<Vec<u8>> {
+    return transcript
+        .split_inclusive("\n\n")
+        .map(|event| event.as_bytes().to_vec())
+        .collect();
+}
+
+// fixed-size chunks that cut through events, lines and multi-byte characters
+fn by_bytes(transcript: &str, size: usize) -> Vec<Vec<u8>> {
+    return transcript.as_bytes().chunks(size).map(|chunk| chunk.to_vec()).collect();
+}
+
+async fn run(provider: Provider, model: &str, reply: Reply) -> Outcome {
+    let (base_url, server) = serve(reply).await;
+    let tokens = RefCell::new(vec![]);
+    let errors = RefCell::new(vec![]);
+
+    let (metadata, _) = fetch_response_tokens(
+        test_settings(provider, model, base_url),
+        &[Message::text(Role::User, "Hi")],
+        None,
+        CancellationToken::new(),
+        |token| tokens.borrow_mut().push(token.to_string()),
+        |err| errors.borrow_mut().push(err),
+        |_| ()
+    ).await;
+
+    return Outcome {
+        tokens: tokens.into_inner(),
+        errors: errors.into_inner(),
+        usage: metadata.usage,
+        stop_reason: metadata.stop_reason,
+        request: server.await.unwrap()
+    };
+}
+
+#[tokio::test]
+async fn openai_stream() {
+    let outcome = run(Provider::OpenAI, "gpt-4o", Reply::stream(by_event(OPENAI_CHAT))).await;
+
+    assert!(outcome.request.starts_with("POST /v1/chat/completions "));
+    assert!(outcome.request.contains("\"include_usage\":true"));
+    assert_eq!(outcome.tokens, vec!["", "Hello", ", wörld", " 👋"]);
+    assert!(outcome.errors.is_empty());
+    let usage = outcome.usage.unwrap();
+    assert_eq!((usage.input_tokens, usage.cache_read_tokens, usage.output_tokens), (10, 2, 5));
+    assert!(usage.cost.is_some());
+    assert_eq!(outcome.stop_reason.as_deref(), Some("stop"));
+}
+
+#[tokio::test]
+async fn openai_stream_split_mid_character() {
+    let outcome = run(Provider::OpenAI, "gpt-4o", Reply::stream(by_bytes(OPENAI_CHAT, 7))).await;
+
+    assert_eq!(outcome.tokens, vec!["", "Hello", ", wörld", " 👋"]);
+    assert!(outcome.errors.is_empty());
+}
+
+#[tokio::test]
+async fn openai_error_event() {
+    let outcome = run(Provider::OpenAI, "gpt-4o", Reply::stream(by_event(OPENAI_ERROR))).await;
+
+    assert_eq!(outcome.tokens, vec!["", "Partial"]);
+    assert_eq!(outcome.errors, vec!["The server had an error while processing your request."]);
+}
+
+#[tokio::test]
+async fn openai_early_disconnect() {
+    let mut chunks = by_event(OPENAI_CHAT);
+    chunks.truncate(3);
+    let outcome = run(Provider::OpenAI, "gpt-4o", Reply { complete: false, ..Reply::stream(chunks) }).await;
+
+    assert_eq!(outcome.tokens, vec!["", "Hello", ", wörld"]);
+    assert_eq!(outcome.errors.len(), 1);
+    assert!(outcome.usage.is_none());
+}
+
+#[tokio::test]
+async fn openai_rate_limited() {
+    let body = r#"{"error":{"message":"Rate limit reached","type":"requests","code":"rate_limit_exceeded"}}"#;
+    let reply = Reply {
+        status: "429 Too Many Requests",
+        content_type: "application/json",
+        chunks: vec![body.as_bytes().to_vec()],
+        complete: true
+    };
+    let outcome = run(Provider::OpenAI, "gpt-4o", reply).await;
+
+    assert!(outcome.tokens.is_empty());
+    assert_eq!(outcome.errors.len(), 1);
+    assert!(outcome.errors[0].contains("429"));
+}
+
+#[tokio::test]
+async fn anthropic_stream() {
+    let outcome = run(
+        Provider::Anthropic,
+        "claude-3-5-sonnet-20241022",
+        Reply::stream(by_event(ANTHROPIC_MESSAGES))
+    ).await;
+
+    assert!(outcome.request.starts_with("POST /v1/messages "));
+    assert!(outcome.request.to_lowercase().contains("x-api-key: test-key"));
+    assert_eq!(outcome.tokens, vec!["Grüße", " aus 東京"]);
+    assert!(outcome.errors.is_empty());
+    let usage = outcome.usage.unwrap();
+    assert_eq!((usage.input_tokens, usage.cache_read_tokens, usage.output_tokens), (20, 4, 7));
+    assert_eq!(outcome.stop_reason.as_deref(), Some("end_turn"));
+}
+
+#[tokio::test]
+async fn anthropic_stream_split_mid_character() {
+    let outcome = run(
+        Provider::Anthropic,
+        "claude-3-5-sonnet-20241022",
+        Reply::stream(by_bytes(ANTHROPIC_MESSAGES, 5))
+    ).await;
+
+    assert_eq!(outcome.tokens, vec!["Grüße", " aus 東京"]);
+    assert!(outcome.errors.is_empty());
+}
+
+#[tokio::test]
+async fn anthropic_error_event() {
+    let outcome = run(
+        Provider::Anthropic,
+        "claude-3-5-sonnet-20241022",
+        Reply::stream(by_event(ANTHROPIC_ERROR))
+    ).await;
+
+    assert_eq!(outcome.tokens, vec!["Partial"]);
+    assert_eq!(outcome.errors, vec!["Overloaded"]);
+}
+
+#[tokio::test]
+async fn anthropic_early_disconnect() {
+    let mut chunks = by_bytes(ANTHROPIC_MESSAGES, 64);
+    chunks.truncate(8);
+    let outcome = run(
+        Provider::Anthropic,
+        "claude-3-5-sonnet-20241022",
+        Reply { complete: false, ..Reply::stream(chunks) }
+    ).await;
+
+    assert_eq!(outcome.errors.len(), 1);
+    assert!(!outcome.tokens.iter().any(|token| token.contains("東京")));
+}
+
+// summarizes the first two of three messages
+async fn summarize(token: &CancellationToken) -> (Conversation, Result<Vec<Message>, String>) {
+    let (base_url, _server) = serve(Reply::stream(by_event(OPENAI_CHAT))).await;
+    let settings = test_settings(Provider::OpenAI, "gpt-4o", base_url).get_cloned();
+    let conversation = Conversation::default();
+    let history = [
+        Message::text(Role::User, "First"),
+        Message::text(Role::Assistant, "Second"),
+        Message::text(Role::User, "Third")
+    ];
+
+    let context = build_context(
+        &conversation,
+        &settings,
+        OverflowStrategy::Summarize,
+        &history,
+        &[false, false, true],
+        token,
+        &MutableVec::new(),
+        &Mutable::new(0.0)
+    ).await;
+    return (conversation, context);
+}
+
+#[tokio::test]
+async fn summary_is_cached() {
+    let (conversation, context) = summarize(&CancellationToken::new()).await;
+
+    let context = context.unwrap();
+    assert_eq!(context.len(), 3);
+    assert!(context[0].text_content().contains("Hello, wörld 👋"));
+    let (covered, summary) = conversation.summary.borrow().clone().unwrap();
+    assert_eq!(covered.len(), 2);
+    assert_eq!(summary, "Hello, wörld 👋");
+}
+
+// what streamed before a cancel is at best part of a summary, so it's neither sent nor kept
+#[tokio::test]
+async fn cancelled_summary_is_not_cached() {
+    let token = CancellationToken::new();
+    token.cancel();
+    let (conversation, context) = summarize(&token).await;
+
+    assert!(context.is_err());
+    assert!(conversation.summary.borrow().is_none());
+}
+
+// Azure reports streamed usage from 2024-09-01 on and rejects stream_options before that
+#[test]
+fn azure_asks_for_usage_when_supported() {
+    let settings = test_settings(Provider::AzureOpenAI, "gpt-4o", String::new());
+    let messages = [Message::text(Role::User, "Hi")];
+    assert_eq!(build_request_body(&settings.lock_ref(), &messages, None)["stream_options"]["include_usage"], true);
+
+    settings.lock_mut().api_keys[0].api_version = Some("2024-06-01".to_string());
+    assert!(build_request_body(&settings.lock_ref(), &messages, None).get("stream_options").is_none());
+}
+
+#[tokio::test]
+async fn azure_key_with_unsendable_characters() {
+    let settings = test_settings(Provider::AzureOpenAI, "gpt-4o", String::new());
+    {
+        let mut settings = settings.lock_mut();
+        settings.api_keys[0].key = "key\nwith a line break".to_string();
+        settings.api_keys[0].endpoint = Some("https://example.openai.azure.com".to_string());
+        settings.api_keys[0].deployment = Some("gpt-4o".to_string());
+    }
+    let errors = RefCell::new(vec![]);
+
+    fetch_response_tokens(
+        settings,
+        &[Message::text(Role::User, "Hi")],
+        None,
+        CancellationToken::new(),
+        |_| (),
+        |err| errors.borrow_mut().push(err),
+        |_| ()
+    ).await;
+    assert_eq!(errors.into_inner(), vec!["Invalid characters in the API key; check the Azure key."]);
+}
+
+// an endpoint saved without a scheme makes a relative URL
+#[tokio::test]
+async fn azure_endpoint_without_scheme() {
+    let settings = test_settings(Provider::AzureOpenAI, "gpt-4o", String::new());
+    {
+        let mut settings = settings.lock_mut();
+        settings.api_keys[0].endpoint = Some("my-resource.openai.azure.com".to_string());
+        settings.api_keys[0].deployment = Some("gpt-4o".to_string());
+    }
+    let errors = RefCell::new(vec![]);
+
+    fetch_response_tokens(
+        settings,
+        &[Message::text(Role::User, "Hi")],
+        None,
+        CancellationToken::new(),
+        |_| (),
+        |err| errors.borrow_mut().push(err),
+        |_| ()
+    ).await;
+    assert_eq!(errors.into_inner(), vec!["Couldn't build the request; check the key's URL."]);
+}
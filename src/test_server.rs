@@ -0,0 +1,129 @@
+// A local HTTP server for tests that talk to a provider. It speaks chunked HTTP/1.1
+// so that a reply can be cut at any byte, and a missing final chunk looks like a
+// dropped connection rather than a clean end.
+
+use std::{collections::HashMap, time::Duration};
+
+use futures_signals::signal::Mutable;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, task::JoinHandle};
+
+use crate::settings::{APIKey, OpenAIAPI, Provider, Settings};
+
+pub struct Reply {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub chunks: Vec<Vec<u8>>,
+    // false drops the connection without the terminating chunk
+    pub complete: bool
+}
+
+impl Reply {
+    pub fn stream(chunks: Vec<Vec<u8>>) -> Reply {
+        return Reply { status: "200 OK", content_type: "text/event-stream", chunks, complete: true };
+    }
+
+    pub fn json(body: &str) -> Reply {
+        return Reply { status: "200 OK", content_type: "application/json", chunks: vec![body.as_bytes().to_vec()], complete: true };
+    }
+}
+
+async fn read_request(socket: &mut TcpStream) -> String {
+    let mut request = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = socket.read(&mut buffer).await.unwrap();
+        request.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&request).to_string();
+        if let Some(header_end) = text.find("\r\n\r\n") {
+            let content_length = text[..header_end]
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length").then(|| value.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+            if request.len() >= header_end + 4 + content_length {
+                return text;
+            }
+        }
+        if read == 0 {
+            return String::from_utf8_lossy(&request).to_string();
+        }
+    }
+}
+
+async fn write_reply(socket: &mut TcpStream, reply: Reply) {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+        reply.status, reply.content_type
+    );
+    socket.write_all(head.as_bytes()).await.unwrap();
+    for chunk in reply.chunks {
+        socket.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await.unwrap();
+        socket.write_all(&chunk).await.unwrap();
+        socket.write_all(b"\r\n").await.unwrap();
+        socket.flush().await.unwrap();
+        // keeps the chunks in separate reads on the client
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    if reply.complete {
+        socket.write_all(b"0\r\n\r\n").await.unwrap();
+    }
+    socket.flush().await.unwrap();
+}
+
+// answers one connection per reply, in order, and hands back the requests it received;
+// the base URL ends in /v1 like the providers' own
+pub async fn serve_all(replies: Vec<Reply>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+
+    let server = tokio::spawn(async move {
+        let mut requests = vec![];
+        for reply in replies {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            requests.push(read_request(&mut socket).await);
+            write_reply(&mut socket, reply).await;
+        }
+
+        requests
+    });
+
+    return (base_url, server);
+}
+
+pub async fn serve(reply: Reply) -> (String, JoinHandle<String>) {
+    let (base_url, server) = serve_all(vec![reply]).await;
+    return (base_url, tokio::spawn(async move { server.await.unwrap().remove(0) }));
+}
+
+// the body of a request read by the server
+pub fn request_body(request: &str) -> &str {
+    return request.split_once("\r\n\r\n").map(|(_, body)| body).unwrap_or_default();
+}
+
+pub fn test_settings(provider: Provider, model: &str, base_url: String) -> Mutable<Settings> {
+    return Mutable::new(Settings {
+        temperature: 1.0,
+        max_tokens: 64,
+        model: model.to_string(),
+        api_key: Some(0),
+        api_keys: vec![APIKey {
+            name: "test".to_string(),
+            key: "test-key".to_string(),
+            provider,
+            base_url: Some(base_url),
+            endpoint: None,
+            deployment: None,
+            api_version: None,
+            secret_key: None,
+            session_token: None,
+            region: None,
+            mock: None
+        }],
+        pricing: HashMap::new(),
+        openai_api: OpenAIAPI::ChatCompletions,
+        instructions: String::new(),
+        chain_responses: false
+    });
+}
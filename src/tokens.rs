@@ -0,0 +1,159 @@
+use lazy_static::lazy_static;
+use tiktoken_rs::{cl100k_base, o200k_base, CoreBPE};
+
+use crate::{message::Message, util::is_model_version};
+
+lazy_static! {
+    static ref CL100K_BASE: CoreBPE = cl100k_base().unwrap();
+    static ref O200K_BASE: CoreBPE = o200k_base().unwrap();
+}
+
+// tokens every chat message costs on top of its content, and the ones priming the reply
+const TOKENS_PER_MESSAGE: usize = 4;
+const TOKENS_PER_REPLY: usize = 3;
+
+// a conservative characters-per-token ratio for models without a bundled tokenizer
+const CHARS_PER_TOKEN: f64 = 3.5;
+
+fn context_windows() -> Vec<(&'static str, u32)> {
+    return vec![
+        ("gpt-3.5-turbo", 16_385),
+        ("gpt-4", 8_192),
+        ("gpt-4-32k", 32_768),
+        ("gpt-4-turbo", 128_000),
+        ("gpt-4-turbo-preview", 128_000),
+        ("gpt-4-0125-preview", 128_000),
+        ("gpt-4-1106-preview", 128_000),
+        ("gpt-4o", 128_000),
+        ("gpt-4o-mini", 128_000),
+        ("gpt-4.1", 1_047_576),
+        ("gpt-4.1-mini", 1_047_576),
+        ("gpt-4.1-nano", 1_047_576),
+        ("o1", 200_000),
+        ("o1-mini", 128_000),
+        ("o3", 200_000),
+        ("o3-mini", 200_000),
+        ("claude-3-haiku", 200_000),
+        ("claude-3-sonnet", 200_000),
+        ("claude-3-opus", 200_000),
+        ("claude-3-5-haiku", 200_000),
+        ("claude-3-5-sonnet", 200_000),
+        ("claude-3-7-sonnet", 200_000),
+        ("claude-sonnet-4", 200_000),
+        ("claude-opus-4", 200_000)
+    ];
+}
+
+fn tokenizer(model: &str) -> Option<&'static CoreBPE> {
+    if model.starts_with("gpt-4o") || model.starts_with("gpt-4.1") || model.starts_with("o1") || model.starts_with("o3") {
+        return Some(&*O200K_BASE);
+    }
+    if model.starts_with("gpt-4") || model.starts_with("gpt-3.5") {
+        return Some(&*CL100K_BASE);
+    }
+
+    return None;
+}
+
+// None for a model that isn't listed; guessing a smaller window would block requests the API accepts
+pub fn context_window(model: &str) -> Option<u32> {
+    return context_windows()
+        .into_iter()
+        .find(|(name, _)| is_model_version(name, model))
+        .map(|(_, window)| window);
+}
+
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    match tokenizer(model) {
+        Some(bpe) => bpe.encode_with_special_tokens(text).len(),
+        None => (text.chars().count() as f64 / CHARS_PER_TOKEN).ceil() as usize
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TokenEstimate {
+    pub input_tokens: usize,
+    pub max_tokens: u32,
+    pub context_window: Option<u32>,
+    // false when the count comes from the character heuristic
+    pub exact: bool
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ContextFit {
+    Fits,
+    Tight,
+    Overflow
+}
+
+impl TokenEstimate {
+    pub fn fit(&self) -> ContextFit {
+        let context_window = match self.context_window {
+            Some(context_window) => context_window,
+            None => return ContextFit::Fits
+        };
+
+        // the heuristic can overshoot, so only call an overflow obvious past a margin
+        let input_tokens = if self.exact {
+            self.input_tokens as f64
+        } else {
+            self.input_tokens as f64 * 0.9
+        };
+        let needed = input_tokens + self.max_tokens as f64;
+        if needed > context_window as f64 {
+            return ContextFit::Overflow;
+        } else if needed > context_window as f64 * 0.9 {
+            return ContextFit::Tight;
+        }
+
+        return ContextFit::Fits;
+    }
+
+    pub fn describe(&self) -> String {
+        let approximate = if self.exact { "" } else { "~" };
+        match self.context_window {
+            Some(context_window) => format!(
+                "{}{} + {} max / {} tokens",
+                approximate, self.input_tokens, self.max_tokens, context_window
+            ),
+            None => format!("{}{} + {} max tokens", approximate, self.input_tokens, self.max_tokens)
+        }
+    }
+
+    pub fn fraction(&self) -> f64 {
+        match self.context_window {
+            Some(context_window) => (self.input_tokens as f64 + self.max_tokens as f64) / context_window as f64,
+            None => 0.0
+        }
+    }
+}
+
+// attachments and tool blocks are counted by their text form, which is only a rough guide
+pub fn count_message_tokens(model: &str, messages: &[Message]) -> Vec<usize> {
+    return messages
+        .iter()
+        .map(|message| TOKENS_PER_MESSAGE + count_tokens(model, &message.display_text()))
+        .collect();
+}
+
+// an empty prompt sends the conversation as it is, so only the reply is primed
+pub fn count_prompt_tokens(model: &str, prompt: &str) -> usize {
+    if prompt.trim().is_empty() {
+        return TOKENS_PER_REPLY;
+    }
+    return TOKENS_PER_MESSAGE + count_tokens(model, prompt) + TOKENS_PER_REPLY;
+}
+
+pub fn estimate_tokens(
+    model: &str,
+    max_tokens: u32,
+    history_tokens: usize,
+    prompt_tokens: usize
+) -> TokenEstimate {
+    return TokenEstimate {
+        input_tokens: history_tokens + prompt_tokens,
+        max_tokens,
+        context_window: context_window(model),
+        exact: tokenizer(model).is_some()
+    };
+}
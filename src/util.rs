@@ -1,3 +1,5 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use gtk::{prelude::*, Label};
 
 pub fn get_buffer_content(buffer: &gtk::TextBuffer) -> String {
@@ -23,4 +25,38 @@ pub fn center(widget: impl IsA<gtk::Widget>) -> gtk::Box {
     hbox.append(&DummyLabel(gtk::Orientation::Horizontal));
 
     return hbox;
-}
\ No newline at end of file
+}
+
+// formats the time of day in UTC as HH:MM:SS.mmm
+pub fn format_timestamp(timestamp: SystemTime) -> String {
+    let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() % 86400;
+    return format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    );
+}
+
+// a model id names the model itself, a dated snapshot of it such as gpt-4-0613,
+// gpt-4o-2024-08-06 or claude-3-5-sonnet-20241022, or Anthropic's -latest alias;
+// anything else after the name (-mini, -32k, .5-preview) is a different model
+pub fn is_model_version(name: &str, model: &str) -> bool {
+    let suffix = match model.strip_prefix(name) {
+        Some("") => return true,
+        Some(suffix) => suffix,
+        None => return false
+    };
+    let Some(version) = suffix.strip_prefix('-') else {
+        return false;
+    };
+    let digits = |text: &str, count: usize| text.len() == count && text.bytes().all(|byte| byte.is_ascii_digit());
+    let date = match version.split('-').collect::<Vec<&str>>()[..] {
+        [year, month, day] => digits(year, 4) && digits(month, 2) && digits(day, 2),
+        _ => false
+    };
+
+    return date || digits(version, 4) || digits(version, 8) || version == "latest";
+}